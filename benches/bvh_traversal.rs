@@ -0,0 +1,85 @@
+//Times closest-hit queries against the boxed tree BVH and the flattened
+//BVH built from the same primitives. Each pixel contributes a camera ray
+//and, where that hits, a diffuse bounce ray, so both coherent and
+//incoherent rays are measured.
+extern crate ray_trace;
+
+use ray_trace::*;
+use ray_trace::bvh::{BvhNode, FlatBvh};
+use ray_trace::ray::Ray;
+use ray_trace::rng::Rng;
+use ray_trace::scenes;
+use ray_trace::traceable::Hit;
+use ray_trace::vec::Vec3;
+
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+const WIDTH: i32 = 600;
+const HEIGHT: i32 = 400;
+const REPEATS: usize = 5;
+
+fn main(){
+    bench_scene("sphere_world", scenes::sphere_world);
+    if Path::new(scenes::CAR_OBJ).exists() {
+        bench_scene("obj_test", scenes::obj_test);
+    } else {
+        println!("obj_test: skipped, {} not found", scenes::CAR_OBJ);
+    }
+}
+
+fn bench_scene(name: &str, scene: fn() -> scenes::Scene){
+    let (world, _, look_from, look_at) = scene();
+    let cam = scenes::default_camera(look_from, look_at, WIDTH as f64 / HEIGHT as f64);
+
+    let build_start = Instant::now();
+    let tree = BvhNode::new(world.clone());
+    let tree_build = build_start.elapsed();
+    let build_start = Instant::now();
+    let flat = FlatBvh::new(world);
+    let flat_build = build_start.elapsed();
+
+    let rays = make_rays(&tree, &cam);
+    let (tree_time, tree_hits) = time_rays(&tree, &rays);
+    let (flat_time, flat_hits) = time_rays(&flat, &rays);
+    assert_eq!(tree_hits, flat_hits, "The two BVHs disagree");
+
+    println!("{}: {} rays, {}", name, rays.len(), tree.stats());
+    println!("    tree  build {:>8.2} ms, trace {:>8.2} ms, {:>6.2} Mrays/s",
+             millis(tree_build), millis(tree_time), rays.len() as f64 / tree_time.as_secs_f64() / 1e6);
+    println!("    flat  build {:>8.2} ms, trace {:>8.2} ms, {:>6.2} Mrays/s ({:.2}x)",
+             millis(flat_build), millis(flat_time), rays.len() as f64 / flat_time.as_secs_f64() / 1e6,
+             tree_time.as_secs_f64() / flat_time.as_secs_f64());
+}
+
+fn make_rays<H: Hit>(world: &H, cam: &Camera) -> Vec<Ray>{
+    let mut rays = Vec::new();
+    let mut rng = Rng::new(0);
+    for j in 0..HEIGHT {
+        for i in 0..WIDTH {
+            let r = cam.get_ray((i as f64 + 0.5) / WIDTH as f64, (j as f64 + 0.5) / HEIGHT as f64, &mut rng);
+            rays.push(r);
+            if let Some((rec, _)) = world.hit(&r, 0.001, f64::INFINITY, &mut rng) {
+                rays.push(Ray::new(rec.p, rec.normal + Vec3::rand_unit_vec(&mut rng)));
+            }
+        }
+    }
+    rays
+}
+
+//The fastest of REPEATS passes over the rays, and how many of them hit
+fn time_rays<H: Hit>(world: &H, rays: &[Ray]) -> (Duration, usize){
+    let mut best = Duration::MAX;
+    let mut hits = 0;
+    let mut rng = Rng::new(0);
+    for _ in 0..REPEATS {
+        let start = Instant::now();
+        hits = rays.iter().filter(|r| world.hit(r, 0.001, f64::INFINITY, &mut rng).is_some()).count();
+        best = best.min(start.elapsed());
+    }
+    (best, hits)
+}
+
+fn millis(duration: Duration) -> f64{
+    duration.as_secs_f64() * 1000.0
+}
@@ -0,0 +1,326 @@
+use ray_trace::scenes;
+use ray_trace::OutputFormat;
+use ray_trace::bvh::SplitMethod;
+
+use std::fmt;
+
+pub const USAGE: &str = "Usage: ray_trace_cli [OPTIONS]
+
+Renders a scene straight to a file without opening a window.
+
+Options:
+    --scene <NAME|FILE>     Built-in scene or .toml scene file to render (default: obj_test)
+    --width <PIXELS>        Image width in pixels, at least 2 (default: 800)
+    --aspect-ratio <RATIO>  Width over height, as a number or W:H (default: 3:2)
+    --samples <N>           Samples per pixel, or the most any pixel takes with
+                            --adaptive (default: 500)
+    --adaptive <THRESHOLD>  Stop sampling each pixel once the noise in its
+                            displayed brightness is below THRESHOLD, where 1 is
+                            black to white (e.g. 0.01)
+    --min-samples <N>       Samples every pixel takes before --adaptive can
+                            stop it (default: 16)
+    --sample-heatmap <PATH> Also write a PNG of the samples taken per pixel
+    --max-depth <N>         Maximum number of ray bounces (default: 50)
+    --roulette-depth <N>    Bounces before paths may be ended at random (default: 3)
+    --threads <N>           Number of render threads (default: number of CPUs)
+    --seed <N>              Seed for the random numbers; the same seed and
+                            options give the same image (default: 0)
+    --bvh <METHOD>          BVH construction: sah or median (default: sah)
+    --bvh-stats             Print the node count and SAH cost of the BVH
+    --checkpoint <FILE>     Save the samples taken so far to FILE as the render
+                            goes, and when it finishes
+    --checkpoint-interval <SECONDS>
+                            Time between checkpoints (default: 300)
+    --resume                Carry on from the --checkpoint file if it exists.
+                            The scene, size and depths must match; --samples
+                            can be raised to add samples to a finished render
+    --output <PATH>         Output image path (default: results.ppm)
+    --format <FORMAT>       Output format: png, png16, ppm, ppm-ascii, or the
+                            linear floating point pfm and exr
+                            (default: chosen from the output extension)
+    --help                  Print this message";
+
+#[derive (Debug, Clone, PartialEq)]
+pub struct RenderArgs {
+    pub scene: String,
+    pub image_width: i32,
+    pub aspect_ratio: f64,
+    pub samples_per_pixel: i32,
+    pub adaptive_threshold: Option<f64>,
+    pub min_samples: i32,
+    pub sample_heatmap: Option<String>,
+    pub max_depth: i32,
+    pub roulette_depth: i32,
+    pub num_threads: i32,
+    pub seed: u64,
+    pub split_method: SplitMethod,
+    pub print_bvh_stats: bool,
+    pub checkpoint: Option<String>,
+    pub checkpoint_interval: u64,
+    pub resume: bool,
+    pub output: String,
+    pub format: OutputFormat,
+}
+
+#[derive (Debug, Clone, PartialEq)]
+pub enum Command {
+    Render(RenderArgs),
+    Help,
+}
+
+#[derive (Debug, Clone, PartialEq)]
+pub enum ArgError {
+    UnknownFlag(String),
+    MissingValue(String),
+    InvalidValue{flag: String, value: String},
+    UnknownScene(String),
+    UnknownFormat(String),
+    ResumeWithoutCheckpoint,
+}
+
+impl Default for RenderArgs {
+    fn default() -> RenderArgs {
+        RenderArgs {
+            scene: "obj_test".to_string(),
+            image_width: 800,
+            aspect_ratio: 3.0/2.0,
+            samples_per_pixel: 500,
+            adaptive_threshold: None,
+            min_samples: 16,
+            sample_heatmap: None,
+            max_depth: 50,
+            roulette_depth: 3,
+            num_threads: num_cpus::get() as i32,
+            seed: 0,
+            split_method: SplitMethod::Sah,
+            print_bvh_stats: false,
+            checkpoint: None,
+            checkpoint_interval: 300,
+            resume: false,
+            output: "results.ppm".to_string(),
+            format: OutputFormat::Ppm,
+        }
+    }
+}
+
+impl RenderArgs {
+    pub fn image_height(&self) -> i32 {
+        ((self.image_width as f64)/self.aspect_ratio) as i32
+    }
+}
+
+impl fmt::Display for ArgError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ArgError::UnknownFlag(flag) => write!(f, "unknown argument '{}'", flag),
+            ArgError::MissingValue(flag) => write!(f, "'{}' expects a value", flag),
+            ArgError::InvalidValue{flag, value} => write!(f, "invalid value '{}' for '{}'", value, flag),
+            ArgError::UnknownScene(name) => write!(f, "unknown scene '{}', expected a .toml file or one of: {}", name, scenes::NAMES.join(", ")),
+            ArgError::UnknownFormat(output) => write!(f, "cannot tell the format of '{}', pass --format with one of: {}", output, OutputFormat::NAMES.join(", ")),
+            ArgError::ResumeWithoutCheckpoint => write!(f, "'--resume' needs a '--checkpoint' file to resume from"),
+        }
+    }
+}
+
+impl std::error::Error for ArgError {}
+
+//Parses the program arguments (excluding the program name)
+pub fn parse_args<I>(args: I) -> Result<Command, ArgError> where I: IntoIterator<Item = String> {
+    let mut args = args.into_iter();
+    let mut render_args = RenderArgs::default();
+    let mut format = None;
+
+    while let Some(flag) = args.next() {
+        if flag == "--help" || flag == "-h" {
+            return Ok(Command::Help);
+        }
+        if flag == "--bvh-stats" {
+            render_args.print_bvh_stats = true;
+            continue;
+        }
+        if flag == "--resume" {
+            render_args.resume = true;
+            continue;
+        }
+
+        let value = match flag.as_str() {
+            "--scene" | "--width" | "--aspect-ratio" | "--samples" | "--adaptive" | "--min-samples" | "--sample-heatmap" | "--max-depth" | "--roulette-depth" | "--threads" | "--seed" | "--bvh" | "--checkpoint" | "--checkpoint-interval" | "--output" | "--format" => {
+                args.next().ok_or_else(|| ArgError::MissingValue(flag.clone()))?
+            }
+            _ => return Err(ArgError::UnknownFlag(flag)),
+        };
+
+        match flag.as_str() {
+            "--scene" => {
+                if !is_scene_file(&value) && scenes::by_name(&value).is_none() {
+                    return Err(ArgError::UnknownScene(value));
+                }
+                render_args.scene = value;
+            }
+            "--width" => {
+                //Camera rays are spread from the first pixel to the last, so
+                //there must be at least two
+                render_args.image_width = parse_positive(&flag, &value)?;
+                if render_args.image_width < 2 {
+                    return Err(ArgError::InvalidValue{flag, value});
+                }
+            }
+            "--aspect-ratio" => render_args.aspect_ratio = parse_aspect_ratio(&flag, &value)?,
+            "--samples" => render_args.samples_per_pixel = parse_positive(&flag, &value)?,
+            "--adaptive" => {
+                render_args.adaptive_threshold = match value.parse::<f64>() {
+                    Ok(threshold) if threshold > 0.0 && threshold.is_finite() => Some(threshold),
+                    _ => return Err(ArgError::InvalidValue{flag, value}),
+                };
+            }
+            "--min-samples" => render_args.min_samples = parse_positive(&flag, &value)?,
+            "--sample-heatmap" => render_args.sample_heatmap = Some(value),
+            "--max-depth" => render_args.max_depth = parse_positive(&flag, &value)?,
+            "--roulette-depth" => render_args.roulette_depth = parse_positive(&flag, &value)?,
+            "--threads" => render_args.num_threads = parse_positive(&flag, &value)?,
+            "--seed" => {
+                render_args.seed = value.parse().map_err(|_| ArgError::InvalidValue{flag: flag.clone(), value: value.clone()})?;
+            }
+            "--bvh" => {
+                render_args.split_method = match value.as_str() {
+                    "sah" => SplitMethod::Sah,
+                    "median" => SplitMethod::Median,
+                    _ => return Err(ArgError::InvalidValue{flag, value}),
+                };
+            }
+            "--checkpoint" => render_args.checkpoint = Some(value),
+            "--checkpoint-interval" => render_args.checkpoint_interval = parse_positive(&flag, &value)? as u64,
+            "--output" => render_args.output = value,
+            "--format" => {
+                format = OutputFormat::from_name(&value);
+                if format.is_none() {
+                    return Err(ArgError::InvalidValue{flag, value});
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    if render_args.image_height() < 2 {
+        return Err(ArgError::InvalidValue{flag: "--aspect-ratio".to_string(), value: render_args.aspect_ratio.to_string()});
+    }
+
+    if render_args.resume && render_args.checkpoint.is_none() {
+        return Err(ArgError::ResumeWithoutCheckpoint);
+    }
+
+    render_args.format = format.or_else(|| OutputFormat::from_path(&render_args.output))
+                               .ok_or_else(|| ArgError::UnknownFormat(render_args.output.clone()))?;
+
+    Ok(Command::Render(render_args))
+}
+
+pub fn is_scene_file(scene: &str) -> bool {
+    scene.ends_with(".toml")
+}
+
+fn parse_positive(flag: &str, value: &str) -> Result<i32, ArgError> {
+    match value.parse::<i32>() {
+        Ok(n) if n > 0 => Ok(n),
+        _ => Err(ArgError::InvalidValue{flag: flag.to_string(), value: value.to_string()}),
+    }
+}
+
+//Accepts either a plain ratio ("1.5") or a width:height pair ("3:2")
+fn parse_aspect_ratio(flag: &str, value: &str) -> Result<f64, ArgError> {
+    let invalid = || ArgError::InvalidValue{flag: flag.to_string(), value: value.to_string()};
+    let ratio = match value.split_once(':') {
+        Some((w, h)) => {
+            let w = w.trim().parse::<f64>().map_err(|_| invalid())?;
+            let h = h.trim().parse::<f64>().map_err(|_| invalid())?;
+            w / h
+        }
+        None => value.trim().parse::<f64>().map_err(|_| invalid())?,
+    };
+    if ratio.is_finite() && ratio > 0.0 {
+        Ok(ratio)
+    } else {
+        Err(invalid())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(list: &[&str]) -> Vec<String> {
+        list.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_defaults(){
+        assert_eq!(parse_args(args(&[])), Ok(Command::Render(RenderArgs::default())));
+        assert_eq!(parse_args(args(&["--help"])), Ok(Command::Help));
+    }
+
+    #[test]
+    fn test_options(){
+        let result = parse_args(args(&["--scene", "sphere_world", "--width", "400", "--aspect-ratio", "16:9",
+                                       "--samples", "10", "--adaptive", "0.02", "--min-samples", "4", "--sample-heatmap", "samples.png", "--max-depth", "5", "--roulette-depth", "3", "--threads", "2", "--seed", "42", "--bvh", "median", "--bvh-stats",
+                                       "--checkpoint", "run.ckpt", "--checkpoint-interval", "60", "--resume", "--output", "out.png"]));
+        match result {
+            Ok(Command::Render(render_args)) => {
+                assert_eq!(render_args.scene, "sphere_world");
+                assert_eq!(render_args.image_width, 400);
+                assert_eq!(render_args.aspect_ratio, 16.0/9.0);
+                assert_eq!(render_args.image_height(), 225);
+                assert_eq!(render_args.samples_per_pixel, 10);
+                assert_eq!(render_args.adaptive_threshold, Some(0.02));
+                assert_eq!(render_args.min_samples, 4);
+                assert_eq!(render_args.sample_heatmap, Some("samples.png".to_string()));
+                assert_eq!(render_args.max_depth, 5);
+                assert_eq!(render_args.roulette_depth, 3);
+                assert_eq!(render_args.num_threads, 2);
+                assert_eq!(render_args.seed, 42);
+                assert_eq!(render_args.split_method, SplitMethod::Median);
+                assert!(render_args.print_bvh_stats);
+                assert_eq!(render_args.checkpoint, Some("run.ckpt".to_string()));
+                assert_eq!(render_args.checkpoint_interval, 60);
+                assert!(render_args.resume);
+                assert_eq!(render_args.output, "out.png");
+                assert_eq!(render_args.format, OutputFormat::Png);
+            }
+            _ => panic!("Expected a render command")
+        }
+    }
+
+    #[test]
+    fn test_bad_args(){
+        //Case 1: Unknown flag
+        assert_eq!(parse_args(args(&["--fast"])), Err(ArgError::UnknownFlag("--fast".to_string())));
+
+        //Case 2: Missing value
+        assert_eq!(parse_args(args(&["--width"])), Err(ArgError::MissingValue("--width".to_string())));
+
+        //Case 3: Non-numeric and non-positive values
+        assert!(matches!(parse_args(args(&["--samples", "many"])), Err(ArgError::InvalidValue{..})));
+        assert!(matches!(parse_args(args(&["--threads", "0"])), Err(ArgError::InvalidValue{..})));
+        assert!(matches!(parse_args(args(&["--aspect-ratio", "3:0"])), Err(ArgError::InvalidValue{..})));
+        assert!(matches!(parse_args(args(&["--width", "1"])), Err(ArgError::InvalidValue{..})));
+        assert!(matches!(parse_args(args(&["--width", "10", "--aspect-ratio", "6"])), Err(ArgError::InvalidValue{..})));
+        assert!(matches!(parse_args(args(&["--bvh", "octree"])), Err(ArgError::InvalidValue{..})));
+        assert!(matches!(parse_args(args(&["--seed", "-1"])), Err(ArgError::InvalidValue{..})));
+        assert!(matches!(parse_args(args(&["--adaptive", "0"])), Err(ArgError::InvalidValue{..})));
+        assert!(matches!(parse_args(args(&["--min-samples", "0"])), Err(ArgError::InvalidValue{..})));
+
+        //Case 4: Unknown scene
+        assert_eq!(parse_args(args(&["--scene", "teapot"])), Err(ArgError::UnknownScene("teapot".to_string())));
+        assert!(parse_args(args(&["--scene", "teapot.toml"])).is_ok());
+
+        //Case 5: Resuming needs a checkpoint
+        assert_eq!(parse_args(args(&["--resume"])), Err(ArgError::ResumeWithoutCheckpoint));
+
+        //Case 6: Output format
+        assert_eq!(parse_args(args(&["--output", "out.exr.gz"])), Err(ArgError::UnknownFormat("out.exr.gz".to_string())));
+        assert!(matches!(parse_args(args(&["--format", "tiff"])), Err(ArgError::InvalidValue{..})));
+        match parse_args(args(&["--output", "out.png", "--format", "png16"])) {
+            Ok(Command::Render(render_args)) => assert_eq!(render_args.format, OutputFormat::Png16),
+            _ => panic!("Expected a render command")
+        }
+    }
+}
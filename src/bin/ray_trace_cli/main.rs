@@ -0,0 +1,125 @@
+extern crate ray_trace;
+extern crate num_cpus;
+
+mod args;
+
+use crate::args::*;
+use ray_trace::*;
+use ray_trace::bvh::{BvhNode, FlatBvh};
+use ray_trace::checkpoint::{Checkpoint, CheckpointSettings};
+use ray_trace::environment::Background;
+use ray_trace::light::LightList;
+use ray_trace::scenes;
+use ray_trace::traceable::Hit;
+
+use std::env;
+use std::error::Error;
+use std::path::Path;
+use std::process;
+use std::time::{Duration, Instant};
+
+//Samples added between chances to save a checkpoint
+const CHECKPOINT_PASS_SAMPLES: i32 = 4;
+
+fn main(){
+
+    let render_args = match args::parse_args(env::args().skip(1)) {
+        Ok(Command::Render(render_args)) => render_args,
+        Ok(Command::Help) => {
+            println!("{}", args::USAGE);
+            return;
+        }
+        Err(err) => {
+            eprintln!("error: {}\n\n{}", err, args::USAGE);
+            process::exit(2);
+        }
+    };
+
+    if let Err(err) = render_to_file(&render_args) {
+        eprintln!("error: {}", err);
+        process::exit(1);
+    }
+}
+
+fn render_to_file(render_args: &RenderArgs) -> Result<(), Box<dyn Error>> {
+
+    //Scene
+    let (world, background, fog, camera_settings) = if is_scene_file(&render_args.scene) {
+        let scene = load_scene(&render_args.scene)?;
+        (scene.world, scene.background, scene.fog, scene.camera)
+    } else {
+        let scene = scenes::by_name(&render_args.scene).expect("The scene name has already been validated");
+        let (world, background, look_from, look_at) = scene();
+        (world, Background::Constant(background), None, CameraSettings::new(look_from, look_at))
+    };
+    let lights = LightList::new(&world);
+    let tree = BvhNode::with_split_method(world, render_args.split_method);
+    if render_args.print_bvh_stats {
+        println!("BVH: {}", tree.stats());
+    }
+    let world = FlatBvh::from_tree(tree);
+
+    //Image
+    let image_data = ImageData {
+        image_width: render_args.image_width,
+        image_height: render_args.image_height(),
+        samples_per_pixel: render_args.samples_per_pixel,
+        max_depth: render_args.max_depth,
+        roulette_depth: render_args.roulette_depth,
+        seed: render_args.seed,
+        adaptive: render_args.adaptive_threshold.map(|threshold| AdaptiveSampling::new(render_args.min_samples, threshold))
+    };
+
+    //Camera
+    let cam = camera_settings.to_camera(render_args.aspect_ratio);
+
+    //Render
+    let scene_data = SceneData { world, lights, background, fog, cam };
+    let framebuffer = match &render_args.checkpoint {
+        Some(path) => render_with_checkpoints(path, render_args, image_data, scene_data)?,
+        None => render(image_data, scene_data, render_args.num_threads),
+    };
+    if image_data.adaptive.is_some() {
+        println!("Average samples per pixel: {:.1}", framebuffer.average_samples());
+    }
+    write_image(&framebuffer, &render_args.output, render_args.format)?;
+    if let Some(path) = &render_args.sample_heatmap {
+        write_sample_heatmap(&framebuffer, path)?;
+    }
+    Ok(())
+}
+
+//Renders in passes, saving the samples taken so far to path whenever the
+//checkpoint interval has passed and once more at the end. With --resume an
+//existing checkpoint is carried on from instead of starting again.
+fn render_with_checkpoints<H>(path: &str, render_args: &RenderArgs, image_data: ImageData, scene_data: SceneData<H>) -> Result<Framebuffer, Box<dyn Error>>
+where H: Hit + 'static {
+    let settings = CheckpointSettings::new(&render_args.scene, &image_data);
+    let framebuffer = if render_args.resume && Path::new(path).exists() {
+        let checkpoint = Checkpoint::load(path).map_err(|err| format!("cannot load checkpoint '{}': {}", path, err))?;
+        if checkpoint.settings != settings {
+            return Err(format!("checkpoint '{}' was rendered with {}, not {}", path, checkpoint.settings, settings).into());
+        }
+        println!("Resuming from {} samples per pixel", checkpoint.framebuffer.samples_per_pixel);
+        checkpoint.framebuffer
+    } else {
+        Framebuffer::empty(image_data.image_width, image_data.image_height)
+    };
+
+    //A failed save is reported but the render carries on, as the next save may work
+    let interval = Duration::from_secs(render_args.checkpoint_interval);
+    let mut last_save = Instant::now();
+    let framebuffer = render_progressive_from(framebuffer, image_data, scene_data, render_args.num_threads, CHECKPOINT_PASS_SAMPLES, |framebuffer| {
+        if last_save.elapsed() >= interval {
+            if let Err(err) = Checkpoint::new(settings.clone(), framebuffer.clone()).save(path) {
+                eprintln!("warning: cannot save checkpoint '{}': {}", path, err);
+            }
+            last_save = Instant::now();
+        }
+        true
+    });
+
+    let checkpoint = Checkpoint::new(settings, framebuffer);
+    checkpoint.save(path).map_err(|err| format!("cannot save checkpoint '{}': {}", path, err))?;
+    Ok(checkpoint.framebuffer)
+}
@@ -6,9 +6,11 @@ use crate::ray::*;
 use crate::material::*;
 use crate::vec::*;
 use crate::bvh::*;
+use crate::light::*;
+use crate::rng::*;
 use crate::enum_dispatch::*;
 
-#[derive (Copy, Clone)]
+#[derive (Clone)]
 pub struct BoundingBox{
     bb: Aabb,
     mat: Material
@@ -21,7 +23,7 @@ impl BoundingBox{
 }
 
 impl Hit for BoundingBox{
-    fn hit(&self, r:&Ray, t_min: f64, t_max: f64) -> Option<(HitRecord, &Material)> {
+    fn hit(&self, r:&Ray, t_min: f64, t_max: f64, _: &mut Rng) -> Option<(HitRecord, &Material)> {
         match self.bb.hit(r, t_min, t_max){
             true => {
                 Some((HitRecord::new(Vec3::default(), Vec3::default(), 10.0, *r, Vec3::default()), &self.mat))
@@ -33,4 +35,19 @@ impl Hit for BoundingBox{
     fn bounding_box(&self) -> Option<Aabb>{
        Some(self.bb)
     }
-}
\ No newline at end of file
+}
+
+//Bounding boxes are only drawn for debugging and are never used as lights
+impl Surface for BoundingBox{
+    fn area(&self) -> f64{
+        0.0
+    }
+
+    fn sample_surface(&self, _: &mut Rng) -> (Point3, Vec3){
+        (self.bb.min(), Vec3::default())
+    }
+
+    fn material(&self) -> &Material{
+        &self.mat
+    }
+}
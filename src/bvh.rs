@@ -3,7 +3,47 @@ use crate::ray::*;
 use crate::traceable::*;
 use crate::material::*;
 use crate::primitive::*;
+use crate::rng::*;
 use std::cmp::Ordering;
+use std::fmt;
+
+//Number of buckets centroids are sorted into when looking for a SAH split
+const SAH_BINS: usize = 12;
+
+//Most primitives a SAH leaf may hold
+const MAX_LEAF_SIZE: usize = 4;
+
+//Cost of visiting a node relative to intersecting a primitive
+const TRAVERSAL_COST: f64 = 0.125;
+
+//Deepest tree a FlatBvh can traverse. The SAH builder halves the remaining
+//primitives once it reaches half this depth, so its trees always fit.
+const MAX_DEPTH: usize = 64;
+
+//How a node chooses where to divide its primitives between its children
+#[derive (Debug, Copy, Clone, PartialEq, Default)]
+pub enum SplitMethod {
+    //Binned surface area heuristic. Builds faster trees, with up to
+    //MAX_LEAF_SIZE primitives per leaf.
+    #[default]
+    Sah,
+    //Halves the primitives along the axis of largest extent, giving one
+    //primitive per leaf
+    Median,
+}
+
+//Shape of a built tree. sah_cost is the expected cost of tracing a ray
+//that hits the root, in units of primitive intersections. An instance
+//counts as a single primitive; its own BVH has separate stats.
+#[derive (Debug, Copy, Clone, Default, PartialEq)]
+pub struct BvhStats {
+    pub nodes: usize,
+    pub leaves: usize,
+    pub primitives: usize,
+    pub max_leaf_size: usize,
+    pub max_depth: usize,
+    pub sah_cost: f64,
+}
 
 #[derive (Debug, Copy, Clone, Default, PartialEq)]
 
@@ -21,15 +61,36 @@ pub enum BvhNode {
 #[derive(Clone)]
 pub struct BvhBranch{
     children: (Box<BvhNode>, Box<BvhNode>),
-    bb: Aabb
+    bb: Aabb,
+    //The axis the children were split along
+    axis: usize
 }
 
 #[derive(Clone)]
 pub struct BvhRoot{
-    traceable: Primitive,
+    traceables: TraceableList,
     bb: Aabb
 }
 
+//A BVH laid out in one array in depth-first order, so the first child of
+//an interior node is always the node after it. The primitives of each leaf
+//are a contiguous run of one array.
+#[derive(Clone)]
+pub struct FlatBvh{
+    nodes: Vec<FlatNode>,
+    primitives: Vec<Primitive>
+}
+
+#[derive(Debug, Copy, Clone)]
+struct FlatNode{
+    bb: Aabb,
+    //The first primitive of a leaf, or the second child of an interior node
+    offset: u32,
+    //Primitives in a leaf. Zero for interior nodes.
+    count: u16,
+    axis: u8
+}
+
 impl Aabb{
     pub fn new(min: Point3, max: Point3) -> Aabb{
         Aabb{min, max}
@@ -47,10 +108,22 @@ impl Aabb{
         self.min() + (self.max() - self.min()) / 2.0
     }
 
-    pub fn hit(&self, r: &Ray, mut t_min: f64, mut t_max: f64) -> bool{
+    pub fn surface_area(&self) -> f64{
+        let d = self.max() - self.min();
+        2.0*(d.x()*d.y() + d.y()*d.z() + d.z()*d.x())
+    }
+
+    pub fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> bool{
+        let d = r.direction();
+        self.hit_with_inverse(r.origin(), Vec3::new(1.0/d.x(), 1.0/d.y(), 1.0/d.z()), t_min, t_max)
+    }
+
+    //The slab test with the reciprocal of the ray direction worked out by
+    //the caller, so a traversal divides once per ray rather than per box
+    pub fn hit_with_inverse(&self, origin: Point3, inv_dir: Vec3, mut t_min: f64, mut t_max: f64) -> bool{
         for a in 0..3{
-            let tx0 = (self.min()[a] - r.origin()[a]) / r.direction()[a];
-            let tx1 = (self.max()[a] - r.origin()[a]) / r.direction()[a];
+            let tx0 = (self.min[a] - origin[a]) * inv_dir[a];
+            let tx1 = (self.max[a] - origin[a]) * inv_dir[a];
             
             let t0 = tx0.min(tx1);
             let t1 = tx0.max(tx1);
@@ -65,6 +138,21 @@ impl Aabb{
         return true
     }
 
+    //The part of r between t_min and t_max that is inside the box, if any
+    pub fn span(&self, r: &Ray, mut t_min: f64, mut t_max: f64) -> Option<(f64, f64)>{
+        let (origin, d) = (r.origin(), r.direction());
+        for a in 0..3{
+            let tx0 = (self.min[a] - origin[a]) / d[a];
+            let tx1 = (self.max[a] - origin[a]) / d[a];
+            t_min = tx0.min(tx1).max(t_min);
+            t_max = tx0.max(tx1).min(t_max);
+            if t_max <= t_min{
+                return None;
+            }
+        }
+        Some((t_min, t_max))
+    }
+
     pub fn surrounding_box(box_0: Aabb, box_1: Aabb) -> Aabb{
         let small = Point3::new(box_0.min().x().min(box_1.min().x()),
                                 box_0.min().y().min(box_1.min().y()),
@@ -86,8 +174,8 @@ impl Aabb{
 }
 
 impl BvhBranch {
-    pub fn new(left: TraceableList, right: TraceableList, bb: Aabb) -> BvhNode{
-        BvhNode::Branch(BvhBranch{children: (Box::new(BvhNode::new(left)), Box::new(BvhNode::new(right))), bb})
+    pub fn build(left: BvhNode, right: BvhNode, bb: Aabb, axis: usize) -> BvhNode{
+        BvhNode::Branch(BvhBranch{children: (Box::new(left), Box::new(right)), bb, axis})
     }
 
     fn left(&self) -> &BvhNode{
@@ -104,19 +192,33 @@ impl BvhBranch {
 }
 
 impl BvhRoot{
-    pub fn new(traceable: Primitive, bb: Aabb) -> BvhNode{
-        BvhNode::Root(BvhRoot{traceable, bb})
+    pub fn build(traceables: TraceableList, bb: Aabb) -> BvhNode{
+        BvhNode::Root(BvhRoot{traceables, bb})
     }
 }
 
 impl BvhNode{
-    pub fn new(mut objects: TraceableList) -> BvhNode{
+    pub fn new(objects: TraceableList) -> BvhNode{
+        BvhNode::with_split_method(objects, SplitMethod::default())
+    }
+
+    pub fn with_split_method(objects: TraceableList, method: SplitMethod) -> BvhNode{
+        //Nothing can be split or bound, so an empty list is one empty leaf
+        if objects.empty() {
+            return BvhRoot::build(objects, Aabb::default());
+        }
+        match method {
+            SplitMethod::Sah => BvhNode::new_sah(objects, 1),
+            SplitMethod::Median => BvhNode::new_median(objects),
+        }
+    }
+
+    fn new_median(mut objects: TraceableList) -> BvhNode{
         let object_span = objects.len();
         match object_span {
             1 => {
-                let traceable = objects.remove(0);
-                let bb = traceable.bounding_box().expect("A primitive within the TraceableList cannot be bound");
-                return BvhRoot::new(traceable, bb)
+                let bb = objects.bounding_box().expect("A primitive within the TraceableList cannot be bound");
+                BvhRoot::build(objects, bb)
 
             } 
             
@@ -129,27 +231,134 @@ impl BvhNode{
                 let bb_left = left_objs.bounding_box().expect("A primitive within the TraceableList cannot be bound");
                 let bb_right = right_objs.bounding_box().expect("A primitive within the TraceableList cannot be bound");
                 let bb_surrounding = Aabb::surrounding_box(bb_left, bb_right);
-                return BvhBranch::new(left_objs, right_objs, bb_surrounding)
+                BvhBranch::build(BvhNode::new_median(left_objs), BvhNode::new_median(right_objs), bb_surrounding, axis as usize)
+            }
+        }
+    }
+
+    //Sorts the centroids into SAH_BINS buckets along their widest axis and
+    //splits between the buckets where the SAH cost is lowest. Small groups
+    //become leaves when that is cheaper than splitting them.
+    fn new_sah(mut objects: TraceableList, depth: usize) -> BvhNode{
+        let object_span = objects.len();
+        let bb = objects.bounding_box().expect("A primitive within the TraceableList cannot be bound");
+        if object_span == 1 {
+            return BvhRoot::build(objects, bb);
+        }
+        if depth >= MAX_DEPTH/2 {
+            return BvhNode::new_median(objects);
+        }
+
+        let centroids: Vec<Point3> = objects.iter().map(|object| object.bounding_box().unwrap().centroid()).collect();
+        let centroid_bounds = centroids.iter().fold(Aabb::new(centroids[0], centroids[0]), |bounds, &c| {
+            Aabb::surrounding_box(bounds, Aabb::new(c, c))
+        });
+        let extent = centroid_bounds.max() - centroid_bounds.min();
+        let axis = extent.max_dim();
+
+        //Centroids in one place cannot be told apart, so just keep leaves small
+        if extent[axis] <= 0.0 {
+            if object_span <= MAX_LEAF_SIZE {
+                return BvhRoot::build(objects, bb);
             }
+            let right_objs = objects.split_off(object_span/2);
+            return BvhNode::new_sah_branch(objects, right_objs, axis, depth);
+        }
+
+        let bin_of = |c: Point3| {
+            let offset = (c[axis] - centroid_bounds.min()[axis]) / extent[axis];
+            ((offset * SAH_BINS as f64) as usize).min(SAH_BINS - 1)
+        };
+        let mut counts = [0; SAH_BINS];
+        let mut bounds: [Option<Aabb>; SAH_BINS] = [None; SAH_BINS];
+        for (object, &c) in objects.iter().zip(centroids.iter()) {
+            let bin = bin_of(c);
+            let object_bb = object.bounding_box().unwrap();
+            counts[bin] += 1;
+            bounds[bin] = Some(bounds[bin].map_or(object_bb, |b| Aabb::surrounding_box(b, object_bb)));
         }
+
+        //Area times count for everything left of each split, then right of it
+        let mut left_cost = [0.0; SAH_BINS];
+        let mut running: (usize, Option<Aabb>) = (0, None);
+        for split in 0..SAH_BINS - 1 {
+            running = accumulate_bin(running, counts[split], bounds[split]);
+            left_cost[split] = running.0 as f64 * running.1.map_or(0.0, |b| b.surface_area());
+        }
+        let mut best_cost = f64::INFINITY;
+        let mut best_split = None;
+        let mut running: (usize, Option<Aabb>) = (0, None);
+        for split in (0..SAH_BINS - 1).rev() {
+            running = accumulate_bin(running, counts[split + 1], bounds[split + 1]);
+            let right_count = running.0;
+            if right_count == 0 || right_count == object_span {
+                continue;
+            }
+            let right_cost = right_count as f64 * running.1.map_or(0.0, |b| b.surface_area());
+            let cost = TRAVERSAL_COST + (left_cost[split] + right_cost) / bb.surface_area();
+            if cost < best_cost {
+                best_cost = cost;
+                best_split = Some(split);
+            }
+        }
+
+        let best_split = best_split.expect("Centroids with some extent always have a split");
+        if object_span <= MAX_LEAF_SIZE && object_span as f64 <= best_cost {
+            return BvhRoot::build(objects, bb);
+        }
+        let (left_objs, right_objs) = objects.partition(|object| bin_of(object.bounding_box().unwrap().centroid()) <= best_split);
+        BvhNode::new_sah_branch(left_objs, right_objs, axis, depth)
     }
 
-    fn hit_debug(&self ,r: &Ray, t_min: f64, t_max: f64) -> (i32, Option<(HitRecord, &Material)>) {
+    fn new_sah_branch(left_objs: TraceableList, right_objs: TraceableList, axis: usize, depth: usize) -> BvhNode{
+        let left = BvhNode::new_sah(left_objs, depth + 1);
+        let right = BvhNode::new_sah(right_objs, depth + 1);
+        let bb = Aabb::surrounding_box(left.bounding_box().unwrap(), right.bounding_box().unwrap());
+        BvhBranch::build(left, right, bb, axis)
+    }
+
+    pub fn stats(&self) -> BvhStats{
+        let mut stats = BvhStats::default();
+        let root_area = self.bounding_box().unwrap().surface_area();
+        self.add_stats(&mut stats, 1, if root_area > 0.0 {root_area} else {1.0});
+        stats
+    }
+
+    fn add_stats(&self, stats: &mut BvhStats, depth: usize, root_area: f64){
+        stats.nodes += 1;
+        stats.max_depth = stats.max_depth.max(depth);
+        match self{
+            BvhNode::Branch(x) => {
+                stats.sah_cost += TRAVERSAL_COST * x.bb.surface_area() / root_area;
+                x.left().add_stats(stats, depth + 1, root_area);
+                x.right().add_stats(stats, depth + 1, root_area);
+            }
+            BvhNode::Root(x) => {
+                let count = x.traceables.len();
+                stats.leaves += 1;
+                stats.primitives += count;
+                stats.max_leaf_size = stats.max_leaf_size.max(count);
+                stats.sah_cost += count as f64 * x.bb.surface_area() / root_area;
+            }
+        }
+    }
+
+    fn hit_debug(&self ,r: &Ray, t_min: f64, t_max: f64, rng: &mut Rng) -> (i32, Option<(HitRecord, &Material)>) {
         match self{
-            BvhNode::Branch(x) => x.hit_debug(r, t_min, t_max),
-            BvhNode::Root(x) => x.hit_debug(r, t_min, t_max)
+            BvhNode::Branch(x) => x.hit_debug(r, t_min, t_max, rng),
+            BvhNode::Root(x) => x.hit_debug(r, t_min, t_max, rng)
         }
     }
 }
 
 impl BvhBranch{
-    pub fn hit_debug(&self, r: &Ray, t_min: f64, t_max: f64) -> (i32, Option<(HitRecord, &Material)>) {
+    pub fn hit_debug(&self, r: &Ray, t_min: f64, t_max: f64, rng: &mut Rng) -> (i32, Option<(HitRecord, &Material)>) {
         if !self.bb.hit(r, t_min, t_max){
             return (0, None)
         } 
 
-        let mut hit_left = self.left().hit_debug(r, t_min, t_max);
-        let mut hit_right = self.right().hit_debug(r, t_min, t_max);
+        let mut hit_left = self.left().hit_debug(r, t_min, t_max, rng);
+        let mut hit_right = self.right().hit_debug(r, t_min, t_max, rng);
         hit_left.0 += hit_right.0;
         hit_right.0 = hit_left.0;
         match(hit_left.1, hit_right.1){
@@ -168,23 +377,83 @@ impl BvhBranch{
 }
 
 impl BvhRoot{
-    pub fn hit_debug(&self, r: &Ray, t_min: f64, t_max: f64) -> (i32, Option<(HitRecord, &Material)>) {
-        match self.traceable.hit(r, t_min, t_max) {
-            Some((rec, mat)) => {
-                (1, Some((rec, mat)))
+    pub fn hit_debug(&self, r: &Ray, t_min: f64, t_max: f64, rng: &mut Rng) -> (i32, Option<(HitRecord, &Material)>) {
+        (self.traceables.len() as i32, self.traceables.hit(r, t_min, t_max, rng))
+    }
+}
+
+impl FlatBvh{
+    pub fn new(objects: TraceableList) -> FlatBvh{
+        FlatBvh::from_tree(BvhNode::new(objects))
+    }
+
+    pub fn with_split_method(objects: TraceableList, method: SplitMethod) -> FlatBvh{
+        FlatBvh::from_tree(BvhNode::with_split_method(objects, method))
+    }
+
+    pub fn from_tree(tree: BvhNode) -> FlatBvh{
+        let stats = tree.stats();
+        assert!(stats.max_depth <= MAX_DEPTH, "The BVH is too deep to traverse");
+        let mut flat = FlatBvh{nodes: Vec::with_capacity(stats.nodes), primitives: Vec::with_capacity(stats.primitives)};
+        //An empty leaf would read as a branch, so an empty tree has no nodes
+        if stats.primitives > 0{
+            flat.flatten(tree);
+        }
+        flat
+    }
+
+    //Appends node and everything below it, returning the index it was stored at
+    fn flatten(&mut self, node: BvhNode) -> usize{
+        let index = self.nodes.len();
+        match node{
+            BvhNode::Branch(branch) => {
+                self.nodes.push(FlatNode{bb: branch.bb, offset: 0, count: 0, axis: branch.axis as u8});
+                let (left, right) = branch.children;
+                self.flatten(*left);
+                self.nodes[index].offset = self.flatten(*right) as u32;
+            }
+            BvhNode::Root(leaf) => {
+                let offset = self.primitives.len() as u32;
+                let count = leaf.traceables.len() as u16;
+                self.primitives.extend(leaf.traceables);
+                self.nodes.push(FlatNode{bb: leaf.bb, offset, count, axis: 0});
             }
-            None => (1, (None))
         }
+        index
+    }
+
+    pub fn len(&self) -> usize{
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool{
+        self.nodes.is_empty()
+    }
+}
+
+//Adds a bin's primitives to a running count and bounding box
+fn accumulate_bin(running: (usize, Option<Aabb>), count: usize, bounds: Option<Aabb>) -> (usize, Option<Aabb>){
+    let bb = match (running.1, bounds) {
+        (Some(a), Some(b)) => Some(Aabb::surrounding_box(a, b)),
+        (a, b) => a.or(b),
+    };
+    (running.0 + count, bb)
+}
+
+impl fmt::Display for BvhStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} nodes, {} leaves, {} primitives (at most {} per leaf), depth {}, SAH cost {:.2}",
+               self.nodes, self.leaves, self.primitives, self.max_leaf_size, self.max_depth, self.sah_cost)
     }
 }
 impl Hit for BvhBranch {
-    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<(HitRecord, &Material)> {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rng: &mut Rng) -> Option<(HitRecord, &Material)> {
         if !self.bb.hit(r, t_min, t_max){
             return None
         } 
 
-        let hit_left = self.left().hit(r, t_min, t_max);
-        let hit_right = self.right().hit(r, t_min, t_max);
+        let hit_left = self.left().hit(r, t_min, t_max, rng);
+        let hit_right = self.right().hit(r, t_min, t_max, rng);
         match(hit_left, hit_right){
             (None, None) => None,
             (Some(_), None) => hit_left,
@@ -206,19 +475,70 @@ impl Hit for BvhBranch {
 }
 
 impl Hit for BvhRoot {
-    fn hit(&self ,r: &Ray, t_min: f64, t_max: f64) -> Option<(HitRecord, &Material)> {
-        self.traceable.hit(r, t_min, t_max)
+    fn hit(&self ,r: &Ray, t_min: f64, t_max: f64, rng: &mut Rng) -> Option<(HitRecord, &Material)> {
+        self.traceables.hit(r, t_min, t_max, rng)
     }
     fn bounding_box(&self) -> Option<Aabb>{
         Some(self.bb)
     }
 }
 
+impl Hit for FlatBvh{
+    //Visits the child nearer the ray origin first, so hits found there can
+    //cull the farther child's subtree by shortening the ray
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rng: &mut Rng) -> Option<(HitRecord, &Material)> {
+        let d = r.direction();
+        let inv_dir = Vec3::new(1.0/d.x(), 1.0/d.y(), 1.0/d.z());
+        let dir_is_neg = [inv_dir.x() < 0.0, inv_dir.y() < 0.0, inv_dir.z() < 0.0];
+
+        let mut closest_so_far = t_max;
+        let mut hit_out = None;
+        let mut to_visit = [0usize; MAX_DEPTH];
+        let mut stack_len = 0;
+        let mut index = 0;
+        while index < self.nodes.len(){
+            let node = &self.nodes[index];
+            if node.bb.hit_with_inverse(r.origin(), inv_dir, t_min, closest_so_far){
+                if node.count > 0{
+                    let start = node.offset as usize;
+                    for primitive in &self.primitives[start..start + node.count as usize]{
+                        if let Some(hit_temp) = primitive.hit(r, t_min, closest_so_far, rng){
+                            closest_so_far = hit_temp.0.t;
+                            hit_out = Some(hit_temp);
+                        }
+                    }
+                } else{
+                    //The first child holds the lower coordinates along the split axis
+                    let (near, far) = if dir_is_neg[node.axis as usize]{
+                        (node.offset as usize, index + 1)
+                    } else{
+                        (index + 1, node.offset as usize)
+                    };
+                    to_visit[stack_len] = far;
+                    stack_len += 1;
+                    index = near;
+                    continue;
+                }
+            }
+            if stack_len == 0{
+                break;
+            }
+            stack_len -= 1;
+            index = to_visit[stack_len];
+        }
+        hit_out
+    }
+
+    fn bounding_box(&self) -> Option<Aabb>{
+        self.nodes.first().map(|node| node.bb)
+    }
+}
+
 impl Hit for BvhNode{
-    fn hit(&self ,r: &Ray, t_min: f64, t_max: f64) -> Option<(HitRecord, &Material)> {
+    fn hit(&self ,r: &Ray, t_min: f64, t_max: f64, rng: &mut Rng) -> Option<(HitRecord, &Material)> {
         match self{
-            BvhNode::Branch(x) => x.hit(r, t_min, t_max),
-            BvhNode::Root(x) => x.hit(r, t_min, t_max)
+            BvhNode::Branch(x) => x.hit(r, t_min, t_max, rng),
+            BvhNode::Root(x) => x.hit(r, t_min, t_max, rng)
         }
     }
     fn bounding_box(&self) -> Option<Aabb>{
@@ -276,6 +596,23 @@ mod tests {
         assert_eq!(rec, false);
     }
 
+    #[test]
+    fn test_aabb_span(){
+        let aabb = Aabb::new(Point3::new(-1.0, -1.0, -1.0), Point3::new(1.0, 1.0, 1.0));
+
+        //Case 1: Through the box
+        let r = Ray::new(Point3::new(-5.0, 0.5, 0.0), Vec3::new(2.0, 0.0, 0.0));
+        assert_eq!(aabb.span(&r, 0.0, 100.0), Some((2.0, 3.0)));
+
+        //Case 2: Starting inside, or cut short by t_max
+        assert_eq!(aabb.span(&r, 2.5, 100.0), Some((2.5, 3.0)));
+        assert_eq!(aabb.span(&r, 0.0, 2.25), Some((2.0, 2.25)));
+
+        //Case 3: Miss
+        let r = Ray::new(Point3::new(-5.0, 1.5, 0.0), Vec3::new(1.0, 0.0, 0.0));
+        assert_eq!(aabb.span(&r, 0.0, 100.0), None);
+    }
+
     #[test]
     fn test_surrounding_box(){
         
@@ -314,6 +651,7 @@ mod tests {
 
     #[test]
     fn test_bvhnode_hit(){
+        let mut rng = Rng::new(0);
 
         let mut list = TraceableList::new();
         let r = Ray::new(Vec3::new(-10.0, 0.0, 0.0), Vec3::new( 1.0, 0.0, 0.0));
@@ -331,7 +669,7 @@ mod tests {
         }
         let list_clone = list.clone();
         let bvh = list_clone.to_Bvh();
-        let hit = bvh.hit(&r, t_min, t_max);
+        let hit = bvh.hit(&r, t_min, t_max, &mut rng);
         assert!(hit.is_none());
 
         //Case 2: Single intersection
@@ -342,7 +680,7 @@ mod tests {
         list.add(s);
         let list_clone = list.clone();
         let bvh = list_clone.to_Bvh();
-        let hit = bvh.hit(&r, t_min, t_max);
+        let hit = bvh.hit(&r, t_min, t_max, &mut rng);
         assert!(hit.is_some());
         let (rec, _) = hit.unwrap();
         assert_eq!(rec.t, 5.0); 
@@ -355,7 +693,7 @@ mod tests {
         list.add(s);
         let list_clone = list.clone();
         let bvh = list_clone.to_Bvh();
-        let hit = bvh.hit(&r, t_min, t_max);
+        let hit = bvh.hit(&r, t_min, t_max, &mut rng);
         assert!(hit.is_some());
         let (rec, _) = hit.unwrap();
         assert_eq!(rec.t, 3.0); 
@@ -365,6 +703,8 @@ mod tests {
     #[test]
     
     fn test_bvhnode_hit_debug(){
+        let mut rng = Rng::new(0);
+        //The counts below are for the one primitive per leaf median tree
 
         let t_min = 0.0;
         let t_max = 1000.0;
@@ -376,11 +716,11 @@ mod tests {
         let mat = Material::Lambertian(Lambertian::default());
         for i in 1..100{
             let center = Vec3::new(i as f64, 0.0, 0.0);
-            let s = Primitive::Sphere(Sphere::new(center, radius, mat));
+            let s = Primitive::Sphere(Sphere::new(center, radius, mat.clone()));
             list.add(s);
         }
-        let bvh = list.to_Bvh();
-        let hit = bvh.hit_debug(&r, t_min, t_max);
+        let bvh = BvhNode::with_split_method(list, SplitMethod::Median);
+        let hit = bvh.hit_debug(&r, t_min, t_max, &mut rng);
         assert_eq!(hit.0, 0);
         assert!(hit.1.is_none());
 
@@ -391,11 +731,11 @@ mod tests {
         let mat = Material::Lambertian(Lambertian::default());
         for i in 1..100{
             let center = Vec3::new(i as f64, 0.0, 0.0);
-            let s = Primitive::Sphere(Sphere::new(center, radius, mat));
+            let s = Primitive::Sphere(Sphere::new(center, radius, mat.clone()));
             list.add(s);
         }
-        let bvh = list.to_Bvh();
-        let hit = bvh.hit_debug(&r, t_min, t_max);
+        let bvh = BvhNode::with_split_method(list, SplitMethod::Median);
+        let hit = bvh.hit_debug(&r, t_min, t_max, &mut rng);
         assert_eq!(hit.0, 0);
         assert!(hit.1.is_none());
 
@@ -407,11 +747,11 @@ mod tests {
          let mat = Material::Lambertian(Lambertian::default());
          for i in 1..100{
              let center = Vec3::new(i as f64, 0.0, 0.0);
-             let s = Primitive::Sphere(Sphere::new(center, radius, mat));
+             let s = Primitive::Sphere(Sphere::new(center, radius, mat.clone()));
              list.add(s);
          }
-         let bvh = list.to_Bvh();
-         let hit = bvh.hit_debug(&r, t_min, t_max);
+         let bvh = BvhNode::with_split_method(list, SplitMethod::Median);
+         let hit = bvh.hit_debug(&r, t_min, t_max, &mut rng);
          assert_eq!(hit.0, 0);
          assert!(hit.1.is_none());
 
@@ -422,11 +762,11 @@ mod tests {
          let mat = Material::Lambertian(Lambertian::default());
          for i in 1..100{
              let center = Vec3::new(i as f64, 0.0, 0.0);
-             let s = Primitive::Sphere(Sphere::new(center, radius, mat));
+             let s = Primitive::Sphere(Sphere::new(center, radius, mat.clone()));
              list.add(s);
          }
-         let bvh = list.to_Bvh();
-         let hit = bvh.hit_debug(&r, t_min, t_max);
+         let bvh = BvhNode::with_split_method(list, SplitMethod::Median);
+         let hit = bvh.hit_debug(&r, t_min, t_max, &mut rng);
          assert_eq!(hit.0, 99);
          assert!(hit.1.is_some()); 
          
@@ -437,11 +777,11 @@ mod tests {
          let mat = Material::Lambertian(Lambertian::default());
          for i in 1..100{
              let center = Vec3::new(i as f64, 0.0, 0.0);
-             let s = Primitive::Sphere(Sphere::new(center, radius, mat));
+             let s = Primitive::Sphere(Sphere::new(center, radius, mat.clone()));
              list.add(s);
          }
-         let bvh = list.to_Bvh();
-         let hit = bvh.hit_debug(&r, t_min, t_max);
+         let bvh = BvhNode::with_split_method(list, SplitMethod::Median);
+         let hit = bvh.hit_debug(&r, t_min, t_max, &mut rng);
          let rec = hit.1.unwrap();
          assert_eq!(hit.0, 9);
          assert!(hit.1.is_some());
@@ -455,16 +795,121 @@ mod tests {
              let v2 = Vec3::new((i + 1) as f64, 0.0, 0.0);
              let v3 = Vec3::new(i as f64 + 0.5, 0.0, 10.0);
              let norm = Vec3::new(0.0, 1.0, 0.0);
-             let s = Primitive::Triangle(Triangle::new([v1, v2, v3], [norm; 3], mat ));
+             let s = Primitive::Triangle(Triangle::new([v1, v2, v3], [norm; 3], mat.clone()));
              list.add(s);
          }
-         let bvh = list.to_Bvh();
-         let hit = bvh.hit_debug(&r, t_min, t_max);
+         let bvh = BvhNode::with_split_method(list, SplitMethod::Median);
+         let hit = bvh.hit_debug(&r, t_min, t_max, &mut rng);
          let rec = hit.1;
          assert_eq!(hit.0, 1);
         assert!(rec.is_some());
 
     }
 
-    
+    fn sphere_cluster() -> TraceableList{
+        //Mostly small spheres packed together plus a few large distant ones
+        let mut list = TraceableList::new();
+        let mat = Material::new_lambertian(Color::new(0.5, 0.5, 0.5));
+        for i in 0..200{
+            let center = Vec3::new((i % 10) as f64 * 0.3, (i / 10 % 5) as f64 * 0.3, (i / 50) as f64 * 0.3);
+            list.add(Primitive::new_sphere(center, 0.1, mat.clone()));
+        }
+        for i in 0..5{
+            list.add(Primitive::new_sphere(Vec3::new(50.0 + 20.0*i as f64, 0.0, 0.0), 5.0, mat.clone()));
+        }
+        list
+    }
+
+    #[test]
+    fn test_sah_build(){
+        let mut rng = Rng::new(0);
+        let list = sphere_cluster();
+        let sah = BvhNode::with_split_method(list.clone(), SplitMethod::Sah);
+        let median = BvhNode::with_split_method(list.clone(), SplitMethod::Median);
+        let sah_stats = sah.stats();
+        let median_stats = median.stats();
+
+        assert_eq!(sah_stats.primitives, list.len());
+        assert_eq!(sah_stats.nodes, 2*sah_stats.leaves - 1);
+        assert!(sah_stats.max_leaf_size <= MAX_LEAF_SIZE);
+        assert_eq!(median_stats.leaves, list.len());
+        assert_eq!(median_stats.max_leaf_size, 1);
+        assert!(sah_stats.sah_cost < median_stats.sah_cost);
+
+        //Both trees find the same hits
+        for i in 0..200{
+            let origin = Vec3::new(-10.0, 0.01*i as f64, 0.6);
+            let r = Ray::new(origin, Vec3::new(1.0, 0.002*(i % 7) as f64, -0.001*(i % 3) as f64));
+            let t_sah = sah.hit(&r, 0.001, 1000.0, &mut rng).map(|(rec, _)| rec.t);
+            let t_median = median.hit(&r, 0.001, 1000.0, &mut rng).map(|(rec, _)| rec.t);
+            assert_eq!(t_sah, t_median);
+        }
+    }
+
+    #[test]
+    fn test_sah_coincident(){
+        //Centroids that cannot be split still end up in small leaves
+        let mut list = TraceableList::new();
+        let mat = Material::new_lambertian(Color::new(0.5, 0.5, 0.5));
+        for _ in 0..37{
+            list.add(Primitive::new_sphere(Vec3::new(1.0, 2.0, 3.0), 1.0, mat.clone()));
+        }
+        let stats = list.to_Bvh().stats();
+        assert_eq!(stats.primitives, 37);
+        assert!(stats.max_leaf_size <= MAX_LEAF_SIZE);
+    }
+
+    #[test]
+    fn test_flat_bvh(){
+        let list = sphere_cluster();
+        let tree = BvhNode::new(list.clone());
+        let flat = FlatBvh::new(list);
+        assert_eq!(flat.len(), tree.stats().nodes);
+        assert_eq!(flat.bounding_box(), tree.bounding_box());
+
+        //Rays in every octant find the same hits as the tree
+        let mut rng = Rng::new(0);
+        for i in 0..400{
+            let origin = Vec3::new(1.5, 0.6, 0.6) + 20.0*Vec3::rand_unit_vec(&mut rng);
+            let target = Vec3::new(0.3*(i % 10) as f64, 0.3*(i / 10 % 5) as f64, 0.3*(i % 4) as f64);
+            let r = Ray::new(origin, target - origin + 0.05*Vec3::rand_unit_vec(&mut rng));
+            let t_tree = tree.hit(&r, 0.001, 1000.0, &mut rng).map(|(rec, _)| rec.t);
+            let t_flat = flat.hit(&r, 0.001, 1000.0, &mut rng).map(|(rec, _)| rec.t);
+            assert_eq!(t_tree, t_flat);
+        }
+    }
+
+    #[test]
+    fn test_empty(){
+        let mut rng = Rng::new(0);
+        //Empty lists build without panicking and are never hit
+        let r = Ray::new(Point3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0));
+        for method in [SplitMethod::Sah, SplitMethod::Median].iter(){
+            let tree = BvhNode::with_split_method(TraceableList::new(), *method);
+            assert!(tree.hit(&r, 0.0, 100.0, &mut rng).is_none());
+            let flat = FlatBvh::from_tree(tree);
+            assert!(flat.is_empty());
+            assert!(flat.hit(&r, 0.0, 100.0, &mut rng).is_none());
+            assert!(flat.bounding_box().is_none());
+        }
+    }
+
+    #[test]
+    fn test_sah_depth_limit(){
+        let mut rng = Rng::new(0);
+        //Spacing that doubles each time makes the SAH peel off one sphere per level
+        let mut list = TraceableList::new();
+        let mat = Material::new_lambertian(Color::new(0.5, 0.5, 0.5));
+        //Each center is worked out once, as powi can round differently when
+        //it is folded at compile time
+        let centers: Vec<f64> = (0..200).map(|i| 1.2_f64.powi(i)).collect();
+        for x in centers.iter(){
+            list.add(Primitive::new_sphere(Vec3::new(*x, 0.0, 0.0), 0.1, mat.clone()));
+        }
+        let tree = list.to_Bvh();
+        assert!(tree.stats().max_depth <= MAX_DEPTH);
+        let flat = FlatBvh::from_tree(tree);
+        let r = Ray::new(Vec3::new(centers[150], 10.0, 0.0), Vec3::new(0.0, -1.0, 0.0));
+        assert!((flat.hit(&r, 0.001, 100.0, &mut rng).unwrap().0.t - 9.9).abs() < 1e-9);
+    }
 }
\ No newline at end of file
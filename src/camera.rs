@@ -1,8 +1,10 @@
 use core::f64;
+use std::f64::consts::PI;
 
-use crate::deg_to_rad;
+use crate::util::deg_to_rad;
 use crate::vec::*;
 use crate::ray::*;
+use crate::rng::*;
 
 #[derive (Copy, Clone, Default)]
 pub struct Camera{
@@ -11,7 +13,25 @@ pub struct Camera{
     vertical: Vec3,
     lower_left_corner: Vec3,
     orientation: Orientation,
-    lens_radius: f64
+    lens_radius: f64,
+    shutter_open: f64,
+    shutter_close: f64
+}
+
+//The parameters Camera::new is built from, kept around so the
+//camera can be rebuilt once the image's aspect ratio is known
+#[derive (Copy, Clone, Debug, PartialEq)]
+pub struct CameraSettings{
+    pub look_from: Point3,
+    pub look_at: Point3,
+    pub v_up: Vec3,
+    pub v_fov: f64,
+    pub aperture: f64,
+    pub focus_dist: f64,
+    //The times between which rays are sent out. Moving objects blur over
+    //the interval, and nothing blurs when it is empty.
+    pub shutter_open: f64,
+    pub shutter_close: f64
 }
 
 #[derive (Copy, Clone, Default)]
@@ -30,7 +50,7 @@ impl Camera{
         let viewport_width = aspect_ratio * viewport_height;
 
         let w = (look_from - look_at).unit_vector();
-        let u = Vec3::cross(v_up, w);
+        let u = Vec3::cross(v_up, w).unit_vector();
         let v = Vec3::cross(w, u);
         let orientation = Orientation::new(u,v,w);
 
@@ -40,17 +60,101 @@ impl Camera{
         let lower_left_corner = origin - horizontal/2.0 - vertical/2.0 - focus_dist * w;
 
         let lens_radius = aperture/2.0;
-        Camera{origin, horizontal, vertical, lower_left_corner, orientation, lens_radius}
+        Camera{origin, horizontal, vertical, lower_left_corner, orientation, lens_radius, shutter_open: 0.0, shutter_close: 0.0}
+    }
+
+    pub fn with_shutter(mut self, open: f64, close: f64) -> Camera{
+        self.shutter_open = open;
+        self.shutter_close = close.max(open);
+        self
     }
 
-    pub fn get_ray(&self, s: f64, t:f64) -> Ray{
-        let rd = self.lens_radius * Vec3::rand_in_unit_disk();
+    //The time is uniform over the shutter interval. No random number is
+    //drawn for it when the interval is empty, so still renders are the same
+    //as they were before rays had a time.
+    pub fn get_ray(&self, s: f64, t:f64, rng: &mut Rng) -> Ray{
+        let rd = self.lens_radius * Vec3::rand_in_unit_disk(rng);
         let offset = self.orientation.u() * rd.x() + self.orientation.v() * rd.y();
+        let time = if self.shutter_close > self.shutter_open{
+            self.shutter_open + rng.f64()*(self.shutter_close - self.shutter_open)
+        } else{
+            self.shutter_open
+        };
 
-        Ray::new(self.origin + offset, (self.lower_left_corner + s*self.horizontal + t*self.vertical - self.origin - offset).unit_vector())
+        Ray::new_at_time(self.origin + offset, (self.lower_left_corner + s*self.horizontal + t*self.vertical - self.origin - offset).unit_vector(), time)
     }
 }
 
+impl CameraSettings{
+
+    pub fn new(look_from: Point3, look_at: Point3) -> CameraSettings{
+        CameraSettings{look_from, look_at, v_up: Vec3::new(0.0, 1.0, 0.0), v_fov: 20.0, aperture: 0.0, focus_dist: 10.0,
+                       shutter_open: 0.0, shutter_close: 0.0}
+    }
+
+    pub fn to_camera(&self, aspect_ratio: f64) -> Camera{
+        Camera::new(self.look_from, self.look_at, self.v_up, self.v_fov, aspect_ratio, self.aperture, self.focus_dist)
+            .with_shutter(self.shutter_open, self.shutter_close)
+    }
+
+    //Unit vectors pointing right, up and backwards from the view, as in Camera::new
+    pub fn orientation(&self) -> Orientation{
+        let w = (self.look_from - self.look_at).unit_vector();
+        let u = Vec3::cross(self.v_up, w).unit_vector();
+        let v = Vec3::cross(w, u);
+        Orientation::new(u, v, w)
+    }
+
+    //Turns look_from around look_at by yaw radians about v_up and pitch
+    //radians about the camera's right axis. The pitch stops just short of
+    //v_up so the view never flips over.
+    pub fn orbit(&mut self, yaw: f64, pitch: f64){
+        let up = self.v_up.unit_vector();
+        let offset = rotate(self.look_from - self.look_at, up, yaw);
+        let distance = offset.length();
+
+        let min_angle = 0.01;
+        let angle = (offset.dot(up)/distance).clamp(-1.0, 1.0).acos();
+        let new_angle = (angle - pitch).clamp(min_angle, PI - min_angle);
+        let axis = Vec3::cross(up, offset).unit_vector();
+        self.look_from = self.look_at + rotate(offset, axis, new_angle - angle);
+    }
+
+    //Slides the camera and its target along the view plane
+    pub fn pan(&mut self, right: f64, up: f64){
+        let orientation = self.orientation();
+        let offset = right*orientation.u() + up*orientation.v();
+        self.look_from = self.look_from + offset;
+        self.look_at = self.look_at + offset;
+    }
+
+    //Scales the distance to the target, moving towards it when factor < 1
+    pub fn dolly(&mut self, factor: f64){
+        let offset = self.look_from - self.look_at;
+        let distance = (offset.length()*factor).max(0.01);
+        self.look_from = self.look_at + distance*offset.unit_vector();
+    }
+
+    //Moves the camera and its target together, forwards along the view
+    //direction, right, and up along v_up
+    pub fn fly(&mut self, forward: f64, right: f64, up: f64){
+        let orientation = self.orientation();
+        let offset = right*orientation.u() + up*self.v_up.unit_vector() - forward*orientation.w();
+        self.look_from = self.look_from + offset;
+        self.look_at = self.look_at + offset;
+    }
+
+    pub fn distance(&self) -> f64{
+        (self.look_from - self.look_at).length()
+    }
+}
+
+//Rotates v by angle radians about the unit vector axis (Rodrigues' formula)
+fn rotate(v: Vec3, axis: Vec3, angle: f64) -> Vec3{
+    let (sin, cos) = angle.sin_cos();
+    cos*v + sin*Vec3::cross(axis, v) + (axis.dot(v)*(1.0 - cos))*axis
+}
+
 impl Orientation{
 
     pub fn new(u: Vec3, v: Vec3, w: Vec3) -> Orientation{
@@ -70,3 +174,68 @@ impl Orientation{
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_near(a: Vec3, b: Vec3){
+        assert!((a - b).length() < 1e-9, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn test_orbit(){
+        let mut settings = CameraSettings::new(Point3::new(0.0, 0.0, 10.0), Point3::new(0.0, 0.0, 0.0));
+
+        //Case 1: Yaw a quarter turn about y
+        settings.orbit(PI/2.0, 0.0);
+        assert_near(settings.look_from, Point3::new(10.0, 0.0, 0.0));
+
+        //Case 2: Pitch up keeps the distance
+        settings.orbit(0.0, PI/4.0);
+        assert_near(settings.look_from, Point3::new(10.0/2.0_f64.sqrt(), 10.0/2.0_f64.sqrt(), 0.0));
+        assert!((settings.distance() - 10.0).abs() < 1e-9);
+
+        //Case 3: Pitch stops short of straight up
+        settings.orbit(0.0, PI);
+        assert!(settings.look_from.y() < 10.0 && settings.look_from.y() > 9.99);
+        assert!(settings.look_from.x() > 0.0);
+    }
+
+    #[test]
+    fn test_shutter(){
+        let mut settings = CameraSettings::new(Point3::new(0.0, 0.0, 10.0), Point3::new(0.0, 0.0, 0.0));
+
+        //Case 1: A closed shutter sends every ray at its opening time and draws nothing extra
+        settings.shutter_open = 0.25;
+        let cam = settings.to_camera(1.0);
+        let mut rng = Rng::new(3);
+        let r = cam.get_ray(0.5, 0.5, &mut rng);
+        assert_eq!(r.time(), 0.25);
+        assert_eq!(rng, {let mut rng = Rng::new(3); Vec3::rand_in_unit_disk(&mut rng); rng});
+
+        //Case 2: Times cover the open interval
+        settings.shutter_close = 0.75;
+        let cam = settings.to_camera(1.0);
+        let times: Vec<f64> = (0..1000).map(|_| cam.get_ray(0.5, 0.5, &mut rng).time()).collect();
+        assert!(times.iter().all(|time| (0.25..0.75).contains(time)));
+        assert!(times.iter().any(|&time| time < 0.3) && times.iter().any(|&time| time > 0.7));
+    }
+
+    #[test]
+    fn test_pan_dolly_fly(){
+        let mut settings = CameraSettings::new(Point3::new(0.0, 0.0, 10.0), Point3::new(0.0, 0.0, 0.0));
+
+        settings.pan(1.0, 2.0);
+        assert_near(settings.look_from, Point3::new(1.0, 2.0, 10.0));
+        assert_near(settings.look_at, Point3::new(1.0, 2.0, 0.0));
+
+        settings.dolly(0.5);
+        assert_near(settings.look_from, Point3::new(1.0, 2.0, 5.0));
+        assert_near(settings.look_at, Point3::new(1.0, 2.0, 0.0));
+
+        settings.fly(1.0, -1.0, 1.0);
+        assert_near(settings.look_from, Point3::new(0.0, 3.0, 4.0));
+        assert_near(settings.look_at, Point3::new(0.0, 3.0, -1.0));
+    }
+}
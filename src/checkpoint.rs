@@ -0,0 +1,215 @@
+use crate::vec::*;
+use crate::render::*;
+
+use std::convert::TryInto;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+const MAGIC: &str = "ray_trace checkpoint 3";
+
+//What a render must share with a checkpoint to carry on from it. The
+//sample count and adaptive sampling settings are left out so a finished
+//render can be resumed with more samples or a lower noise threshold.
+#[derive (Debug, Clone, PartialEq)]
+pub struct CheckpointSettings{
+    pub scene: String,
+    pub image_width: i32,
+    pub image_height: i32,
+    pub max_depth: i32,
+    pub roulette_depth: i32,
+    pub seed: u64,
+}
+
+//A render in progress: the summed samples of every pixel, kept exactly, and
+//the settings they were taken with
+#[derive (Clone)]
+pub struct Checkpoint{
+    pub settings: CheckpointSettings,
+    pub framebuffer: Framebuffer,
+}
+
+impl CheckpointSettings{
+    pub fn new(scene: &str, image_data: &ImageData) -> CheckpointSettings{
+        CheckpointSettings{
+            scene: scene.to_string(),
+            image_width: image_data.image_width,
+            image_height: image_data.image_height,
+            max_depth: image_data.max_depth,
+            roulette_depth: image_data.roulette_depth,
+            seed: image_data.seed,
+        }
+    }
+}
+
+impl fmt::Display for CheckpointSettings{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result{
+        write!(f, "scene {}, {}x{}, max depth {}, roulette depth {}, seed {}",
+               self.scene, self.image_width, self.image_height, self.max_depth, self.roulette_depth, self.seed)
+    }
+}
+
+impl Checkpoint{
+    pub fn new(settings: CheckpointSettings, framebuffer: Framebuffer) -> Checkpoint{
+        Checkpoint{settings, framebuffer}
+    }
+
+    //A text header of settings, then for each pixel, row by row from the
+    //top, its color sum and sum of squared brightness as little-endian f64s
+    //and its sample count as a little-endian i32. The file is written next to path and moved
+    //into place, so a crash while saving leaves the last checkpoint intact.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()>{
+        let path = path.as_ref();
+        let mut temp_name = path.as_os_str().to_owned();
+        temp_name.push(".tmp");
+
+        let mut writer = BufWriter::new(File::create(&temp_name)?);
+        let settings = &self.settings;
+        writeln!(writer, "{}", MAGIC)?;
+        writeln!(writer, "scene {}", settings.scene)?;
+        writeln!(writer, "width {}", settings.image_width)?;
+        writeln!(writer, "height {}", settings.image_height)?;
+        writeln!(writer, "max_depth {}", settings.max_depth)?;
+        writeln!(writer, "roulette_depth {}", settings.roulette_depth)?;
+        writeln!(writer, "seed {}", settings.seed)?;
+        writeln!(writer, "samples {}", self.framebuffer.samples_per_pixel)?;
+        writeln!(writer)?;
+        let framebuffer = &self.framebuffer;
+        for ((pixel, squares), samples) in framebuffer.pixel_colors.iter().zip(framebuffer.pixel_squares.iter()).zip(framebuffer.pixel_samples.iter()){
+            for value in [pixel.x(), pixel.y(), pixel.z(), *squares].iter(){
+                writer.write_all(&value.to_le_bytes())?;
+            }
+            writer.write_all(&samples.to_le_bytes())?;
+        }
+        writer.into_inner()?.sync_all()?;
+        fs::rename(&temp_name, path)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Checkpoint>{
+        let mut reader = BufReader::new(File::open(path)?);
+        if read_line(&mut reader)? != MAGIC{
+            return Err(invalid_data("not a checkpoint file".to_string()));
+        }
+        let scene = read_field(&mut reader, "scene")?;
+        let image_width = parse_field(&mut reader, "width")?;
+        let image_height = parse_field(&mut reader, "height")?;
+        let max_depth = parse_field(&mut reader, "max_depth")?;
+        let roulette_depth = parse_field(&mut reader, "roulette_depth")?;
+        let seed = parse_field(&mut reader, "seed")?;
+        let samples_per_pixel = parse_field(&mut reader, "samples")?;
+        if !read_line(&mut reader)?.is_empty() || image_width < 1 || image_height < 1{
+            return Err(invalid_data("malformed checkpoint header".to_string()));
+        }
+
+        //The header can't be trusted, so the data is read before anything is
+        //sized to fit it
+        let pixels = (image_width as usize).checked_mul(image_height as usize)
+            .ok_or_else(|| invalid_data("checkpoint image is too large".to_string()))?;
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+        if pixels.checked_mul(36) != Some(bytes.len()){
+            return Err(invalid_data(format!("expected {} pixels, found {} bytes of data", pixels, bytes.len())));
+        }
+        let mut pixel_colors = Vec::with_capacity(pixels);
+        let mut pixel_squares = Vec::with_capacity(pixels);
+        let mut pixel_samples = Vec::with_capacity(pixels);
+        for chunk in bytes.chunks_exact(36){
+            let value = |i: usize| f64::from_le_bytes(chunk[8*i..8*(i + 1)].try_into().unwrap());
+            pixel_colors.push(Color::new(value(0), value(1), value(2)));
+            pixel_squares.push(value(3));
+            pixel_samples.push(i32::from_le_bytes(chunk[32..36].try_into().unwrap()));
+        }
+
+        let settings = CheckpointSettings{scene, image_width, image_height, max_depth, roulette_depth, seed};
+        let framebuffer = Framebuffer::new(image_width, image_height, samples_per_pixel, pixel_colors, pixel_squares, pixel_samples);
+        Ok(Checkpoint::new(settings, framebuffer))
+    }
+}
+
+fn invalid_data(message: String) -> io::Error{
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn read_line<R: BufRead>(reader: &mut R) -> io::Result<String>{
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    if !line.ends_with('\n'){
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "checkpoint header is cut short"));
+    }
+    line.pop();
+    Ok(line)
+}
+
+//The rest of a "name value" header line
+fn read_field<R: BufRead>(reader: &mut R, name: &str) -> io::Result<String>{
+    let line = read_line(reader)?;
+    match line.split_once(' '){
+        Some((key, value)) if key == name => Ok(value.to_string()),
+        _ => Err(invalid_data(format!("expected '{}' in the checkpoint header, found '{}'", name, line))),
+    }
+}
+
+fn parse_field<R: BufRead, T: FromStr>(reader: &mut R, name: &str) -> io::Result<T>{
+    let value = read_field(reader, name)?;
+    value.parse().map_err(|_| invalid_data(format!("invalid {} '{}' in the checkpoint header", name, value)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    fn test_checkpoint() -> Checkpoint{
+        let settings = CheckpointSettings{scene: "my scenes/room.toml".to_string(), image_width: 3, image_height: 2, max_depth: 50, roulette_depth: 3, seed: 42};
+        let pixel_colors = (0..6).map(|i| Color::new(i as f64 / 3.0, 1e-300, 12345.678)).collect();
+        let pixel_squares = (0..6).map(|i| i as f64 * 0.1).collect();
+        let pixel_samples = vec![17, 3, 17, 17, 9, 17];
+        Checkpoint::new(settings, Framebuffer::new(3, 2, 17, pixel_colors, pixel_squares, pixel_samples))
+    }
+
+    #[test]
+    fn test_save_load(){
+        let path = env::temp_dir().join(format!("ray_trace_checkpoint_{}.ckpt", std::process::id()));
+        let checkpoint = test_checkpoint();
+        checkpoint.save(&path).unwrap();
+        let loaded = Checkpoint::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        //The sums come back bit for bit
+        assert_eq!(loaded.settings, checkpoint.settings);
+        assert_eq!(loaded.framebuffer.samples_per_pixel, 17);
+        assert_eq!(loaded.framebuffer.pixel_colors, checkpoint.framebuffer.pixel_colors);
+        assert_eq!(loaded.framebuffer.pixel_squares, checkpoint.framebuffer.pixel_squares);
+        assert_eq!(loaded.framebuffer.pixel_samples, checkpoint.framebuffer.pixel_samples);
+    }
+
+    #[test]
+    fn test_load_errors(){
+        let path = env::temp_dir().join(format!("ray_trace_bad_checkpoint_{}.ckpt", std::process::id()));
+
+        //Case 1: Not a checkpoint
+        fs::write(&path, "PF\n3 2\n-1.0\n").unwrap();
+        assert_eq!(Checkpoint::load(&path).err().unwrap().kind(), io::ErrorKind::InvalidData);
+
+        //Case 2: Cut short in the pixel data
+        test_checkpoint().save(&path).unwrap();
+        let bytes = fs::read(&path).unwrap();
+        fs::write(&path, &bytes[..bytes.len() - 10]).unwrap();
+        let err = Checkpoint::load(&path).err().unwrap();
+        assert_eq!((err.kind(), err.to_string()), (io::ErrorKind::InvalidData, "expected 6 pixels, found 206 bytes of data".to_string()));
+
+        //Case 3: Bad header value
+        let header = "ray_trace checkpoint 3\nscene a\nwidth x\n";
+        fs::write(&path, header).unwrap();
+        assert!(Checkpoint::load(&path).err().unwrap().to_string().contains("invalid width 'x'"));
+
+        //Case 4: A header far larger than the data
+        let header = "ray_trace checkpoint 3\nscene a\nwidth 2147483647\nheight 2147483647\nmax_depth 50\nroulette_depth 3\nseed 0\nsamples 1\n\n";
+        fs::write(&path, [header.as_bytes(), &[0u8; 36]].concat()).unwrap();
+        let err = Checkpoint::load(&path).err().unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,267 @@
+use crate::vec::*;
+use crate::sphere::*;
+use crate::transform::*;
+use crate::rng::*;
+
+use image::{ImageError, ImageResult, Rgb};
+use image::codecs::hdr::HdrDecoder;
+use image::error::{DecodingError, ImageFormatHint};
+
+use std::f64::consts::PI;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+use std::sync::Arc;
+
+//What a ray sees when it leaves the scene
+#[derive (Clone, PartialEq, Debug)]
+pub enum Background{
+    Constant(Color),
+    Map(Arc<EnvironmentMap>),
+}
+
+//An equirectangular image around the scene at an infinite distance.
+//Directions map onto it the same way points map onto a sphere's UVs, so +y
+//is the top row. Pixels are picked in proportion to their brightness when
+//the map is sampled as a light.
+#[derive (Clone, PartialEq, Debug)]
+pub struct EnvironmentMap{
+    width: usize,
+    height: usize,
+    pixels: Vec<Color>,
+    intensity: f64,
+    rotation: Transform,
+    //Running totals of the sampling weights along each row, stored row by
+    //row, and running totals of the rows
+    column_cdfs: Vec<f64>,
+    row_cdf: Vec<f64>,
+}
+
+impl Default for Background{
+    fn default() -> Background{
+        Background::Constant(Color::default())
+    }
+}
+
+impl From<Color> for Background{
+    fn from(color: Color) -> Background{
+        Background::Constant(color)
+    }
+}
+
+impl Background{
+    pub fn new_map(map: EnvironmentMap) -> Background{
+        Background::Map(Arc::new(map))
+    }
+
+    pub fn value(&self, direction: Vec3) -> Color{
+        match self{
+            Background::Constant(color) => *color,
+            Background::Map(map) => map.value(direction),
+        }
+    }
+}
+
+impl EnvironmentMap{
+    //Pixels are linear radiance stored row by row from the top of the image.
+    //The map is scaled by intensity and turned anticlockwise about +y by
+    //rotation radians.
+    pub fn new(width: usize, height: usize, pixels: Vec<Color>, intensity: f64, rotation: f64) -> EnvironmentMap{
+        assert_eq!(pixels.len(), width*height, "The pixel count does not match the image dimensions");
+
+        //Rows nearer the poles cover less of the sphere
+        let mut column_cdfs = Vec::with_capacity(width*height);
+        let mut row_cdf = Vec::with_capacity(height);
+        let mut total = 0.0;
+        for row in 0..height{
+            let sin_theta = (PI * (row as f64 + 0.5) / height as f64).sin();
+            let mut row_total = 0.0;
+            for pixel in &pixels[row*width..(row + 1)*width]{
+                row_total += pixel.luminance() * sin_theta;
+                column_cdfs.push(row_total);
+            }
+            total += row_total;
+            row_cdf.push(total);
+        }
+
+        let rotation = Transform::identity().rotate_y(rotation);
+        EnvironmentMap{width, height, pixels, intensity, rotation, column_cdfs, row_cdf}
+    }
+
+    //Loads an OpenEXR file, or a Radiance HDR file for any other extension
+    pub fn open<P: AsRef<Path>>(path: P, intensity: f64, rotation: f64) -> ImageResult<EnvironmentMap>{
+        let path = path.as_ref();
+        let is_exr = path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("exr"));
+        let (width, height, pixels) = if is_exr {read_exr(path)?} else {read_hdr(path)?};
+        Ok(EnvironmentMap::new(width, height, pixels, intensity, rotation))
+    }
+
+    //Nearest pixel, so the radiance is constant wherever the density is
+    pub fn value(&self, direction: Vec3) -> Color{
+        let (col, row, _) = self.texel(direction);
+        self.intensity * self.pixels[row*self.width + col]
+    }
+
+    //The pixel that direction lands on and the sine of its angle from +y
+    fn texel(&self, direction: Vec3) -> (usize, usize, f64){
+        let local = self.rotation.inverse().vector(direction).unit_vector();
+        let (u, v) = Sphere::get_uv(local);
+        let col = ((u * self.width as f64) as usize).min(self.width - 1);
+        let row = (((1.0 - v) * self.height as f64) as usize).min(self.height - 1);
+        (col, row, (v * PI).sin())
+    }
+
+    //A direction towards the map, the radiance seen along it and the
+    //density, per unit solid angle, of choosing it. None if the map is black.
+    pub fn sample(&self, rng: &mut Rng) -> Option<(Vec3, Color, f64)>{
+        let total = *self.row_cdf.last()?;
+        if total <= 0.0{
+            return None;
+        }
+        let row = find_bin(&self.row_cdf, total * rng.f64());
+        let columns = &self.column_cdfs[row*self.width..(row + 1)*self.width];
+        let col = find_bin(columns, columns[self.width - 1] * rng.f64());
+
+        //Uniform within the pixel, inverting Sphere::get_uv
+        let theta = PI * (row as f64 + rng.f64()) / self.height as f64;
+        let phi = 2.0 * PI * (col as f64 + rng.f64()) / self.width as f64 - PI;
+        let local = Vec3::new(theta.sin() * phi.cos(), theta.cos(), -theta.sin() * phi.sin());
+        let direction = self.rotation.vector(local);
+
+        let pdf = self.pdf(direction);
+        if pdf <= 0.0{
+            return None;
+        }
+        Some((direction, self.value(direction), pdf))
+    }
+
+    //Probability density, per unit solid angle, of sample choosing direction
+    pub fn pdf(&self, direction: Vec3) -> f64{
+        let total = self.row_cdf.last().copied().unwrap_or(0.0);
+        let (col, row, sin_theta) = self.texel(direction);
+        if total <= 0.0 || sin_theta <= 0.0{
+            return 0.0;
+        }
+        let index = row*self.width + col;
+        let weight = self.column_cdfs[index] - if col > 0 {self.column_cdfs[index - 1]} else {0.0};
+
+        //Each pixel covers 2 pi^2 sin(theta) / (width * height) steradians
+        (weight / total) * (self.width * self.height) as f64 / (2.0 * PI * PI * sin_theta)
+    }
+}
+
+//The first bin whose running total is above target, which skips bins with
+//no weight
+fn find_bin(cdf: &[f64], target: f64) -> usize{
+    cdf.partition_point(|&total| total <= target).min(cdf.len() - 1)
+}
+
+fn read_hdr(path: &Path) -> ImageResult<(usize, usize, Vec<Color>)>{
+    let decoder = HdrDecoder::new(BufReader::new(File::open(path)?))?;
+    let metadata = decoder.metadata();
+    let pixels = decoder.read_image_hdr()?
+                        .into_iter()
+                        .map(|Rgb([r, g, b])| Color::new(r as f64, g as f64, b as f64))
+                        .collect();
+    Ok((metadata.width as usize, metadata.height as usize, pixels))
+}
+
+fn read_exr(path: &Path) -> ImageResult<(usize, usize, Vec<Color>)>{
+    let image = exr::prelude::read_first_rgba_layer_from_file(path,
+        |resolution, _| (resolution.width(), vec![Color::default(); resolution.width() * resolution.height()]),
+        |(width, pixels), position, (r, g, b, _): (f32, f32, f32, f32)| {
+            pixels[position.y() * *width + position.x()] = Color::new(r as f64, g as f64, b as f64);
+        }).map_err(|err| ImageError::Decoding(DecodingError::new(ImageFormatHint::Name("OpenEXR".to_string()), err)))?;
+    let size = image.layer_data.size;
+    let (_, pixels) = image.layer_data.channel_data.pixels;
+    Ok((size.width(), size.height(), pixels))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::codecs::hdr::HdrEncoder;
+    use std::{env, fs};
+
+    //A dark map with one bright pixel in the second row
+    fn test_map(rotation: f64) -> EnvironmentMap{
+        let mut pixels = vec![Color::new(0.1, 0.1, 0.1); 8*4];
+        pixels[8 + 2] = Color::new(50.0, 40.0, 30.0);
+        EnvironmentMap::new(8, 4, pixels, 2.0, rotation)
+    }
+
+    #[test]
+    fn test_value(){
+        let map = test_map(0.0);
+        assert_eq!(map.value(Vec3::new(0.0, -1.0, 0.0)), Color::new(0.2, 0.2, 0.2));
+
+        //The bright pixel is above the horizon, a quarter of the way round
+        let (col, row, _) = map.texel(Vec3::new(0.0, 0.5, 1.0));
+        assert_eq!((col, row), (2, 1));
+        assert_eq!(map.value(Vec3::new(0.0, 0.5, 1.0)), Color::new(100.0, 80.0, 60.0));
+
+        //Turned a quarter turn further round
+        let map = test_map(PI/2.0);
+        assert_eq!(map.value(Vec3::new(1.0, 0.5, 0.0)), Color::new(100.0, 80.0, 60.0));
+    }
+
+    #[test]
+    fn test_sample_and_pdf(){
+        let map = test_map(0.3);
+
+        //Case 1: Samples agree with the density and mostly find the bright pixel
+        let mut rng = Rng::new(0);
+        let mut bright = 0;
+        for _ in 0..1000{
+            let (direction, radiance, pdf) = map.sample(&mut rng).unwrap();
+            assert!((pdf - map.pdf(direction)).abs() < 1e-9 * pdf);
+            assert_eq!(radiance, map.value(direction));
+            if radiance.x() > 1.0{
+                bright += 1;
+            }
+        }
+        assert!(bright > 900);
+
+        //Case 2: The density integrates to one over the sphere
+        let steps = 400;
+        let mut total = 0.0;
+        for i in 0..steps{
+            let theta = (i as f64 + 0.5) / steps as f64 * PI;
+            for j in 0..2*steps{
+                let phi = (j as f64 + 0.5) / steps as f64 * PI;
+                let direction = Vec3::new(theta.sin() * phi.cos(), theta.cos(), theta.sin() * phi.sin());
+                total += map.pdf(direction) * theta.sin();
+            }
+        }
+        total *= (PI / steps as f64) * (PI / steps as f64);
+        assert!((total - 1.0).abs() < 1e-2, "pdf integrates to {}", total);
+
+        //Case 3: A black map can't be sampled
+        let black = EnvironmentMap::new(2, 2, vec![Color::default(); 4], 1.0, 0.0);
+        assert!(black.sample(&mut rng).is_none());
+        assert_eq!(black.pdf(Vec3::new(0.0, 0.0, 1.0)), 0.0);
+    }
+
+    #[test]
+    fn test_open(){
+        //Case 1: Radiance HDR
+        let path = env::temp_dir().join(format!("ray_trace_environment_{}.hdr", std::process::id()));
+        let data = vec![Rgb([4.0f32, 2.0, 1.0]), Rgb([0.0, 0.0, 0.0]), Rgb([0.5, 0.5, 0.5]), Rgb([1.0, 1.0, 1.0])];
+        HdrEncoder::new(File::create(&path).unwrap()).encode(&data, 2, 2).unwrap();
+        let map = EnvironmentMap::open(&path, 1.0, 0.0).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!((map.width, map.height), (2, 2));
+        assert_eq!(map.pixels[0], Color::new(4.0, 2.0, 1.0));
+
+        //Case 2: OpenEXR
+        let path = env::temp_dir().join(format!("ray_trace_environment_{}.exr", std::process::id()));
+        exr::prelude::write_rgb_file(&path, 3, 1, |i, _| (i as f32, 0.5f32, 0.25f32)).unwrap();
+        let map = EnvironmentMap::open(&path, 1.0, 0.0).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!((map.width, map.height), (3, 1));
+        assert_eq!(map.pixels[2], Color::new(2.0, 0.5, 0.25));
+
+        //Case 3: Missing files
+        assert!(EnvironmentMap::open("missing.hdr", 1.0, 0.0).is_err());
+    }
+}
@@ -0,0 +1,97 @@
+use glium::glutin::event::VirtualKeyCode;
+use imgui::*;
+
+use ray_trace::CameraSettings;
+
+// Radians turned per pixel of mouse drag
+const ORBIT_SPEED: f64 = 0.005;
+// Fraction of the distance to the target moved per scroll step
+const DOLLY_STEP: f64 = 0.1;
+// Fraction of the distance to the target flown per second
+const FLY_SPEED: f64 = 0.5;
+
+// Mouse and keyboard navigation for the render preview:
+//  - left drag orbits around look_at
+//  - right or middle drag pans
+//  - the scroll wheel dollies towards look_at
+//  - WASD flies, with Q and E for down and up (hold shift to go faster)
+pub struct CameraControls {
+    pub settings: CameraSettings,
+    dragging: bool,
+}
+
+impl CameraControls {
+    pub fn new(settings: CameraSettings) -> Self {
+        CameraControls {
+            settings,
+            dragging: false,
+        }
+    }
+
+    // Call straight after drawing the image. image_height is its height on
+    // screen in pixels. Returns true if the camera moved.
+    pub fn handle_input(&mut self, ui: &Ui, image_height: f32) -> bool {
+        let io = ui.io();
+        let before = self.settings;
+
+        // Drags have to start on the image but may then leave it
+        let buttons = [MouseButton::Left, MouseButton::Right, MouseButton::Middle];
+        if ui.is_item_hovered() && buttons.iter().any(|&button| ui.is_mouse_clicked(button)) {
+            self.dragging = true;
+        } else if !buttons.iter().any(|&button| ui.is_mouse_down(button)) {
+            self.dragging = false;
+        }
+
+        let [dx, dy] = io.mouse_delta;
+        let (dx, dy) = (dx as f64, dy as f64);
+        if self.dragging && (dx != 0.0 || dy != 0.0) {
+            if ui.is_mouse_down(MouseButton::Left) {
+                self.settings.orbit(-dx * ORBIT_SPEED, dy * ORBIT_SPEED);
+            } else {
+                // Move the scene with the mouse at the depth of look_at
+                let view_height = 2.0 * self.settings.distance() * (0.5 * self.settings.v_fov.to_radians()).tan();
+                let scale = view_height / image_height.max(1.0) as f64;
+                self.settings.pan(-dx * scale, dy * scale);
+            }
+        }
+
+        if ui.is_item_hovered() && io.mouse_wheel != 0.0 {
+            self.settings.dolly((1.0 - DOLLY_STEP).powf(io.mouse_wheel as f64));
+        }
+
+        if ui.is_window_focused() && !io.want_text_input {
+            let key = |code: VirtualKeyCode| if io.keys_down[code as usize] { 1.0 } else { 0.0 };
+            let forward = key(VirtualKeyCode::W) - key(VirtualKeyCode::S);
+            let right = key(VirtualKeyCode::D) - key(VirtualKeyCode::A);
+            let up = key(VirtualKeyCode::E) - key(VirtualKeyCode::Q);
+            if forward != 0.0 || right != 0.0 || up != 0.0 {
+                let boost = if io.key_shift { 4.0 } else { 1.0 };
+                let step = FLY_SPEED * boost * self.settings.distance() * io.delta_time as f64;
+                self.settings.fly(forward * step, right * step, up * step);
+            }
+        }
+
+        self.settings != before
+    }
+
+    // Lens sliders. Returns true if any of them changed.
+    pub fn show_sliders(&mut self, ui: &Ui) -> bool {
+        let mut changed = false;
+        changed |= Slider::new("Field of view", 1.0, 120.0)
+            .display_format("%.1f deg")
+            .build(ui, &mut self.settings.v_fov);
+        changed |= Slider::new("Aperture", 0.0, 2.0)
+            .display_format("%.3f")
+            .build(ui, &mut self.settings.aperture);
+        changed |= Slider::new("Focus distance", 0.1, 100.0)
+            .display_format("%.2f")
+            .flags(SliderFlags::LOGARITHMIC)
+            .build(ui, &mut self.settings.focus_dist);
+        ui.same_line();
+        if ui.button("Focus on target") {
+            self.settings.focus_dist = self.settings.distance();
+            changed = true;
+        }
+        changed
+    }
+}
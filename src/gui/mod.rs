@@ -1,2 +1,3 @@
-pub mod custom_textures;
+pub mod camera_controls;
+pub mod render_view;
 pub mod support;
\ No newline at end of file
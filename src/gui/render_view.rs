@@ -0,0 +1,171 @@
+use std::borrow::Cow;
+use std::error::Error;
+use std::rc::Rc;
+use std::sync::mpsc::{Receiver, Sender};
+use std::time::{Duration, Instant};
+
+use glium::{
+    backend::Facade,
+    texture::{ClientFormat, RawImage2d},
+    uniforms::{MagnifySamplerFilter, MinifySamplerFilter, SamplerBehavior},
+    Rect, Texture2d,
+};
+use imgui::*;
+use imgui_glium_renderer::Texture;
+
+use ray_trace::{CameraSettings, Framebuffer, ImageData};
+
+use super::camera_controls::CameraControls;
+
+//Shows a render in progress. Each finished sample pass arrives on `passes`
+//with the camera it was rendered from and is copied into the texture.
+//Moving the camera sends the new settings on `camera_changes` so the render
+//can start again.
+pub struct RenderView {
+    texture_id: TextureId,
+    gl_texture: Rc<Texture2d>,
+    width: u32,
+    height: u32,
+    samples: i32,
+    target_samples: i32,
+    passes: Receiver<(CameraSettings, Framebuffer)>,
+    camera: CameraControls,
+    camera_changes: Sender<CameraSettings>,
+    started: Instant,
+    finished: Option<Duration>,
+}
+
+impl RenderView {
+    pub fn new<F>(
+        gl_ctx: &F,
+        textures: &mut Textures<Texture>,
+        image_data: ImageData,
+        camera: CameraSettings,
+        passes: Receiver<(CameraSettings, Framebuffer)>,
+        camera_changes: Sender<CameraSettings>,
+    ) -> Result<Self, Box<dyn Error>>
+    where
+        F: Facade,
+    {
+        let width = image_data.image_width as u32;
+        let height = image_data.image_height as u32;
+
+        // Black until the first pass arrives
+        let raw = RawImage2d {
+            data: Cow::Owned(vec![0u8; (width * height * 3) as usize]),
+            width,
+            height,
+            format: ClientFormat::U8U8U8,
+        };
+        let gl_texture = Rc::new(Texture2d::new(gl_ctx, raw)?);
+        let texture = Texture {
+            texture: Rc::clone(&gl_texture),
+            sampler: SamplerBehavior {
+                magnify_filter: MagnifySamplerFilter::Nearest,
+                minify_filter: MinifySamplerFilter::Linear,
+                ..Default::default()
+            },
+        };
+        let texture_id = textures.insert(texture);
+
+        Ok(RenderView {
+            texture_id,
+            gl_texture,
+            width,
+            height,
+            samples: 0,
+            target_samples: image_data.samples_per_pixel,
+            passes,
+            camera: CameraControls::new(camera),
+            camera_changes,
+            started: Instant::now(),
+            finished: None,
+        })
+    }
+
+    // Uploads the most recent pass, skipping any the GUI fell behind on and
+    // any still in flight from before the camera last moved
+    fn update(&mut self) {
+        let camera = self.camera.settings;
+        let latest = self
+            .passes
+            .try_iter()
+            .filter(|(settings, _)| *settings == camera)
+            .last();
+        if let Some((_, framebuffer)) = latest {
+            let raw = RawImage2d {
+                data: Cow::Owned(framebuffer.to_rgb8()),
+                width: self.width,
+                height: self.height,
+                format: ClientFormat::U8U8U8,
+            };
+            let rect = Rect {
+                left: 0,
+                bottom: 0,
+                width: self.width,
+                height: self.height,
+            };
+            self.gl_texture.write(rect, raw);
+            self.samples = framebuffer.samples_per_pixel;
+            if self.samples >= self.target_samples {
+                self.finished = Some(self.started.elapsed());
+            }
+        }
+    }
+
+    fn restart(&mut self) {
+        // The render thread only stops once the window has gone
+        let _ = self.camera_changes.send(self.camera.settings);
+        self.samples = 0;
+        self.started = Instant::now();
+        self.finished = None;
+    }
+
+    pub fn show(&mut self, ui: &Ui) {
+        self.update();
+
+        let mut camera_moved = false;
+        Window::new("Render")
+            .size([1000.0, 760.0], Condition::FirstUseEver)
+            .build(ui, || {
+                let elapsed = self.finished.unwrap_or_else(|| self.started.elapsed());
+                ui.text(format!(
+                    "Samples: {} / {}",
+                    self.samples, self.target_samples
+                ));
+                ui.text(format!("Elapsed: {}", format_duration(elapsed)));
+                match self.finished {
+                    Some(_) => ui.text("Remaining: done"),
+                    None if self.samples == 0 => ui.text("Remaining: estimating..."),
+                    None => {
+                        let remaining = elapsed.mul_f64(
+                            (self.target_samples - self.samples) as f64 / self.samples as f64,
+                        );
+                        ui.text(format!("Remaining: {}", format_duration(remaining)));
+                    }
+                }
+                ui.separator();
+                camera_moved |= self.camera.show_sliders(ui);
+                ui.text("Drag to orbit (left) or pan (right), scroll to dolly, WASD/QE to fly");
+                ui.separator();
+
+                // Scale the image to fit the window, keeping its aspect ratio
+                let [avail_width, avail_height] = ui.content_region_avail();
+                let scale = (avail_width / self.width as f32)
+                    .min(avail_height / self.height as f32)
+                    .max(0.0);
+                let image_height = self.height as f32 * scale;
+                Image::new(self.texture_id, [self.width as f32 * scale, image_height]).build(ui);
+                camera_moved |= self.camera.handle_input(ui, image_height);
+            });
+
+        if camera_moved {
+            self.restart();
+        }
+    }
+}
+
+fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    format!("{:02}:{:02}:{:02}", seconds / 3600, seconds / 60 % 60, seconds % 60)
+}
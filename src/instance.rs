@@ -0,0 +1,197 @@
+use crate::vec::*;
+use crate::ray::*;
+use crate::traceable::*;
+use crate::material::*;
+use crate::bvh::*;
+use crate::light::*;
+use crate::transform::*;
+use crate::rng::*;
+
+use std::sync::Arc;
+
+//A shared BVH placed in the scene with a transform. Any number of instances
+//can point at the same BVH, so repeated objects are only stored once.
+#[derive (Clone)]
+pub struct Instance{
+    object: Arc<FlatBvh>,
+    placement: Placement,
+    bb: Aabb,
+    mat: Material,
+}
+
+//Instances either stay put or move with the time of each ray, in which
+//case their bounding box covers the whole motion. Both are kept on the heap
+//so Primitive stays small.
+#[derive (Clone)]
+enum Placement{
+    Fixed(Box<Transform>),
+    Animated(AnimatedTransform),
+}
+
+impl Instance{
+    pub fn new(object: Arc<FlatBvh>, transform: Transform) -> Instance{
+        let bb = transform.bounding_box(object.bounding_box().expect("A BVH can always be bound"));
+        Instance{object, placement: Placement::Fixed(Box::new(transform)), bb, mat: Material::Lambertian(Lambertian::default())}
+    }
+
+    pub fn new_animated(object: Arc<FlatBvh>, transform: AnimatedTransform) -> Instance{
+        let bb = transform.bounding_box(object.bounding_box().expect("A BVH can always be bound"));
+        Instance{object, placement: Placement::Animated(transform), bb, mat: Material::Lambertian(Lambertian::default())}
+    }
+
+    pub fn object(&self) -> &Arc<FlatBvh>{
+        &self.object
+    }
+
+    pub fn transform(&self, time: f64) -> Transform{
+        match &self.placement{
+            Placement::Fixed(transform) => **transform,
+            Placement::Animated(transform) => transform.at(time),
+        }
+    }
+}
+
+impl Hit for Instance{
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rng: &mut Rng) -> Option<(HitRecord, &Material)> {
+        let transform = self.transform(r.time());
+
+        //The object ray is not normalised and every shape gives t along the
+        //ray it is given, so t carries over unchanged
+        let object_ray = transform.inverse_ray(r);
+        let (mut rec, mat) = self.object.hit(&object_ray, t_min, t_max, rng)?;
+
+        //Transformed normals face the ray exactly when they did in object space
+        rec.p = transform.point(rec.p);
+        rec.normal = transform.normal(rec.normal).unit_vector();
+        rec.p_err = transform.vector(rec.p_err).abs();
+        rec.light_sampled = false;
+        Some((rec, mat))
+    }
+
+    fn bounding_box(&self) -> Option<Aabb>{
+        Some(self.bb)
+    }
+}
+
+//Lights inside instances are not sampled directly. Their hits are marked as
+//such, so light found by bouncing into them is not weighted down.
+impl Surface for Instance{
+    fn area(&self) -> f64{
+        0.0
+    }
+
+    fn sample_surface(&self, _: &mut Rng) -> (Point3, Vec3){
+        (self.bb.min(), Vec3::default())
+    }
+
+    fn material(&self) -> &Material{
+        &self.mat
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::primitive::*;
+    use crate::rect::*;
+    use std::f64::consts::PI;
+
+    fn unit_sphere() -> Arc<FlatBvh>{
+        let mut list = TraceableList::new();
+        list.add(Primitive::new_sphere(Point3::new(0.0, 0.0, 0.0), 1.0, Material::new_lambertian(Color::new(0.5, 0.5, 0.5))));
+        Arc::new(FlatBvh::new(list))
+    }
+
+    #[test]
+    fn test_hit(){
+        let mut rng = Rng::new(0);
+        let sphere = unit_sphere();
+        let instance = Instance::new(Arc::clone(&sphere), Transform::identity().scale(Vec3::new(2.0, 2.0, 2.0))
+                                                                               .translate(Vec3::new(0.0, 0.0, -10.0)));
+
+        //Case 1: Hit on the scaled and moved sphere
+        let r = Ray::new(Point3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0));
+        let (rec, _) = instance.hit(&r, 0.001, 100.0, &mut rng).unwrap();
+        assert!((rec.t - 8.0).abs() < 1e-9);
+        assert!((rec.p - Point3::new(0.0, 0.0, -8.0)).length() < 1e-9);
+        assert!((rec.normal - Vec3::new(0.0, 0.0, 1.0)).length() < 1e-9);
+        assert!(rec.front_face);
+
+        //Case 2: Miss where the untransformed sphere would have been hit
+        let r = Ray::new(Point3::new(0.0, 0.0, 5.0), Vec3::new(1.0, 0.0, 0.0));
+        assert!(instance.hit(&r, 0.001, 100.0, &mut rng).is_none());
+
+        //Case 3: The instances share one BVH
+        let copy = Instance::new(Arc::clone(&sphere), Transform::identity());
+        assert!(Arc::ptr_eq(instance.object(), copy.object()));
+        assert_eq!(Arc::strong_count(&sphere), 3);
+    }
+
+    #[test]
+    fn test_animated(){
+        let mut rng = Rng::new(0);
+        let still = Vec3::new(1.0, 1.0, 1.0);
+        let instance = Instance::new_animated(unit_sphere(), AnimatedTransform::new(vec![
+            Keyframe::new(0.0, still, Vec3::default(), Vec3::new(0.0, 0.0, 0.0)),
+            Keyframe::new(1.0, still, Vec3::default(), Vec3::new(4.0, 0.0, 0.0)),
+        ]));
+
+        //Case 1: The sphere is where the ray's time puts it
+        let r = |time| Ray::new_at_time(Point3::new(3.0, 0.0, 10.0), Vec3::new(0.0, 0.0, -1.0), time);
+        assert!(instance.hit(&r(0.0), 0.001, 100.0, &mut rng).is_none());
+        let (rec, _) = instance.hit(&r(0.75), 0.001, 100.0, &mut rng).unwrap();
+        assert!((rec.t - 9.0).abs() < 1e-9);
+        assert!((rec.normal - Vec3::new(0.0, 0.0, 1.0)).length() < 1e-9);
+
+        //Case 2: The box covers the whole path
+        let bb = instance.bounding_box().unwrap();
+        assert!(bb.min().x() <= -1.0 && bb.max().x() >= 5.0);
+    }
+
+    #[test]
+    fn test_rotated_rect(){
+        let mut rng = Rng::new(0);
+        let mut list = TraceableList::new();
+        list.add(Primitive::new_rect(RectAxes::XY, -1.0, 1.0, -1.0, 1.0, 0.0, Material::new_lambertian(Color::new(0.5, 0.5, 0.5))));
+        let instance = Instance::new(Arc::new(FlatBvh::new(list)), Transform::identity().rotate_y(PI/2.0));
+
+        //The rect now lies in the yz plane facing +x
+        let r = Ray::new(Point3::new(5.0, 0.5, 0.5), Vec3::new(-1.0, 0.0, 0.0));
+        let (rec, _) = instance.hit(&r, 0.001, 100.0, &mut rng).unwrap();
+        assert!((rec.t - 5.0).abs() < 1e-9);
+        assert!((rec.normal - Vec3::new(1.0, 0.0, 0.0)).length() < 1e-9);
+
+        let bb = instance.bounding_box().unwrap();
+        assert!(bb.min().x() < 0.0 && bb.max().x() > 0.0);
+        assert!((bb.max().z() - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_scaled_mesh(){
+        let mut rng = Rng::new(0);
+        let mat = Material::new_lambertian(Color::new(0.5, 0.5, 0.5));
+        let normals = [Vec3::new(0.0, 0.0, 1.0); 3];
+        let mut mesh = TraceableList::new();
+        mesh.add(Primitive::new_triangle([Point3::new(-1.0, -1.0, 0.0), Point3::new(1.0, -1.0, 0.0), Point3::new(1.0, 1.0, 0.0)], normals, mat.clone()));
+        mesh.add(Primitive::new_triangle([Point3::new(-1.0, -1.0, 0.0), Point3::new(1.0, 1.0, 0.0), Point3::new(-1.0, 1.0, 0.0)], normals, mat.clone()));
+        let instance = Primitive::new_instance(Arc::new(FlatBvh::new(mesh)), Transform::identity().scale(Vec3::new(2.0, 2.0, 2.0))
+                                                                                                 .translate(Vec3::new(0.0, 0.0, -10.0)));
+
+        //Case 1: The scaled mesh is hit at the t of the world ray
+        let r = Ray::new(Point3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0));
+        let (rec, _) = instance.hit(&r, 0.001, 100.0, &mut rng).unwrap();
+        assert!((rec.t - 10.0).abs() < 1e-9);
+        assert!((rec.p - Point3::new(0.0, 0.0, -10.0)).length() < 1e-9);
+
+        //Case 2: A sphere in front of the mesh is hit first
+        let mut world = TraceableList::new();
+        world.add(instance);
+        world.add(Primitive::new_sphere(Point3::new(0.0, 0.0, -6.5), 0.5, mat));
+        let world = FlatBvh::new(world);
+        let (rec, _) = world.hit(&r, 0.001, 100.0, &mut rng).unwrap();
+        assert!((rec.t - 6.0).abs() < 1e-9);
+        let r = Ray::new(Point3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -2.0));
+        let (rec, _) = world.hit(&r, 0.001, 100.0, &mut rng).unwrap();
+        assert!((rec.t - 3.0).abs() < 1e-9);
+    }
+}
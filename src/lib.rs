@@ -0,0 +1,67 @@
+#[macro_use]
+extern crate impl_ops;
+extern crate tobj;
+extern crate num_cpus;
+extern crate enum_dispatch;
+extern crate image;
+extern crate serde;
+extern crate toml;
+extern crate exr;
+
+
+pub mod vec;
+pub mod ray;
+pub mod sphere;
+pub mod traceable;
+pub mod camera;
+pub mod material;
+pub mod util;
+pub mod rng;
+pub mod bvh;
+pub mod rect;
+pub mod quad;
+pub mod quadric;
+pub mod triangle;
+pub mod light;
+pub mod texture;
+pub mod environment;
+pub mod transform;
+pub mod instance;
+pub mod medium;
+pub mod volume;
+pub mod scenes;
+pub mod primitive;
+pub mod bounding_box;
+pub mod render;
+pub mod scene_file;
+pub mod output;
+pub mod checkpoint;
+
+//The rest is reached through the modules
+pub use crate::scene_file::{load_scene, parse_scene, SceneFile, SceneError};
+pub use crate::render::{render, render_progressive, render_progressive_from, ImageData, SceneData, AdaptiveSampling, Framebuffer};
+pub use crate::camera::{Camera, CameraSettings};
+pub use crate::output::{write_image, write_sample_heatmap, OutputFormat};
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::PI;
+    use crate::util::*;
+
+    #[test]
+    fn test_bound(){
+        let x = 10.0;
+        let max_x = bound(x, 5.0, 7.0);
+        let min_x = bound(x, 11.0, 14.0);
+        assert_eq!(max_x, 7.0);
+        assert_eq!(min_x, 11.0);
+    }
+
+    #[test]
+    fn test_deg_2_rad(){
+        let deg = 180.0;
+        let rad = deg_to_rad(deg);
+        assert_eq!(PI, rad);
+    }
+
+}
@@ -0,0 +1,115 @@
+use crate::vec::*;
+use crate::material::*;
+use crate::traceable::*;
+use crate::primitive::*;
+use crate::util::*;
+use crate::rng::*;
+use crate::enum_dispatch::*;
+
+//Surfaces that can be sampled directly when they are used as lights
+#[enum_dispatch]
+pub trait Surface{
+    fn area(&self) -> f64;
+
+    //A point chosen uniformly over the surface, with its outward normal
+    fn sample_surface(&self, rng: &mut Rng) -> (Point3, Vec3);
+
+    fn material(&self) -> &Material;
+}
+
+//The emissive primitives of a scene. Lights are picked in proportion to
+//their area, so every point on every light is equally likely to be sampled.
+#[derive (Default, Clone)]
+pub struct LightList{
+    lights: Vec<Primitive>,
+    cumulative_areas: Vec<f64>,
+    total_area: f64,
+}
+
+impl LightList{
+    //Collects the primitives of world whose material emits light
+    pub fn new(world: &TraceableList) -> LightList{
+        let mut light_list = LightList::default();
+        for index in 0..world.len(){
+            let primitive = world.get(index);
+            if primitive.material().emit() != Color::default() && primitive.area() > 0.0{
+                light_list.total_area += primitive.area();
+                light_list.cumulative_areas.push(light_list.total_area);
+                light_list.lights.push(primitive);
+            }
+        }
+        light_list
+    }
+
+    pub fn len(&self) -> usize{
+        self.lights.len()
+    }
+
+    pub fn is_empty(&self) -> bool{
+        self.lights.is_empty()
+    }
+
+    //A point on one of the lights, its outward normal and the light emitted
+    pub fn sample(&self, rng: &mut Rng) -> (Point3, Vec3, Color){
+        let target = rand_double(rng, 0.0, self.total_area);
+        let index = self.cumulative_areas.iter()
+                                         .position(|&area| target < area)
+                                         .unwrap_or(self.lights.len() - 1);
+        let light = &self.lights[index];
+        let (point, normal) = light.sample_surface(rng);
+        (point, normal, light.material().emit())
+    }
+
+    //Probability density, per unit solid angle seen from origin, of sample
+    //choosing the light point with the given normal
+    pub fn pdf(&self, origin: Point3, point: Point3, normal: Vec3) -> f64{
+        let to_light = point - origin;
+        let distance_squared = to_light.length_squared();
+        let cosine = (to_light.dot(normal) / (distance_squared.sqrt() * normal.length())).abs();
+        if cosine < 1e-8 || self.total_area <= 0.0 {
+            return 0.0;
+        }
+        distance_squared / (cosine * self.total_area)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rect::*;
+
+    #[test]
+    fn test_new(){
+        let mut world = TraceableList::new();
+        world.add(Primitive::new_sphere(Point3::new(0.0, 0.0, 0.0), 1.0, Material::new_lambertian(Color::new(0.5, 0.5, 0.5))));
+        world.add(Primitive::new_rect(RectAxes::XZ, -1.0, 1.0, -2.0, 2.0, 5.0, Material::new_diffuse_light(Color::new(4.0, 4.0, 4.0))));
+        world.add(Primitive::new_sphere(Point3::new(0.0, 3.0, 0.0), 0.5, Material::new_diffuse_light(Color::new(1.0, 1.0, 1.0))));
+
+        let lights = LightList::new(&world);
+        assert_eq!(lights.len(), 2);
+        assert!((lights.total_area - (8.0 + std::f64::consts::PI)).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_sample_and_pdf(){
+        let mut world = TraceableList::new();
+        world.add(Primitive::new_rect(RectAxes::XZ, -1.0, 1.0, -2.0, 2.0, 5.0, Material::new_diffuse_light(Color::new(4.0, 4.0, 4.0))));
+        let lights = LightList::new(&world);
+
+        let mut rng = Rng::new(0);
+        for _ in 0..100{
+            let (point, normal, emitted) = lights.sample(&mut rng);
+            assert_eq!(point.y(), 5.0);
+            assert!(point.x().abs() <= 1.0 && point.z().abs() <= 2.0);
+            assert_eq!(normal, Vec3::new(0.0, 1.0, 0.0));
+            assert_eq!(emitted, Color::new(4.0, 4.0, 4.0));
+        }
+
+        //Straight below the light: distance^2 / (cos * area)
+        let origin = Point3::new(0.0, 0.0, 0.0);
+        assert_eq!(lights.pdf(origin, Point3::new(0.0, 5.0, 0.0), Vec3::new(0.0, 1.0, 0.0)), 25.0/8.0);
+
+        //Edge on
+        assert_eq!(lights.pdf(Point3::new(-3.0, 5.0, 0.0), Point3::new(0.0, 5.0, 0.0), Vec3::new(0.0, 1.0, 0.0)), 0.0);
+    }
+}
@@ -1,20 +1,33 @@
 use crate::vec::*;
 use crate::ray::*;
 use crate::traceable::*;
-use crate::util::*;
+use crate::texture::*;
+use crate::rng::*;
 
-#[derive(Default, Clone, Copy, PartialEq)]
+use std::f64::consts::PI;
+
+#[derive(Default, Clone, PartialEq)]
 pub struct Lambertian{
-    pub albedo: Color
+    pub albedo: Texture
 }
 
-#[derive(Default, Clone, Copy, PartialEq)]
+#[derive(Default, Clone, PartialEq)]
 pub struct Metal{
-    albedo: Color,
+    albedo: Texture,
     fuzz: f64
 }
 
 
+//A rough conductor with a GGX (Trowbridge-Reitz) microfacet distribution.
+//albedo is the reflectance at normal incidence, and the roughness along the
+//two tangent directions can differ to give a brushed look.
+#[derive(Default, Clone, PartialEq)]
+pub struct Conductor{
+    albedo: Texture,
+    alpha_x: f64,
+    alpha_y: f64
+}
+
 #[derive(Default, Clone, Copy, PartialEq)]
 pub struct Dielectric{
     index_of_refraction :f64,
@@ -25,31 +38,81 @@ pub struct DiffuseLights{
     color: Color
 }
 
+//Scattering inside a participating medium, where there is no surface and
+//the normal of a hit means nothing. The phase function gives the spread of
+//the scattered directions about the incoming one: g = 0 scatters evenly,
+//and other values use Henyey-Greenstein, forwards for g > 0 and backwards
+//for g < 0.
+#[derive(Default, Clone, PartialEq)]
+pub struct Isotropic{
+    albedo: Texture,
+    g: f64
+}
+
 
-#[derive(Clone, Copy, PartialEq)]
+#[derive(Clone, PartialEq)]
 pub enum Material{
     Lambertian(Lambertian),
     Metal(Metal),
+    Conductor(Conductor),
     Dielectric(Dielectric),
-    DiffuseLights(DiffuseLights)
+    DiffuseLights(DiffuseLights),
+    Isotropic(Isotropic)
 }
 
 impl Scatter for Material {
-    fn scatter(&self, r : &Ray, rec: &HitRecord) -> Option<(Color, Ray)> {
-        match *self {
-            Material::Lambertian(material) => material.scatter(r, rec),
-            Material::Metal(material) => material.scatter(r, rec),
-            Material::Dielectric(material) => material.scatter(r, rec),
-            Material::DiffuseLights(material) => material.scatter(r, rec)
+    fn scatter(&self, r : &Ray, rec: &HitRecord, rng: &mut Rng) -> Option<(Color, Ray)> {
+        match self {
+            Material::Lambertian(material) => material.scatter(r, rec, rng),
+            Material::Metal(material) => material.scatter(r, rec, rng),
+            Material::Conductor(material) => material.scatter(r, rec, rng),
+            Material::Dielectric(material) => material.scatter(r, rec, rng),
+            Material::DiffuseLights(material) => material.scatter(r, rec, rng),
+            Material::Isotropic(material) => material.scatter(r, rec, rng)
         }
     }
 
     fn emit(&self) -> Color {
-        match *self {
+        match self {
             Material::Lambertian(material) => material.emit(),
             Material::Metal(material) => material.emit(),
+            Material::Conductor(material) => material.emit(),
             Material::Dielectric(material) => material.emit(),
-            Material::DiffuseLights(material) => material.emit()
+            Material::DiffuseLights(material) => material.emit(),
+            Material::Isotropic(material) => material.emit()
+        }
+    }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> Color {
+        match self {
+            Material::Lambertian(material) => material.eval(r_in, rec, scattered),
+            Material::Metal(material) => material.eval(r_in, rec, scattered),
+            Material::Conductor(material) => material.eval(r_in, rec, scattered),
+            Material::Dielectric(material) => material.eval(r_in, rec, scattered),
+            Material::DiffuseLights(material) => material.eval(r_in, rec, scattered),
+            Material::Isotropic(material) => material.eval(r_in, rec, scattered)
+        }
+    }
+
+    fn scattering_pdf(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> f64 {
+        match self {
+            Material::Lambertian(material) => material.scattering_pdf(r_in, rec, scattered),
+            Material::Metal(material) => material.scattering_pdf(r_in, rec, scattered),
+            Material::Conductor(material) => material.scattering_pdf(r_in, rec, scattered),
+            Material::Dielectric(material) => material.scattering_pdf(r_in, rec, scattered),
+            Material::DiffuseLights(material) => material.scattering_pdf(r_in, rec, scattered),
+            Material::Isotropic(material) => material.scattering_pdf(r_in, rec, scattered)
+        }
+    }
+
+    fn is_specular(&self) -> bool {
+        match self {
+            Material::Lambertian(material) => material.is_specular(),
+            Material::Metal(material) => material.is_specular(),
+            Material::Conductor(material) => material.is_specular(),
+            Material::Dielectric(material) => material.is_specular(),
+            Material::DiffuseLights(material) => material.is_specular(),
+            Material::Isotropic(material) => material.is_specular()
         }
     }
 }
@@ -59,10 +122,26 @@ impl Material {
         Material::Lambertian(Lambertian::new(alb))
     }
 
+    pub fn new_textured_lambertian(albedo: Texture) -> Material {
+        Material::Lambertian(Lambertian::new_textured(albedo))
+    }
+
     pub fn new_metal(alb:Vec3, mut fuzz: f64) -> Material {
         Material::Metal(Metal::new(alb, fuzz))
     }
 
+    pub fn new_textured_metal(albedo: Texture, fuzz: f64) -> Material {
+        Material::Metal(Metal::new_textured(albedo, fuzz))
+    }
+
+    pub fn new_conductor(alb: Color, roughness: f64) -> Material {
+        Material::Conductor(Conductor::new(alb, roughness))
+    }
+
+    pub fn new_textured_conductor(albedo: Texture, roughness_u: f64, roughness_v: f64) -> Material {
+        Material::Conductor(Conductor::new_anisotropic(albedo, roughness_u, roughness_v))
+    }
+
     pub fn new_dielectric(ir: f64) -> Material{
         Material::Dielectric(Dielectric::new(ir))
     }
@@ -70,11 +149,23 @@ impl Material {
     pub fn new_diffuse_light(color: Color) -> Material{
         Material::DiffuseLights(DiffuseLights::new(color))
     }
+
+    pub fn new_isotropic(albedo: Color) -> Material{
+        Material::Isotropic(Isotropic::new(Texture::Constant(albedo), 0.0))
+    }
+
+    pub fn new_henyey_greenstein(albedo: Texture, g: f64) -> Material{
+        Material::Isotropic(Isotropic::new(albedo, g))
+    }
 }
 
 impl Lambertian{
     pub fn new(alb: Color) -> Lambertian {
-        Lambertian{albedo: alb}
+        Lambertian{albedo: Texture::Constant(alb)}
+    }
+
+    pub fn new_textured(albedo: Texture) -> Lambertian {
+        Lambertian{albedo}
     }
 
     fn deterministic_scatter(&self, rec: &HitRecord, rand_unit_vec: Vec3) -> Option<(Color, Ray)>{
@@ -85,31 +176,53 @@ impl Lambertian{
             scatter_direction = rec.normal;
         }
         let scattered = Ray::new(rec.p, scatter_direction);
-        let attenuation = self.albedo;
+        let attenuation = self.albedo.value(rec.u, rec.v, rec.p);
         Some((attenuation, scattered))
     }
 }
 
 impl Scatter for Lambertian {
-    fn scatter(&self, _: &Ray, rec: &HitRecord) -> Option<(Color, Ray)>{
+    fn scatter(&self, _: &Ray, rec: &HitRecord, rng: &mut Rng) -> Option<(Color, Ray)>{
 
-        let reflect_dir = Vec3::rand_unit_vec();
+        let reflect_dir = Vec3::rand_unit_vec(rng);
         self.deterministic_scatter( rec, reflect_dir)
 
     }
+
+    fn eval(&self, _: &Ray, rec: &HitRecord, scattered: &Ray) -> Color{
+        let cosine = rec.normal.dot(scattered.direction().unit_vector());
+        if cosine <= 0.0{
+            return Color::new(0.0, 0.0, 0.0);
+        }
+        self.albedo.value(rec.u, rec.v, rec.p) * (cosine / PI)
+    }
+
+    //normal + a random unit vector is cosine weighted about the normal
+    fn scattering_pdf(&self, _: &Ray, rec: &HitRecord, scattered: &Ray) -> f64{
+        let cosine = rec.normal.dot(scattered.direction().unit_vector());
+        (cosine / PI).max(0.0)
+    }
+
+    fn is_specular(&self) -> bool{
+        false
+    }
 }
 
 impl Metal {
-    pub fn new(alb:Vec3, mut fuzz: f64) -> Metal {
+    pub fn new(alb:Vec3, fuzz: f64) -> Metal {
+        Metal::new_textured(Texture::Constant(alb), fuzz)
+    }
+
+    pub fn new_textured(albedo: Texture, mut fuzz: f64) -> Metal {
         if fuzz > 1.0 {fuzz = 1.0}
         else if fuzz < 0.0 {fuzz = 0.0}
-        Metal{albedo: alb, fuzz}
+        Metal{albedo, fuzz}
     }
 
     fn deterministic_scatter(&self, r_in: &Ray, rec: &HitRecord, rand_in_unit_sphere: Vec3) -> Option<(Color, Ray)>{
         let reflected = r_in.direction().unit_vector().reflect(rec.normal);
         let scattered = Ray::new(rec.p, reflected + self.fuzz*rand_in_unit_sphere);
-        let attenuation = self.albedo;
+        let attenuation = self.albedo.value(rec.u, rec.v, rec.p);
         if Vec3::dot(scattered.direction(), rec.normal) > 0.0{
             Some((attenuation, scattered))
         }else{
@@ -119,10 +232,162 @@ impl Metal {
 }
 
 impl Scatter for Metal {
-    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<(Color, Ray)>{
-        let fuzz_dir = Vec3::rand_in_unit_sphere();
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord, rng: &mut Rng) -> Option<(Color, Ray)>{
+        let fuzz_dir = Vec3::rand_in_unit_sphere(rng);
         self.deterministic_scatter(r_in, rec, fuzz_dir)
     }
+
+    //Fuzzy reflections are treated as specular too
+    fn is_specular(&self) -> bool{
+        true
+    }
+}
+
+//Below this alpha the distribution is too sharp to evaluate reliably
+const MIN_ALPHA: f64 = 1e-3;
+
+impl Conductor {
+    pub fn new(alb: Color, roughness: f64) -> Conductor {
+        Conductor::new_anisotropic(Texture::Constant(alb), roughness, roughness)
+    }
+
+    //Roughness is clamped to [0, 1] and squared to give alpha, so it reads
+    //roughly linearly. roughness_u runs along the first tangent of the
+    //shading frame and roughness_v along the second.
+    pub fn new_anisotropic(albedo: Texture, roughness_u: f64, roughness_v: f64) -> Conductor {
+        let alpha = |roughness: f64| roughness.clamp(0.0, 1.0).powi(2).max(MIN_ALPHA);
+        Conductor{albedo, alpha_x: alpha(roughness_u), alpha_y: alpha(roughness_v)}
+    }
+
+    //Density of microfacet normals h, given in the shading frame
+    fn distribution(&self, h: Vec3) -> f64 {
+        let (x, y) = (h.x() / self.alpha_x, h.y() / self.alpha_y);
+        let d = x*x + y*y + h.z()*h.z();
+        1.0 / (PI * self.alpha_x * self.alpha_y * d * d)
+    }
+
+    //Smith's lambda, the masking of microfacets seen from w
+    fn lambda(&self, w: Vec3) -> f64 {
+        let (x, y) = (self.alpha_x * w.x(), self.alpha_y * w.y());
+        let tan_squared = (x*x + y*y) / (w.z()*w.z());
+        0.5 * ((1.0 + tan_squared).sqrt() - 1.0)
+    }
+
+    //Schlick's approximation, with the albedo as the reflectance at normal incidence
+    fn fresnel(&self, rec: &HitRecord, cosine: f64) -> Color {
+        let f0 = self.albedo.value(rec.u, rec.v, rec.p);
+        f0 + (1.0 - f0) * (1.0 - cosine).max(0.0).powi(5)
+    }
+
+    //A microfacet normal chosen in proportion to how much of it is visible
+    //from wo, using Heitz's method with the two random numbers u1 and u2
+    fn sample_visible_normal(&self, wo: Vec3, u1: f64, u2: f64) -> Vec3 {
+        //Stretch to the hemisphere configuration of a unit roughness surface
+        let vh = Vec3::new(self.alpha_x * wo.x(), self.alpha_y * wo.y(), wo.z()).unit_vector();
+        let length_squared = vh.x()*vh.x() + vh.y()*vh.y();
+        let t1 = if length_squared > 0.0 {
+            Vec3::new(-vh.y(), vh.x(), 0.0) / length_squared.sqrt()
+        } else {
+            Vec3::new(1.0, 0.0, 0.0)
+        };
+        let t2 = vh.cross(t1);
+
+        //A point on the disk, squashed onto the part of it that is visible
+        let r = u1.sqrt();
+        let phi = 2.0 * PI * u2;
+        let p1 = r * phi.cos();
+        let s = 0.5 * (1.0 + vh.z());
+        let p2 = (1.0 - s) * (1.0 - p1*p1).sqrt() + s * r * phi.sin();
+        let nh = p1*t1 + p2*t2 + (1.0 - p1*p1 - p2*p2).max(0.0).sqrt()*vh;
+
+        //Unstretch
+        Vec3::new(self.alpha_x * nh.x(), self.alpha_y * nh.y(), nh.z().max(0.0)).unit_vector()
+    }
+
+    //The scattering directions of r_in and scattered in the shading frame of rec
+    fn local_directions(r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> (Vec3, Vec3) {
+        let frame = ShadingFrame::new(rec.normal);
+        (frame.to_local(-r_in.direction().unit_vector()), frame.to_local(scattered.direction().unit_vector()))
+    }
+
+    fn deterministic_scatter(&self, r_in: &Ray, rec: &HitRecord, u1: f64, u2: f64) -> Option<(Color, Ray)> {
+        let frame = ShadingFrame::new(rec.normal);
+        let wo = frame.to_local(-r_in.direction().unit_vector());
+        if wo.z() <= 0.0 {
+            return None;
+        }
+        let h = self.sample_visible_normal(wo, u1, u2);
+        let wi = (-wo).reflect(h);
+        if wi.z() <= 0.0 {
+            return None;
+        }
+
+        //eval over scattering_pdf, which leaves Fresnel times G2/G1
+        let lambda_o = self.lambda(wo);
+        let masking = (1.0 + lambda_o) / (1.0 + lambda_o + self.lambda(wi));
+        let attenuation = self.fresnel(rec, wi.dot(h)) * masking;
+        Some((attenuation, Ray::new(rec.p, frame.to_world(wi))))
+    }
+}
+
+impl Scatter for Conductor {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord, rng: &mut Rng) -> Option<(Color, Ray)>{
+        self.deterministic_scatter(r_in, rec, rng.f64(), rng.f64())
+    }
+
+    //F D G2 / (4 cos_o cos_i), times cos_i
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> Color{
+        let (wo, wi) = Conductor::local_directions(r_in, rec, scattered);
+        if wo.z() <= 0.0 || wi.z() <= 0.0 {
+            return Color::new(0.0, 0.0, 0.0);
+        }
+        let h = (wo + wi).unit_vector();
+        let g2 = 1.0 / (1.0 + self.lambda(wo) + self.lambda(wi));
+        self.fresnel(rec, wi.dot(h)) * (self.distribution(h) * g2 / (4.0 * wo.z()))
+    }
+
+    //The visible normal density G1 D (wo.h) / cos_o, over the 4 (wo.h) of the reflection
+    fn scattering_pdf(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> f64{
+        let (wo, wi) = Conductor::local_directions(r_in, rec, scattered);
+        if wo.z() <= 0.0 || wi.z() <= 0.0 {
+            return 0.0;
+        }
+        let h = (wo + wi).unit_vector();
+        let g1 = 1.0 / (1.0 + self.lambda(wo));
+        g1 * self.distribution(h) / (4.0 * wo.z())
+    }
+
+    fn is_specular(&self) -> bool{
+        false
+    }
+}
+
+//An orthonormal basis with the normal as z. The tangents only depend on the
+//normal, so anisotropic roughness is oriented consistently over a surface.
+struct ShadingFrame{
+    tangent: Vec3,
+    bitangent: Vec3,
+    normal: Vec3
+}
+
+impl ShadingFrame{
+    //Duff et al's branchless basis
+    fn new(normal: Vec3) -> ShadingFrame{
+        let sign = 1.0_f64.copysign(normal.z());
+        let a = -1.0 / (sign + normal.z());
+        let b = normal.x() * normal.y() * a;
+        let tangent = Vec3::new(1.0 + sign * normal.x() * normal.x() * a, sign * b, -sign * normal.x());
+        let bitangent = Vec3::new(b, sign + normal.y() * normal.y() * a, -normal.y());
+        ShadingFrame{tangent, bitangent, normal}
+    }
+
+    fn to_local(&self, v: Vec3) -> Vec3{
+        Vec3::new(v.dot(self.tangent), v.dot(self.bitangent), v.dot(self.normal))
+    }
+
+    fn to_world(&self, v: Vec3) -> Vec3{
+        v.x() * self.tangent + v.y() * self.bitangent + v.z() * self.normal
+    }
 }
 
 impl Dielectric {
@@ -161,10 +426,14 @@ impl Dielectric {
 }
 
 impl Scatter for Dielectric {
-    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<(Color, Ray)>{
-        let rand = rand_double(0.0, 1.0);
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord, rng: &mut Rng) -> Option<(Color, Ray)>{
+        let rand = rng.f64();
         self.deterministic_scatter(r_in, rec, rand)
     }
+
+    fn is_specular(&self) -> bool{
+        true
+    }
 }
 
 impl DiffuseLights{
@@ -174,7 +443,7 @@ impl DiffuseLights{
 }
 
 impl Scatter for DiffuseLights{
-    fn scatter(&self, _: &Ray, _: &HitRecord) -> Option<(Color, Ray)>{
+    fn scatter(&self, _: &Ray, _: &HitRecord, _: &mut Rng) -> Option<(Color, Ray)>{
         None
     }
 
@@ -182,13 +451,94 @@ impl Scatter for DiffuseLights{
         self.color
     }
 
+    fn is_specular(&self) -> bool{
+        false
+    }
+}
+
+//Below this |g| the phase function is treated as even
+const MIN_ASYMMETRY: f64 = 1e-3;
+
+impl Isotropic{
+    //g is clamped short of +-1, where all light would go one way
+    pub fn new(albedo: Texture, g: f64) -> Isotropic{
+        Isotropic{albedo, g: g.clamp(-0.99, 0.99)}
+    }
+
+    //Density, per unit solid angle, of scattering at an angle whose cosine
+    //is cos_theta from the direction of travel
+    fn phase(&self, cos_theta: f64) -> f64{
+        if self.g.abs() < MIN_ASYMMETRY{
+            return 1.0 / (4.0 * PI);
+        }
+        let g = self.g;
+        let denom = 1.0 + g*g - 2.0*g*cos_theta;
+        (1.0 - g*g) / (4.0 * PI * denom * denom.sqrt())
+    }
+
+    //Inverts the cumulative distribution of the phase function in
+    //cos_theta, so u = 0 scatters straight back and u = 1 straight on
+    fn sample_cos_theta(&self, u: f64) -> f64{
+        if self.g.abs() < MIN_ASYMMETRY{
+            return 2.0*u - 1.0;
+        }
+        let g = self.g;
+        let s = (1.0 - g*g) / (1.0 - g + 2.0*g*u);
+        ((1.0 + g*g - s*s) / (2.0*g)).clamp(-1.0, 1.0)
+    }
+
+    fn cos_theta(r_in: &Ray, scattered: &Ray) -> f64{
+        r_in.direction().unit_vector().dot(scattered.direction().unit_vector())
+    }
+}
+
+impl Scatter for Isotropic{
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord, rng: &mut Rng) -> Option<(Color, Ray)>{
+        let cos_theta = self.sample_cos_theta(rng.f64());
+        let sin_theta = (1.0 - cos_theta*cos_theta).max(0.0).sqrt();
+        let phi = 2.0 * PI * rng.f64();
+        let frame = ShadingFrame::new(r_in.direction().unit_vector());
+        let direction = frame.to_world(Vec3::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta));
+        Some((self.albedo.value(rec.u, rec.v, rec.p), Ray::new(rec.p, direction)))
+    }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> Color{
+        self.albedo.value(rec.u, rec.v, rec.p) * self.phase(Isotropic::cos_theta(r_in, scattered))
+    }
+
+    fn scattering_pdf(&self, r_in: &Ray, _: &HitRecord, scattered: &Ray) -> f64{
+        self.phase(Isotropic::cos_theta(r_in, scattered))
+    }
+
+    fn is_specular(&self) -> bool{
+        false
+    }
 }
 
 pub trait Scatter: Clone{
-    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<(Color, Ray)>;
+    //Chooses the direction light arriving along r_in leaves in, drawing any
+    //random numbers it needs from rng
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord, rng: &mut Rng) -> Option<(Color, Ray)>;
     fn emit(&self) -> Color{
         Color::new(0.0, 0.0, 0.0)
     }
+
+    //The BSDF times the cosine at the surface for light leaving along
+    //scattered. Used to weight light samples, so only needed by materials
+    //that are not specular.
+    fn eval(&self, _r_in: &Ray, _rec: &HitRecord, _scattered: &Ray) -> Color{
+        Color::new(0.0, 0.0, 0.0)
+    }
+
+    //Probability density, per unit solid angle, of scatter choosing the
+    //direction of scattered
+    fn scattering_pdf(&self, _r_in: &Ray, _rec: &HitRecord, _scattered: &Ray) -> f64{
+        0.0
+    }
+
+    //Specular materials scatter into a single direction, so lights can't
+    //be sampled from them
+    fn is_specular(&self) -> bool;
 }
 
 #[cfg(test)]
@@ -198,13 +548,14 @@ mod tests {
 
     #[test]
     fn test_lambertian_deterministic_scatter(){
+        let mut rng = Rng::new(0);
 
         //Initialisation
         let albedo = Color::new(0.7, 0.6, 0.5);
         let mat = Material::new_lambertian(albedo);
         let s = Primitive::new_sphere(Point3::new(1.0,0.0,0.0), 1.0, mat);
         let r = Ray::new(Point3::new(-10.0, -10.0, 0.0), Vec3::new( 1.0, 1.0, 0.0));
-        let hit = s.hit(&r, 0.0, 100.0);
+        let hit = s.hit(&r, 0.0, 100.0, &mut rng);
         let (rec, _) = hit.unwrap();
         
         //Case 1: Scatter direction is non-degenerate
@@ -234,13 +585,14 @@ mod tests {
 
     #[test]
     fn test_metal_deterministic_scatter(){
+        let mut rng = Rng::new(0);
 
         //Initialisation
         let albedo = Color::new(0.7, 0.6, 0.5);
         let mat = Material::new_metal(albedo, 20.0);
         let s = Primitive::new_sphere(Point3::new(1.0,0.0,0.0), 1.0, mat);
         let r = Ray::new(Point3::new(-10.0, -10.0, 0.0), Vec3::new( 1.0, 1.0, 0.0));
-        let hit = s.hit(&r, 0.0, 100.0);
+        let hit = s.hit(&r, 0.0, 100.0, &mut rng);
         let (rec, _) = hit.unwrap();
         
         //Case 1: Ray reflects
@@ -268,13 +620,14 @@ mod tests {
 
     #[test]
     fn test_diffuse_light_scatter(){
+        let mut rng = Rng::new(0);
         let mat = Material::new_diffuse_light(Color::new(0.7, 0.6, 0.5));
-        let s = Primitive::new_sphere(Point3::new(1.0,0.0,0.0), 1.0, mat);
+        let s = Primitive::new_sphere(Point3::new(1.0,0.0,0.0), 1.0, mat.clone());
         let r = Ray::new(Point3::new(-10.0, -10.0, 0.0), Vec3::new( 1.0, 1.0, 0.0));
-        let hit = s.hit(&r, 0.0, 100.0);
+        let hit = s.hit(&r, 0.0, 100.0, &mut rng);
         let (rec, _) = hit.unwrap();
 
-        let scatter_result = mat.scatter(&r, &rec);
+        let scatter_result = mat.scatter(&r, &rec, &mut Rng::new(0));
         assert!(scatter_result.is_none());
     }
 
@@ -285,6 +638,138 @@ mod tests {
         assert_eq!(emission, Color::new(0.7, 0.6, 0.5));
     }
 
+    #[test]
+    fn test_lambertian_eval_pdf(){
+        let mut rng = Rng::new(0);
+        let albedo = Color::new(0.7, 0.6, 0.5);
+        let mat = Material::new_lambertian(albedo);
+        let s = Primitive::new_sphere(Point3::new(1.0,0.0,0.0), 1.0, mat.clone());
+        let r = Ray::new(Point3::new(-10.0, 0.0, 0.0), Vec3::new( 1.0, 0.0, 0.0));
+        let (rec, _) = s.hit(&r, 0.0, 100.0, &mut rng).unwrap();
+
+        //Case 1: Along the normal
+        let scattered = Ray::new(rec.p, Vec3::new(-2.0, 0.0, 0.0));
+        assert_eq!(mat.scattering_pdf(&r, &rec, &scattered), 1.0/PI);
+        assert!((mat.eval(&r, &rec, &scattered) - albedo/PI).length() < 1e-12);
+
+        //Case 2: eval/pdf matches the attenuation given by scatter
+        let scattered = Ray::new(rec.p, Vec3::new(-1.0, 1.0, 0.5));
+        let ratio = mat.eval(&r, &rec, &scattered) / mat.scattering_pdf(&r, &rec, &scattered);
+        assert!((ratio - albedo).length() < 1e-12);
+
+        //Case 3: Below the surface
+        let scattered = Ray::new(rec.p, Vec3::new(1.0, 1.0, 0.0));
+        assert_eq!(mat.scattering_pdf(&r, &rec, &scattered), 0.0);
+        assert_eq!(mat.eval(&r, &rec, &scattered), Color::new(0.0, 0.0, 0.0));
+
+        assert!(!mat.is_specular());
+        assert!(Material::new_metal(albedo, 0.5).is_specular());
+        assert!(Material::new_dielectric(1.5).is_specular());
+    }
+
+    #[test]
+    fn test_isotropic(){
+        let albedo = Color::new(0.9, 0.8, 0.7);
+        let rec = HitRecord::new(Point3::new(0.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0), 1.0, Ray::default(), Vec3::default());
+        let r = Ray::new(Point3::new(0.0, 0.0, -1.0), Vec3::new(0.0, 0.0, 2.0));
+        let mut rng = Rng::new(0);
+
+        for g in [0.0, 0.7, -0.4]{
+            let mat = Material::new_henyey_greenstein(Texture::Constant(albedo), g);
+            assert!(!mat.is_specular());
+
+            //Case 1: The phase function integrates to one
+            let steps = 2000;
+            let total: f64 = (0..steps).map(|i| {
+                let cos_theta = -1.0 + 2.0 * (i as f64 + 0.5) / steps as f64;
+                let direction = Vec3::new((1.0 - cos_theta*cos_theta).sqrt(), 0.0, cos_theta);
+                mat.scattering_pdf(&r, &rec, &Ray::new(rec.p, direction)) * 2.0 * PI * 2.0 / steps as f64
+            }).sum();
+            assert!((total - 1.0).abs() < 1e-3, "g = {}: the phase function integrates to {}", g, total);
+
+            //Case 2: Scattered directions average to g along the ray and
+            //eval/pdf matches the attenuation
+            let mut mean_cosine = 0.0;
+            for _ in 0..20000{
+                let (attenuation, scattered) = mat.scatter(&r, &rec, &mut rng).unwrap();
+                assert_eq!(attenuation, albedo);
+                let ratio = mat.eval(&r, &rec, &scattered) / mat.scattering_pdf(&r, &rec, &scattered);
+                assert!((ratio - albedo).length() < 1e-9);
+                mean_cosine += scattered.direction().unit_vector().z() / 20000.0;
+            }
+            assert!((mean_cosine - g).abs() < 0.02, "g = {}: the mean cosine is {}", g, mean_cosine);
+        }
+    }
+
+    #[test]
+    fn test_conductor_eval_pdf(){
+        let mut rng = Rng::new(0);
+        let albedo = Color::new(0.9, 0.6, 0.3);
+        let mat = Material::new_textured_conductor(Texture::Constant(albedo), 0.5, 0.3);
+        let s = Primitive::new_sphere(Point3::new(1.0,0.0,0.0), 1.0, mat.clone());
+        let r = Ray::new(Point3::new(-10.0, 5.0, 0.0), Vec3::new( 10.0, -5.0, 0.0));
+        let (rec, _) = s.hit(&r, 0.0, 100.0, &mut rng).unwrap();
+        let conductor = match &mat {
+            Material::Conductor(conductor) => conductor,
+            _ => unreachable!()
+        };
+
+        //Case 1: The sampled weight is eval over pdf for the same direction
+        for i in 0..10{
+            for j in 0..10{
+                let (u1, u2) = ((i as f64 + 0.5)/10.0, (j as f64 + 0.5)/10.0);
+                if let Some((attenuation, scattered)) = conductor.deterministic_scatter(&r, &rec, u1, u2){
+                    assert!(scattered.direction().dot(rec.normal) > 0.0);
+                    let ratio = mat.eval(&r, &rec, &scattered) / mat.scattering_pdf(&r, &rec, &scattered);
+                    assert!((ratio - attenuation).length() < 1e-9);
+                }
+            }
+        }
+
+        //Case 2: The pdf integrates to at most one over the hemisphere, with
+        //little lost to reflections that end up below the surface
+        let frame = ShadingFrame::new(rec.normal);
+        let steps = 400;
+        let mut total = 0.0;
+        for i in 0..steps{
+            let theta = (i as f64 + 0.5) / steps as f64 * PI / 2.0;
+            for j in 0..steps{
+                let phi = (j as f64 + 0.5) / steps as f64 * 2.0 * PI;
+                let wi = Vec3::new(theta.sin() * phi.cos(), theta.sin() * phi.sin(), theta.cos());
+                let scattered = Ray::new(rec.p, frame.to_world(wi));
+                total += mat.scattering_pdf(&r, &rec, &scattered) * theta.sin();
+            }
+        }
+        total *= (PI / 2.0 / steps as f64) * (2.0 * PI / steps as f64);
+        assert!(total > 0.95 && total < 1.001, "pdf integrates to {}", total);
+
+        //Case 3: Below the surface
+        let scattered = Ray::new(rec.p, -rec.normal);
+        assert_eq!(mat.scattering_pdf(&r, &rec, &scattered), 0.0);
+        assert_eq!(mat.eval(&r, &rec, &scattered), Color::new(0.0, 0.0, 0.0));
+        assert!(!mat.is_specular());
+    }
+
+    #[test]
+    fn test_conductor_smooth_reflection(){
+        let mut rng = Rng::new(0);
+        //Nearly smooth surfaces reflect close to the mirror direction with
+        //the full albedo at normal incidence
+        let albedo = Color::new(0.9, 0.6, 0.3);
+        let mat = Conductor::new(albedo, 0.0);
+        let s = Primitive::new_sphere(Point3::new(1.0,0.0,0.0), 1.0, Material::Conductor(mat.clone()));
+        let r = Ray::new(Point3::new(-10.0, 0.0, 0.0), Vec3::new( 1.0, 0.0, 0.0));
+        let (rec, _) = s.hit(&r, 0.0, 100.0, &mut rng).unwrap();
+        let (attenuation, scattered) = mat.deterministic_scatter(&r, &rec, 0.3, 0.7).unwrap();
+        assert!((scattered.direction() - Vec3::new(-1.0, 0.0, 0.0)).length() < 1e-2);
+        assert!((attenuation - albedo).length() < 1e-3);
+
+        //The shading frame is orthonormal even facing straight down
+        let frame = ShadingFrame::new(Vec3::new(0.0, 0.0, -1.0));
+        assert!(frame.tangent.dot(frame.bitangent).abs() < 1e-12);
+        assert!((frame.tangent.cross(frame.bitangent) - frame.normal).length() < 1e-12);
+    }
+
     #[test]
     fn test_reflectance(){
         let unit_vec = Vec3::new(1.0, 2.0, 3.0).unit_vector();
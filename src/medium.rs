@@ -0,0 +1,448 @@
+use crate::vec::*;
+use crate::ray::*;
+use crate::traceable::*;
+use crate::bvh::*;
+use crate::material::*;
+use crate::light::*;
+use crate::rng::*;
+use crate::volume::*;
+
+use std::sync::Arc;
+
+//Smoke, mist or murky liquid of constant density filling the inside of a
+//closed boundary, which can be any watertight shape: a sphere, a box or a
+//mesh. A ray scatters inside it after a random distance, with the phase
+//function given by its material, so the thicker and denser the medium the
+//less is seen through it.
+#[derive (Clone)]
+pub struct ConstantMedium{
+    boundary: Arc<FlatBvh>,
+    density: f64,
+    phase_function: Material,
+}
+
+//A medium of constant density filling the scene below height, which can be
+//infinite. Rays that leave through the top see the background dimmed by the
+//fog they passed through.
+#[derive (Clone)]
+pub struct Fog{
+    density: f64,
+    height: f64,
+    phase_function: Material,
+}
+
+//A medium whose density changes from place to place, following a voxel
+//grid or procedural noise and scaled by density. Where a ray scatters is
+//found by delta tracking: it takes steps as if the medium were everywhere
+//as dense as its densest point, and at each one really collides with
+//probability equal to the local density over that maximum. A collision is
+//an absorption with probability absorption, and otherwise scatters with
+//the phase function. Absorbed rays end, giving off the emission color
+//scaled by the local temperature over its maximum, or by the density if
+//there is no temperature field, which makes fire.
+#[derive (Clone)]
+pub struct HeterogeneousMedium{
+    density: DensityField,
+    scale: f64,
+    phase_function: Material,
+    absorption: f64,
+    temperature: Option<DensityField>,
+    emitter: Material,
+    absorber: Material,
+}
+
+impl ConstantMedium{
+    pub fn new(boundary: Arc<FlatBvh>, density: f64, phase_function: Material) -> ConstantMedium{
+        ConstantMedium{boundary, density, phase_function}
+    }
+}
+
+impl Hit for ConstantMedium{
+    //Walks along the ray from boundary crossing to boundary crossing, using
+    //up the distance it travels before scattering on the parts that are
+    //inside. Crossings the ray leaves through face away from it, which also
+    //tells a ray that starts inside the medium where it is.
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rng: &mut Rng) -> Option<(HitRecord, &Material)> {
+        let ray_length = r.direction().length();
+        let mut remaining = free_path(self.density, rng);
+        let mut t = t_min;
+        while t < t_max{
+            let (crossing, _) = self.boundary.hit(r, t, f64::INFINITY, rng)?;
+            if !crossing.front_face{
+                let inside = (crossing.t.min(t_max) - t) * ray_length;
+                if remaining < inside{
+                    let t = t + remaining / ray_length;
+                    return Some((scattering_record(r, t), &self.phase_function));
+                }
+                remaining -= inside;
+            }
+            t = crossing.t + 0.0001;
+        }
+        None
+    }
+
+    fn bounding_box(&self) -> Option<Aabb>{
+        self.boundary.bounding_box()
+    }
+}
+
+//Media are not sampled as lights
+impl Surface for ConstantMedium{
+    fn area(&self) -> f64{
+        0.0
+    }
+
+    fn sample_surface(&self, _: &mut Rng) -> (Point3, Vec3){
+        (Point3::default(), Vec3::default())
+    }
+
+    fn material(&self) -> &Material{
+        &self.phase_function
+    }
+}
+
+impl HeterogeneousMedium{
+    pub fn new(density: DensityField, scale: f64, phase_function: Material) -> HeterogeneousMedium{
+        let black = Material::new_diffuse_light(Color::default());
+        HeterogeneousMedium{density, scale: scale.max(0.0), phase_function, absorption: 0.0, temperature: None, emitter: black.clone(), absorber: black}
+    }
+
+    //Makes the medium absorb that fraction of the collisions in it, giving
+    //off up to emission where they happen
+    pub fn with_emission(mut self, absorption: f64, emission: Color, temperature: Option<DensityField>) -> HeterogeneousMedium{
+        self.absorption = absorption.clamp(0.0, 1.0);
+        self.emitter = Material::new_diffuse_light(emission);
+        self.temperature = temperature;
+        self
+    }
+
+    //How brightly an absorbed ray glows at p, from 0 to 1
+    fn glow(&self, p: Point3) -> f64{
+        let field = self.temperature.as_ref().unwrap_or(&self.density);
+        if field.max_value() > 0.0 {field.value(p) / field.max_value()} else {0.0}
+    }
+}
+
+impl Hit for HeterogeneousMedium{
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rng: &mut Rng) -> Option<(HitRecord, &Material)> {
+        let majorant = self.scale * self.density.max_value();
+        if majorant <= 0.0{
+            return None;
+        }
+        let (mut t, t_end) = self.density.bounding_box().span(r, t_min, t_max)?;
+
+        let ray_length = r.direction().length();
+        loop{
+            t += free_path(majorant, rng) / ray_length;
+            if t >= t_end{
+                return None;
+            }
+            let p = r.at(t);
+            if rng.f64() * majorant >= self.scale * self.density.value(p){
+                continue;
+            }
+            let rec = scattering_record(r, t);
+            if rng.f64() >= self.absorption{
+                return Some((rec, &self.phase_function));
+            }
+            //Glowing in proportion to the temperature is the same on
+            //average as glowing fully that often
+            if rng.f64() < self.glow(p){
+                return Some((rec, &self.emitter));
+            }
+            return Some((rec, &self.absorber));
+        }
+    }
+
+    fn bounding_box(&self) -> Option<Aabb>{
+        Some(self.density.bounding_box())
+    }
+}
+
+impl Surface for HeterogeneousMedium{
+    fn area(&self) -> f64{
+        0.0
+    }
+
+    fn sample_surface(&self, _: &mut Rng) -> (Point3, Vec3){
+        (Point3::default(), Vec3::default())
+    }
+
+    fn material(&self) -> &Material{
+        &self.phase_function
+    }
+}
+
+impl Fog{
+    pub fn new(density: f64, height: f64, phase_function: Material) -> Fog{
+        Fog{density: density.max(0.0), height, phase_function}
+    }
+
+    pub fn phase_function(&self) -> &Material{
+        &self.phase_function
+    }
+
+    //Where r scatters in the fog before reaching t_max, if it does, as a hit
+    //with the fog's phase function
+    pub fn hit(&self, r: &Ray, t_max: f64, rng: &mut Rng) -> Option<(HitRecord, &Material)>{
+        let (start, end) = self.span(r, t_max)?;
+        let t = start + free_path(self.density, rng) / r.direction().length();
+        if t < end{
+            Some((scattering_record(r, t), &self.phase_function))
+        } else{
+            None
+        }
+    }
+
+    //The fraction of light getting through the fog along r up to t_max
+    pub fn transmittance(&self, r: &Ray, t_max: f64) -> f64{
+        match self.span(r, t_max){
+            Some((start, end)) if end.is_finite() => (-self.density * (end - start) * r.direction().length()).exp(),
+            Some(_) => 0.0,
+            None => 1.0,
+        }
+    }
+
+    //The part of r between 0 and t_max that is in the fog
+    fn span(&self, r: &Ray, t_max: f64) -> Option<(f64, f64)>{
+        if self.density <= 0.0{
+            return None;
+        }
+        let (start, end) = if r.direction().y() == 0.0{
+            if r.origin().y() >= self.height {return None;}
+            (0.0, t_max)
+        } else{
+            let crossing = (self.height - r.origin().y()) / r.direction().y();
+            if r.direction().y() > 0.0 {(0.0, crossing.min(t_max))} else {(crossing.max(0.0), t_max)}
+        };
+        if start < end {Some((start, end))} else {None}
+    }
+}
+
+//The distance light travels through a medium before scattering, which is
+//exponentially distributed with the density as its rate
+fn free_path(density: f64, rng: &mut Rng) -> f64{
+    -(1.0 - rng.f64()).ln() / density
+}
+
+//A scattering event at t along r. There is no surface there, so the normal
+//just faces back along the ray.
+fn scattering_record(r: &Ray, t: f64) -> HitRecord{
+    let mut rec = HitRecord::new(r.at(t), -r.direction().unit_vector(), t, *r, Vec3::default());
+    rec.light_sampled = false;
+    rec
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::primitive::*;
+    use crate::transform::*;
+
+    fn unit_sphere() -> Arc<FlatBvh>{
+        let mut list = TraceableList::new();
+        list.add(Primitive::new_sphere(Point3::new(0.0, 0.0, 0.0), 1.0, Material::new_lambertian(Color::new(0.5, 0.5, 0.5))));
+        Arc::new(FlatBvh::new(list))
+    }
+
+    //A cube from -1 to 1 made of triangles, placed as the scene file places
+    //mesh boundaries: as an instance, here scaled up from a smaller cube
+    fn cube_mesh() -> Arc<FlatBvh>{
+        let mat = Material::new_lambertian(Color::new(0.5, 0.5, 0.5));
+        let mut faces = TraceableList::new();
+        for axis in 0..3{
+            for &side in [-0.5, 0.5].iter(){
+                let corner = |a: f64, b: f64| {
+                    let mut p = Point3::default();
+                    p[axis] = side;
+                    p[(axis + 1) % 3] = a;
+                    p[(axis + 2) % 3] = b;
+                    p
+                };
+                let mut normal = Vec3::default();
+                normal[axis] = side.signum();
+                faces.add(Primitive::new_triangle([corner(-0.5, -0.5), corner(0.5, -0.5), corner(0.5, 0.5)], [normal; 3], mat.clone()));
+                faces.add(Primitive::new_triangle([corner(-0.5, -0.5), corner(0.5, 0.5), corner(-0.5, 0.5)], [normal; 3], mat.clone()));
+            }
+        }
+        let mut list = TraceableList::new();
+        list.add(Primitive::new_instance(Arc::new(FlatBvh::new(faces)), Transform::identity().scale(Vec3::new(2.0, 2.0, 2.0))));
+        Arc::new(FlatBvh::new(list))
+    }
+
+    //Rays from random points on the z = 5 plane, towards -z
+    fn rays(count: usize) -> impl Iterator<Item = Ray>{
+        let mut rng = Rng::new(1);
+        (0..count).map(move |_| Ray::new(Point3::new(0.1*rng.f64(), 0.1*rng.f64(), 5.0), Vec3::new(0.0, 0.0, -1.0)))
+    }
+
+    #[test]
+    fn test_constant_medium(){
+        let mut rng = Rng::new(0);
+        let medium = ConstantMedium::new(unit_sphere(), 0.5, Material::new_isotropic(Color::new(0.8, 0.8, 0.8)));
+        let bb = medium.bounding_box().unwrap();
+        assert_eq!((bb.min(), bb.max()), (Point3::new(-1.0, -1.0, -1.0), Point3::new(1.0, 1.0, 1.0)));
+
+        //Case 1: Rays through the middle get through about exp(-density * 2) of the time
+        //and otherwise scatter inside
+        let mut through = 0;
+        for r in rays(10000){
+            match medium.hit(&r, 0.001, 100.0, &mut rng){
+                Some((rec, mat)) => {
+                    assert!(rec.p.length() <= 1.0 && (4.0..=6.0).contains(&rec.t));
+                    assert!(rec.front_face);
+                    assert!(matches!(mat, Material::Isotropic(_)));
+                }
+                None => through += 1,
+            }
+        }
+        assert!((through as f64 / 10000.0 - (-1.0f64).exp()).abs() < 0.02);
+
+        //Case 2: The same ray draws a new distance from the generator each time it is
+        //traced, and the same generator state always gives the same distance
+        let r = Ray::new(Point3::new(0.0, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0));
+        let outcomes: Vec<Option<f64>> = (0..100).map(|_| medium.hit(&r, 0.001, 100.0, &mut rng).map(|(rec, _)| rec.t)).collect();
+        let mut scattered: Vec<f64> = outcomes.iter().flatten().copied().collect();
+        scattered.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        scattered.dedup();
+        assert!(outcomes.contains(&None) && scattered.len() > 10);
+        assert_eq!(medium.hit(&r, 0.001, 100.0, &mut Rng::new(5)).map(|(rec, _)| rec.t), medium.hit(&r, 0.001, 100.0, &mut Rng::new(5)).map(|(rec, _)| rec.t));
+
+        //Case 3: Surfaces in front of the medium hide it
+        assert!(rays(100).all(|r| medium.hit(&r, 0.001, 3.9, &mut rng).is_none()));
+
+        //Case 4: A ray starting inside only travels the rest of the way out
+        let dense = ConstantMedium::new(unit_sphere(), 1000.0, Material::new_isotropic(Color::new(0.8, 0.8, 0.8)));
+        let (rec, _) = dense.hit(&Ray::new(Point3::new(0.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0)), 0.001, 100.0, &mut rng).unwrap();
+        assert!(rec.t < 0.1);
+    }
+
+    #[test]
+    fn test_mesh_boundary(){
+        let mut rng = Rng::new(0);
+        let medium = ConstantMedium::new(cube_mesh(), 0.5, Material::new_isotropic(Color::new(0.8, 0.8, 0.8)));
+
+        //Rays three times unit length cross the cube between t = 4/3 and
+        //t = 2, and get through two units of medium about exp(-density * 2)
+        //of the time
+        let mut through = 0;
+        for r in rays(10000){
+            let r = Ray::new(r.origin(), 3.0 * r.direction());
+            match medium.hit(&r, 0.001, 100.0, &mut rng){
+                Some((rec, _)) => {
+                    assert!((4.0/3.0..=2.0).contains(&rec.t));
+                    assert!((-1.0..=1.0).contains(&rec.p.z()));
+                }
+                None => through += 1,
+            }
+        }
+        assert!((through as f64 / 10000.0 - (-1.0f64).exp()).abs() < 0.02);
+    }
+
+    #[test]
+    fn test_hollow_boundary(){
+        let mut rng = Rng::new(0);
+        //Two nested spheres, with the medium in the shell between them
+        let mut list = TraceableList::new();
+        let mat = Material::new_lambertian(Color::new(0.5, 0.5, 0.5));
+        list.add(Primitive::new_sphere(Point3::new(0.0, 0.0, 0.0), 2.0, mat.clone()));
+        list.add(Primitive::new_sphere(Point3::new(0.0, 0.0, 0.0), -1.0, mat));
+        let medium = ConstantMedium::new(Arc::new(FlatBvh::new(list)), 0.5, Material::new_isotropic(Color::new(0.8, 0.8, 0.8)));
+
+        //Straight through the middle crosses two units of medium, and
+        //nothing scatters in the hole
+        let mut through = 0;
+        for r in rays(10000){
+            match medium.hit(&r, 0.001, 100.0, &mut rng){
+                Some((rec, _)) => assert!(rec.p.length() >= 0.99),
+                None => through += 1,
+            }
+        }
+        assert!((through as f64 / 10000.0 - (-1.0f64).exp()).abs() < 0.02);
+    }
+
+    #[test]
+    fn test_heterogeneous_medium(){
+        let mut rng = Rng::new(0);
+        //A grid that is empty in its left half and has density 2 in its right half
+        let bb = Aabb::new(Point3::new(-1.0, -1.0, -1.0), Point3::new(1.0, 1.0, 1.0));
+        let grid = VoxelGrid::new([4, 1, 1], vec![0.0, 0.0, 2.0, 2.0], bb);
+        let medium = HeterogeneousMedium::new(DensityField::new_grid(grid), 0.5, Material::new_isotropic(Color::new(0.8, 0.8, 0.8)));
+        let bb = medium.bounding_box().unwrap();
+        assert_eq!((bb.min(), bb.max()), (Point3::new(-1.0, -1.0, -1.0), Point3::new(1.0, 1.0, 1.0)));
+
+        //Case 1: Along x the ray crosses half a unit that ramps up to density 2, then
+        //three quarters of a unit at density 2, for an optical depth of 0.5 * 2
+        let rays_along_x = |count: usize| {
+            let mut rng = Rng::new(3);
+            (0..count).map(move |_| Ray::new(Point3::new(-5.0, 0.1*rng.f64(), 0.1*rng.f64()), Vec3::new(1.0, 0.0, 0.0)))
+        };
+        let mut through = 0;
+        for r in rays_along_x(10000){
+            match medium.hit(&r, 0.001, 100.0, &mut rng){
+                Some((rec, mat)) => {
+                    assert!(rec.p.x() >= -0.25 && !rec.light_sampled);
+                    assert!(matches!(mat, Material::Isotropic(_)));
+                }
+                None => through += 1,
+            }
+        }
+        assert!((through as f64 / 10000.0 - (-1.0f64).exp()).abs() < 0.02);
+
+        //Case 2: Nothing in the empty half
+        let r = Ray::new(Point3::new(-0.75, 5.0, 0.0), Vec3::new(0.0, -1.0, 0.0));
+        assert!(medium.hit(&r, 0.001, 100.0, &mut rng).is_none());
+
+        //Case 3: With every collision absorbed, rays glow as often as the
+        //temperature is high where they stop
+        let temperature = VoxelGrid::new([1, 1, 1], vec![1.0], Aabb::new(Point3::new(-1.0, -1.0, -1.0), Point3::new(1.0, 1.0, 1.0)));
+        let fire = HeterogeneousMedium::new(DensityField::new_noise(bb, 1.0), 50.0, Material::new_isotropic(Color::new(0.8, 0.8, 0.8)))
+            .with_emission(1.0, Color::new(4.0, 2.0, 1.0), Some(DensityField::new_grid(temperature)));
+        let mut glowing = 0;
+        let mut stopped = 0;
+        for r in rays(1000){
+            if let Some((rec, mat)) = fire.hit(&r, 0.001, 100.0, &mut rng){
+                stopped += 1;
+                if mat.emit() == Color::new(4.0, 2.0, 1.0){
+                    glowing += 1;
+                }
+                assert!(mat.scatter(&r, &rec, &mut Rng::new(0)).is_none());
+            }
+        }
+        assert!(stopped > 500);
+        assert_eq!(glowing, stopped);
+    }
+
+    #[test]
+    fn test_fog(){
+        let fog = Fog::new(0.25, 1.0, Material::new_isotropic(Color::new(1.0, 1.0, 1.0)));
+        let mut rng = Rng::new(2);
+
+        //Case 1: Level below the top
+        let r = Ray::new(Point3::new(0.0, 0.0, 0.0), Vec3::new(2.0, 0.0, 0.0));
+        assert!((fog.transmittance(&r, 2.0) - (-1.0f64).exp()).abs() < 1e-12);
+        let scattered = (0..10000).filter(|_| fog.hit(&r, 2.0, &mut rng).is_some()).count();
+        assert!((scattered as f64 / 10000.0 - (1.0 - (-1.0f64).exp())).abs() < 0.02);
+
+        //Case 2: Upwards only the part below the top counts, so the sky is seen
+        let r = Ray::new(Point3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0));
+        assert!((fog.transmittance(&r, f64::INFINITY) - (-0.25f64).exp()).abs() < 1e-12);
+        assert!((0..100).filter_map(|_| fog.hit(&r, f64::INFINITY, &mut rng)).all(|(rec, _)| rec.t < 1.0));
+
+        //Case 3: Above the fog looking along or away from it
+        let r = Ray::new(Point3::new(0.0, 2.0, 0.0), Vec3::new(1.0, 0.0, 0.0));
+        assert_eq!(fog.transmittance(&r, f64::INFINITY), 1.0);
+        assert!(fog.hit(&r, f64::INFINITY, &mut rng).is_none());
+
+        //Case 4: Downwards into endless fog nothing gets through
+        let r = Ray::new(Point3::new(0.0, 2.0, 0.0), Vec3::new(0.0, -1.0, 0.0));
+        assert_eq!(fog.transmittance(&r, f64::INFINITY), 0.0);
+        assert!(fog.hit(&r, f64::INFINITY, &mut rng).unwrap().0.t >= 1.0);
+
+        //Case 5: Only the fog in front of a mesh dims it, whatever the length of the ray
+        let r = Ray::new(Point3::new(-5.0, 0.0, 0.0), Vec3::new(3.0, 0.0, 0.0));
+        let (rec, _) = cube_mesh().hit(&r, 0.001, f64::INFINITY, &mut rng).unwrap();
+        assert!((rec.t - 4.0/3.0).abs() < 1e-9);
+        assert!((fog.transmittance(&r, rec.t) - (-1.0f64).exp()).abs() < 1e-12);
+    }
+}
@@ -0,0 +1,255 @@
+use crate::render::*;
+
+use image::codecs::pnm::{PnmEncoder, PNMSubtype, SampleEncoding};
+use image::error::{EncodingError, ImageError, ImageFormatHint};
+use image::{ColorType, ImageBuffer, ImageResult, Rgb};
+
+use std::fs::File;
+use std::fs::OpenOptions;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+#[derive (Copy, Clone, Debug, PartialEq)]
+pub enum OutputFormat {
+    Png,
+    Png16,
+    Ppm,
+    PpmAscii,
+    Pfm,
+    Exr,
+}
+
+impl OutputFormat {
+    pub const NAMES: [&'static str; 6] = ["png", "png16", "ppm", "ppm-ascii", "pfm", "exr"];
+
+    //The format implied by a file extension. Formats that share an
+    //extension with another (png16, ppm-ascii) must be asked for by name.
+    pub fn from_path(path: &str) -> Option<OutputFormat> {
+        let extension = Path::new(path).extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "png" => Some(OutputFormat::Png),
+            "ppm" | "pnm" => Some(OutputFormat::Ppm),
+            "pfm" => Some(OutputFormat::Pfm),
+            "exr" => Some(OutputFormat::Exr),
+            _ => None
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<OutputFormat> {
+        match name {
+            "png" => Some(OutputFormat::Png),
+            "png16" => Some(OutputFormat::Png16),
+            "ppm" => Some(OutputFormat::Ppm),
+            "ppm-ascii" => Some(OutputFormat::PpmAscii),
+            "pfm" => Some(OutputFormat::Pfm),
+            "exr" => Some(OutputFormat::Exr),
+            _ => None
+        }
+    }
+}
+
+pub fn write_image(framebuffer: &Framebuffer, path: &str, format: OutputFormat) -> ImageResult<()> {
+    let width = framebuffer.image_width as u32;
+    let height = framebuffer.image_height as u32;
+
+    match format {
+        OutputFormat::Png => {
+            let data = framebuffer.to_rgb8();
+            let image: ImageBuffer<Rgb<u8>, Vec<u8>> = ImageBuffer::from_raw(width, height, data).expect("The framebuffer size does not match its dimensions");
+            image.save_with_format(path, image::ImageFormat::Png)
+        }
+        OutputFormat::Png16 => {
+            let data = framebuffer.pixel_colors.iter()
+                                               .zip(framebuffer.pixel_samples.iter())
+                                               .flat_map(|(pixel, &samples)| pixel.to_rgb16(samples))
+                                               .collect();
+            let image: ImageBuffer<Rgb<u16>, Vec<u16>> = ImageBuffer::from_raw(width, height, data).expect("The framebuffer size does not match its dimensions");
+            image.save_with_format(path, image::ImageFormat::Png)
+        }
+        OutputFormat::Ppm => {
+            let data = framebuffer.to_rgb8();
+            let mut writer = BufWriter::new(File::create(path)?);
+            PnmEncoder::new(&mut writer)
+                .with_subtype(PNMSubtype::Pixmap(SampleEncoding::Binary))
+                .encode(&data[..], width, height, ColorType::Rgb8)?;
+            writer.flush()?;
+            Ok(())
+        }
+        OutputFormat::PpmAscii => {
+            let mut writer = BufWriter::new(initialise_file(path, framebuffer.image_width, framebuffer.image_height)?);
+            for (pixel, &samples) in framebuffer.pixel_colors.iter().zip(framebuffer.pixel_samples.iter()) {
+                pixel.write_color(&mut writer, samples);
+            }
+            writer.flush()?;
+            Ok(())
+        }
+        OutputFormat::Pfm => {
+            write_pfm(framebuffer, path)?;
+            Ok(())
+        }
+        OutputFormat::Exr => {
+            exr::prelude::write_rgb_file(path, width as usize, height as usize, |i, j| {
+                let pixel = framebuffer.pixel(i as i32, j as i32);
+                (pixel.x() as f32, pixel.y() as f32, pixel.z() as f32)
+            }).map_err(|err| ImageError::Encoding(EncodingError::new(ImageFormatHint::Name("OpenEXR".to_string()), err)))
+        }
+    }
+}
+
+//Writes the averaged linear radiance without clamping or gamma correction.
+//PFM stores little-endian f32 rows from the bottom of the image up.
+pub fn write_pfm(framebuffer: &Framebuffer, path: &str) -> std::io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    write!(writer, "PF\n{} {}\n-1.0\n", framebuffer.image_width, framebuffer.image_height)?;
+    for j in (0..framebuffer.image_height).rev() {
+        for i in 0..framebuffer.image_width {
+            let pixel = framebuffer.pixel(i, j);
+            for value in [pixel.x(), pixel.y(), pixel.z()].iter() {
+                writer.write_all(&(*value as f32).to_le_bytes())?;
+            }
+        }
+    }
+    writer.flush()
+}
+
+//Writes a PNG showing how many samples each pixel took, running from black
+//for none through blue, red and yellow to white for samples_per_pixel.
+//Useful for tuning the adaptive sampling threshold.
+pub fn write_sample_heatmap(framebuffer: &Framebuffer, path: &str) -> ImageResult<()> {
+    const STOPS: [[f64; 3]; 5] = [[0.0, 0.0, 0.0], [0.1, 0.1, 0.8], [0.9, 0.1, 0.1], [1.0, 0.9, 0.0], [1.0, 1.0, 1.0]];
+    let most = framebuffer.samples_per_pixel.max(1) as f64;
+    let data = framebuffer.pixel_samples.iter().flat_map(|&samples| {
+        let position = (samples as f64 / most).clamp(0.0, 1.0) * (STOPS.len() - 1) as f64;
+        let stop = (position as usize).min(STOPS.len() - 2);
+        let t = position - stop as f64;
+        let [low, high] = [STOPS[stop], STOPS[stop + 1]];
+        [0, 1, 2].map(|c| (255.0 * (low[c] + t*(high[c] - low[c]))).round() as u8)
+    }).collect();
+    let image: ImageBuffer<Rgb<u8>, Vec<u8>> = ImageBuffer::from_raw(framebuffer.image_width as u32, framebuffer.image_height as u32, data)
+        .expect("The framebuffer size does not match its dimensions");
+    image.save_with_format(path, image::ImageFormat::Png)
+}
+
+pub fn initialise_file(path: &str, image_width: i32, image_height: i32) -> std::io::Result<File>{
+    let mut file = OpenOptions::new()
+                                    .create(true)
+                                    .write(true)
+                                    .truncate(true)
+                                    .open(path)?;
+    write!(file, "P3\n{} {} \n255\n", image_width, image_height)?;
+    println!("{}",image_width*image_height);
+    Ok(file)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vec::*;
+
+    use std::env;
+    use std::fs;
+
+    fn test_framebuffer() -> Framebuffer {
+        let pixel_colors = vec![Color::new(0.0, 0.0, 0.0), Color::new(2.0, 0.5, 0.0),
+                                Color::new(8.0, 8.0, 8.0), Color::new(0.5, 0.5, 0.5)];
+        Framebuffer::new(2, 2, 2, pixel_colors, vec![0.0; 4], vec![2; 4])
+    }
+
+    #[test]
+    fn test_from_path(){
+        assert_eq!(OutputFormat::from_path("render.png"), Some(OutputFormat::Png));
+        assert_eq!(OutputFormat::from_path("render.PPM"), Some(OutputFormat::Ppm));
+        assert_eq!(OutputFormat::from_path("render.pfm"), Some(OutputFormat::Pfm));
+        assert_eq!(OutputFormat::from_path("render.exr"), Some(OutputFormat::Exr));
+        assert_eq!(OutputFormat::from_path("dir.png/render"), None);
+        assert_eq!(OutputFormat::from_path("render.jpg"), None);
+    }
+
+    #[test]
+    fn test_write_png(){
+        let path = env::temp_dir().join("ray_trace_test_write.png");
+        let path = path.to_str().unwrap();
+        let framebuffer = test_framebuffer();
+
+        write_image(&framebuffer, path, OutputFormat::Png).unwrap();
+        let image = image::open(path).unwrap().to_rgb8();
+        assert_eq!(image.dimensions(), (2, 2));
+        assert_eq!(image.get_pixel(1, 0).0, Color::new(2.0, 0.5, 0.0).to_rgb8(2));
+        assert_eq!(image.get_pixel(0, 1).0, [255, 255, 255]);
+
+        write_image(&framebuffer, path, OutputFormat::Png16).unwrap();
+        let image = image::open(path).unwrap().to_rgb16();
+        assert_eq!(image.get_pixel(1, 1).0, Color::new(0.5, 0.5, 0.5).to_rgb16(2));
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_write_ppm(){
+        let path = env::temp_dir().join("ray_trace_test_write.ppm");
+        let path = path.to_str().unwrap();
+        let framebuffer = test_framebuffer();
+
+        //Case 1: Binary
+        write_image(&framebuffer, path, OutputFormat::Ppm).unwrap();
+        let bytes = fs::read(path).unwrap();
+        assert!(bytes.starts_with(b"P6"));
+        assert_eq!(&bytes[bytes.len() - 12..], &[0, 0, 0, 255, 128, 0, 255, 255, 255, 128, 128, 128]);
+
+        //Case 2: ASCII
+        write_image(&framebuffer, path, OutputFormat::PpmAscii).unwrap();
+        let text = fs::read_to_string(path).unwrap();
+        assert_eq!(text, "P3\n2 2 \n255\n0 0 0\n255 128 0\n255 255 255\n128 128 128\n");
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_write_pfm(){
+        let path = env::temp_dir().join("ray_trace_test_write.pfm");
+        let path = path.to_str().unwrap();
+        let framebuffer = test_framebuffer();
+
+        write_image(&framebuffer, path, OutputFormat::Pfm).unwrap();
+        let bytes = fs::read(path).unwrap();
+        let header = b"PF\n2 2\n-1.0\n";
+        assert!(bytes.starts_with(header));
+        let values: Vec<f32> = bytes[header.len()..].chunks(4)
+                                                    .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+                                                    .collect();
+        //Bottom row first, averaged but not clamped
+        assert_eq!(values, vec![4.0, 4.0, 4.0, 0.25, 0.25, 0.25, 0.0, 0.0, 0.0, 1.0, 0.25, 0.0]);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_write_sample_heatmap(){
+        let path = env::temp_dir().join("ray_trace_test_heatmap.png");
+        let path = path.to_str().unwrap();
+        let mut framebuffer = test_framebuffer();
+        framebuffer.samples_per_pixel = 8;
+        framebuffer.pixel_samples = vec![0, 2, 4, 8];
+
+        write_sample_heatmap(&framebuffer, path).unwrap();
+        let image = image::open(path).unwrap().to_rgb8();
+        assert_eq!(image.get_pixel(0, 0).0, [0, 0, 0]);
+        assert_eq!(image.get_pixel(1, 0).0, [26, 26, 204]);
+        assert_eq!(image.get_pixel(0, 1).0, [230, 26, 26]);
+        assert_eq!(image.get_pixel(1, 1).0, [255, 255, 255]);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_write_exr(){
+        let path = env::temp_dir().join("ray_trace_test_write.exr");
+        let path = path.to_str().unwrap();
+        let framebuffer = test_framebuffer();
+
+        write_image(&framebuffer, path, OutputFormat::Exr).unwrap();
+        let image = exr::prelude::read_first_rgba_layer_from_file(path,
+            |resolution, _| vec![[0.0f32; 3]; resolution.width() * resolution.height()],
+            |pixels, position, (r, g, b, _): (f32, f32, f32, f32)| pixels[position.y() * 2 + position.x()] = [r, g, b]).unwrap();
+        let pixels = image.layer_data.channel_data.pixels;
+        assert_eq!(pixels[2], [4.0, 4.0, 4.0]);
+        assert_eq!(pixels[1], [1.0, 0.25, 0.0]);
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -1,22 +1,41 @@
 use crate::triangle::*;
 use crate::sphere::*;
 use crate::rect::*;
+use crate::quad::*;
+use crate::quadric::*;
 use crate::traceable::*;
 use crate::ray::*;
 use crate::material::*;
 use crate::vec::*;
 use crate::bvh::*;
 use crate::bounding_box::*;
+use crate::light::*;
+use crate::instance::*;
+use crate::medium::*;
+use crate::volume::*;
+use crate::transform::*;
+use crate::rng::*;
 use crate::enum_dispatch::*;
 
+use std::sync::Arc;
 
-#[enum_dispatch(Hit)]
-#[derive (Copy, Clone)]
+
+#[enum_dispatch(Hit, Surface)]
+#[derive (Clone)]
 pub enum Primitive {
     Triangle(Triangle),
     Sphere(Sphere),
+    MovingSphere(MovingSphere),
     Rect(Rect),
-    BoundingBox(BoundingBox)
+    Quad(Quad),
+    Cuboid(Cuboid),
+    Cylinder(Cylinder),
+    Disk(Disk),
+    Cone(Cone),
+    BoundingBox(BoundingBox),
+    Instance(Instance),
+    ConstantMedium(ConstantMedium),
+    HeterogeneousMedium(HeterogeneousMedium)
 }
 
 impl Primitive {
@@ -28,7 +47,53 @@ impl Primitive {
         Primitive::Sphere(Sphere::new(cen, rad, mat))
     }
 
+    pub fn new_moving_sphere(center0: Point3, center1: Point3, time0: f64, time1: f64, rad: f64, mat: Material) -> Primitive {
+        Primitive::MovingSphere(MovingSphere::new(center0, center1, time0, time1, rad, mat))
+    }
+
     pub fn new_rect(axes: RectAxes, axis1_min: f64, axis1_max: f64, axis2_min: f64, axis2_max: f64, k: f64, mat: Material) -> Primitive {
         Primitive::Rect(Rect::new(axes, axis1_min, axis1_max, axis2_min, axis2_max, k, mat))
     }
+
+    pub fn new_quad(q: Point3, u: Vec3, v: Vec3, mat: Material) -> Primitive {
+        Primitive::Quad(Quad::new(q, u, v, mat))
+    }
+
+    pub fn new_cuboid(a: Point3, b: Point3, mat: Material) -> Primitive {
+        Primitive::Cuboid(Cuboid::new(a, b, mat))
+    }
+
+    //Shapes around an axis start at base and extend along axis. Sweep is the
+    //angle around the axis, in radians, that is kept.
+    pub fn new_cylinder(base: Point3, axis: Vec3, radius: f64, height: f64, sweep: f64, capped: bool, mat: Material) -> Primitive {
+        Primitive::Cylinder(Cylinder::new(Frame::new(base, axis), radius, height, sweep, capped, mat))
+    }
+
+    pub fn new_disk(center: Point3, axis: Vec3, radius: f64, sweep: f64, mat: Material) -> Primitive {
+        Primitive::Disk(Disk::new(Frame::new(center, axis), radius, 0.0, sweep, mat))
+    }
+
+    pub fn new_annulus(center: Point3, axis: Vec3, inner_radius: f64, outer_radius: f64, sweep: f64, mat: Material) -> Primitive {
+        Primitive::Disk(Disk::new(Frame::new(center, axis), outer_radius, inner_radius, sweep, mat))
+    }
+
+    pub fn new_cone(base: Point3, axis: Vec3, radius: f64, height: f64, sweep: f64, mat: Material) -> Primitive {
+        Primitive::Cone(Cone::new(Frame::new(base, axis), radius, height, sweep, mat))
+    }
+
+    pub fn new_instance(object: Arc<FlatBvh>, transform: Transform) -> Primitive {
+        Primitive::Instance(Instance::new(object, transform))
+    }
+
+    pub fn new_constant_medium(boundary: Arc<FlatBvh>, density: f64, phase_function: Material) -> Primitive {
+        Primitive::ConstantMedium(ConstantMedium::new(boundary, density, phase_function))
+    }
+
+    pub fn new_heterogeneous_medium(density: DensityField, scale: f64, phase_function: Material) -> Primitive {
+        Primitive::HeterogeneousMedium(HeterogeneousMedium::new(density, scale, phase_function))
+    }
+
+    pub fn new_animated_instance(object: Arc<FlatBvh>, transform: AnimatedTransform) -> Primitive {
+        Primitive::Instance(Instance::new_animated(object, transform))
+    }
 }
@@ -0,0 +1,288 @@
+use crate::vec::*;
+use crate::ray::*;
+use crate::traceable::*;
+use crate::bvh::*;
+use crate::material::*;
+use crate::light::*;
+use crate::rng::*;
+
+//A parallelogram with a corner at q and sides u and v. The outward normal
+//is along u x v, and u and v run from 0 to 1 along the sides.
+#[derive (Clone)]
+pub struct Quad{
+    q: Point3,
+    u: Vec3,
+    v: Vec3,
+    normal: Vec3,
+    //The plane is normal.p = d
+    d: f64,
+    //(u x v) / |u x v|^2, which turns a point in the plane into its
+    //coordinates along u and v
+    w: Vec3,
+    mat: Material
+}
+
+//An axis-aligned box made of six quads facing outwards. The sides are
+//boxed to keep Primitive, and so every triangle of a mesh, small.
+#[derive (Clone)]
+pub struct Cuboid{
+    sides: Box<[Quad; 6]>,
+    bb: Aabb
+}
+
+impl Quad{
+    pub fn new(q: Point3, u: Vec3, v: Vec3, mat: Material) -> Quad{
+        let n = u.cross(v);
+        let normal = n.unit_vector();
+        Quad{q, u, v, normal, d: normal.dot(q), w: n / n.length_squared(), mat}
+    }
+
+    pub fn corner(&self) -> Point3{
+        self.q
+    }
+
+    pub fn outward_normal(&self) -> Vec3{
+        self.normal
+    }
+}
+
+impl Hit for Quad{
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, _: &mut Rng) -> Option<(HitRecord, &Material)> {
+        //Rays parallel to the plane miss it
+        let denom = self.normal.dot(r.direction());
+        if denom.abs() < 1e-8{
+            return None;
+        }
+        let t = (self.d - self.normal.dot(r.origin())) / denom;
+        if t < t_min || t > t_max{
+            return None;
+        }
+
+        let p = r.at(t);
+        let planar = p - self.q;
+        let alpha = self.w.dot(planar.cross(self.v));
+        let beta = self.w.dot(self.u.cross(planar));
+        if !(0.0..=1.0).contains(&alpha) || !(0.0..=1.0).contains(&beta){
+            return None;
+        }
+        let mut rec = HitRecord::new(p, self.normal, t, *r, Vec3::default());
+        rec.set_uv(alpha, beta);
+        Some((rec, &self.mat))
+    }
+
+    fn bounding_box(&self) -> Option<Aabb>{
+        let corners = [self.q, self.q + self.u, self.q + self.v, self.q + self.u + self.v];
+        let mut min = corners[0];
+        let mut max = corners[0];
+        for corner in corners.iter(){
+            min = Point3::new(min.x().min(corner.x()), min.y().min(corner.y()), min.z().min(corner.z()));
+            max = Point3::new(max.x().max(corner.x()), max.y().max(corner.y()), max.z().max(corner.z()));
+        }
+        Some(padded_box(min, max))
+    }
+}
+
+impl Surface for Quad{
+    fn area(&self) -> f64{
+        self.u.cross(self.v).length()
+    }
+
+    fn sample_surface(&self, rng: &mut Rng) -> (Point3, Vec3){
+        (self.q + rng.f64()*self.u + rng.f64()*self.v, self.normal)
+    }
+
+    fn material(&self) -> &Material{
+        &self.mat
+    }
+}
+
+impl Cuboid{
+    //The box between the opposite corners a and b
+    pub fn new(a: Point3, b: Point3, mat: Material) -> Cuboid{
+        let min = Point3::new(a.x().min(b.x()), a.y().min(b.y()), a.z().min(b.z()));
+        let max = Point3::new(a.x().max(b.x()), a.y().max(b.y()), a.z().max(b.z()));
+        let dx = Vec3::new(max.x() - min.x(), 0.0, 0.0);
+        let dy = Vec3::new(0.0, max.y() - min.y(), 0.0);
+        let dz = Vec3::new(0.0, 0.0, max.z() - min.z());
+
+        //Each pair of sides is ordered so that u x v points out of the box
+        let sides = Box::new([
+            Quad::new(Point3::new(min.x(), min.y(), max.z()), dx, dy, mat.clone()),
+            Quad::new(Point3::new(max.x(), min.y(), max.z()), -dz, dy, mat.clone()),
+            Quad::new(Point3::new(max.x(), min.y(), min.z()), -dx, dy, mat.clone()),
+            Quad::new(Point3::new(min.x(), min.y(), min.z()), dz, dy, mat.clone()),
+            Quad::new(Point3::new(min.x(), max.y(), max.z()), dx, -dz, mat.clone()),
+            Quad::new(Point3::new(min.x(), min.y(), min.z()), dx, dz, mat),
+        ]);
+        Cuboid{sides, bb: padded_box(min, max)}
+    }
+
+    pub fn sides(&self) -> &[Quad; 6]{
+        &self.sides
+    }
+}
+
+impl Hit for Cuboid{
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rng: &mut Rng) -> Option<(HitRecord, &Material)> {
+        let mut closest = None;
+        let mut closest_t = t_max;
+        for side in self.sides.iter(){
+            if let Some((rec, mat)) = side.hit(r, t_min, closest_t, rng){
+                closest_t = rec.t;
+                closest = Some((rec, mat));
+            }
+        }
+        closest
+    }
+
+    fn bounding_box(&self) -> Option<Aabb>{
+        Some(self.bb)
+    }
+}
+
+impl Surface for Cuboid{
+    fn area(&self) -> f64{
+        self.sides.iter().map(|side| side.area()).sum()
+    }
+
+    //Sides are picked in proportion to their area
+    fn sample_surface(&self, rng: &mut Rng) -> (Point3, Vec3){
+        let mut target = rng.f64() * self.area();
+        for side in self.sides.iter(){
+            if target < side.area(){
+                return side.sample_surface(rng);
+            }
+            target -= side.area();
+        }
+        self.sides[5].sample_surface(rng)
+    }
+
+    fn material(&self) -> &Material{
+        self.sides[0].material()
+    }
+}
+
+//The bounding box must have a non-zero width in each dimension, so pad any
+//flat dimension a small amount
+fn padded_box(mut min: Point3, mut max: Point3) -> Aabb{
+    for axis in 0..3{
+        if max[axis] - min[axis] < 0.0002{
+            min[axis] -= 0.0001;
+            max[axis] += 0.0001;
+        }
+    }
+    Aabb::new(min, max)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn light() -> Material{
+        Material::new_diffuse_light(Color::new(4.0, 4.0, 4.0))
+    }
+
+    #[test]
+    fn test_quad_hit(){
+        let mut rng = Rng::new(0);
+        //A slanted quad in the plane x = z
+        let quad = Quad::new(Point3::new(0.0, 0.0, 0.0), Vec3::new(2.0, 0.0, 2.0), Vec3::new(0.0, 3.0, 0.0), light());
+
+        //Case 1: Collision, with the normal facing the ray and the surface coordinates along the sides
+        let r = Ray::new(Point3::new(1.0, 1.5, 5.0), Vec3::new(0.0, 0.0, -1.0));
+        let (rec, _) = quad.hit(&r, 0.0, 100.0, &mut rng).unwrap();
+        assert!((rec.t - 4.0).abs() < 1e-12);
+        assert!((rec.p - Point3::new(1.0, 1.5, 1.0)).length() < 1e-12);
+        assert!((rec.normal - Vec3::new(-1.0, 0.0, 1.0).unit_vector()).length() < 1e-12);
+        assert!(rec.front_face);
+        assert!((rec.u - 0.5).abs() < 1e-12 && (rec.v - 0.5).abs() < 1e-12);
+
+        //Case 2: From behind
+        let r = Ray::new(Point3::new(1.0, 1.5, -5.0), Vec3::new(0.0, 0.0, 1.0));
+        let (rec, _) = quad.hit(&r, 0.0, 100.0, &mut rng).unwrap();
+        assert!(!rec.front_face);
+        assert!((rec.normal - Vec3::new(1.0, 0.0, -1.0).unit_vector()).length() < 1e-12);
+
+        //Case 3: Past a side
+        let r = Ray::new(Point3::new(1.0, 3.01, 5.0), Vec3::new(0.0, 0.0, -1.0));
+        assert!(quad.hit(&r, 0.0, 100.0, &mut rng).is_none());
+
+        //Case 4: Out of range
+        let r = Ray::new(Point3::new(1.0, 1.5, 5.0), Vec3::new(0.0, 0.0, -1.0));
+        assert!(quad.hit(&r, 0.0, 3.99, &mut rng).is_none());
+
+        //Case 5: Parallel to the plane
+        let r = Ray::new(Point3::new(-1.0, 1.5, -1.0), Vec3::new(1.0, 0.0, 1.0));
+        assert!(quad.hit(&r, 0.0, 100.0, &mut rng).is_none());
+    }
+
+    #[test]
+    fn test_quad_bounding_box(){
+        //Case 1: Slanted
+        let quad = Quad::new(Point3::new(1.0, 0.0, 0.0), Vec3::new(-1.0, 0.0, 2.0), Vec3::new(0.0, 3.0, 0.0), light());
+        let bb = quad.bounding_box().unwrap();
+        assert_eq!(bb.min(), Point3::new(0.0, 0.0, 0.0));
+        assert_eq!(bb.max(), Point3::new(1.0, 3.0, 2.0));
+
+        //Case 2: Flat in y is padded
+        let quad = Quad::new(Point3::new(0.0, 1.0, 0.0), Vec3::new(2.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 2.0), light());
+        let bb = quad.bounding_box().unwrap();
+        assert_eq!(bb.min(), Point3::new(0.0, 0.9999, 0.0));
+        assert_eq!(bb.max(), Point3::new(2.0, 1.0001, 2.0));
+    }
+
+    #[test]
+    fn test_quad_surface(){
+        let quad = Quad::new(Point3::new(0.0, 1.0, 0.0), Vec3::new(2.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 3.0), light());
+        assert!((quad.area() - 6.0).abs() < 1e-12);
+        let mut rng = Rng::new(0);
+        for _ in 0..100{
+            let (point, normal) = quad.sample_surface(&mut rng);
+            assert_eq!(normal, Vec3::new(0.0, -1.0, 0.0));
+            let r = Ray::new(point + Vec3::new(0.0, 1.0, 0.0), Vec3::new(0.0, -1.0, 0.0));
+            assert!(quad.hit(&r, 0.0, 2.0, &mut rng).is_some());
+        }
+    }
+
+    #[test]
+    fn test_cuboid(){
+        let mut rng = Rng::new(0);
+        let cuboid = Cuboid::new(Point3::new(1.0, 2.0, 3.0), Point3::new(-1.0, 0.0, 0.0), light());
+        let bb = cuboid.bounding_box().unwrap();
+        assert_eq!(bb.min(), Point3::new(-1.0, 0.0, 0.0));
+        assert_eq!(bb.max(), Point3::new(1.0, 2.0, 3.0));
+        assert!((cuboid.area() - 2.0*(2.0*2.0 + 2.0*3.0 + 2.0*3.0)).abs() < 1e-12);
+
+        //Case 1: Every side's normal points out of the box
+        let center = Point3::new(0.0, 1.0, 1.5);
+        for side in cuboid.sides().iter(){
+            let middle = side.corner() + 0.5*(side.u + side.v);
+            assert!((middle - center).dot(side.outward_normal()) > 0.0);
+        }
+
+        //Case 2: The nearest side is hit, facing the ray
+        let r = Ray::new(Point3::new(0.0, 1.0, 10.0), Vec3::new(0.0, 0.0, -1.0));
+        let (rec, _) = cuboid.hit(&r, 0.0, 100.0, &mut rng).unwrap();
+        assert_eq!(rec.t, 7.0);
+        assert_eq!(rec.normal, Vec3::new(0.0, 0.0, 1.0));
+        assert!(rec.front_face);
+
+        //Case 3: From inside, the far side is hit from behind
+        let r = Ray::new(center, Vec3::new(1.0, 0.0, 0.0));
+        let (rec, _) = cuboid.hit(&r, 0.0, 100.0, &mut rng).unwrap();
+        assert_eq!(rec.t, 1.0);
+        assert!(!rec.front_face);
+
+        //Case 4: Miss
+        let r = Ray::new(Point3::new(0.0, 2.5, 10.0), Vec3::new(0.0, 0.0, -1.0));
+        assert!(cuboid.hit(&r, 0.0, 100.0, &mut rng).is_none());
+
+        //Case 5: Samples lie on the surface
+        for _ in 0..100{
+            let (point, normal) = cuboid.sample_surface(&mut rng);
+            let r = Ray::new(point + normal, -normal);
+            let (rec, _) = cuboid.hit(&r, 0.0, 2.0, &mut rng).unwrap();
+            assert!((rec.t - 1.0).abs() < 1e-9);
+        }
+    }
+}
@@ -0,0 +1,477 @@
+use crate::vec::*;
+use crate::ray::*;
+use crate::traceable::*;
+use crate::bvh::*;
+use crate::material::*;
+use crate::light::*;
+use crate::rng::*;
+
+use std::f64::consts::PI;
+
+//Where a shape built around the y axis sits in the scene: the shape's
+//origin goes to origin and its y axis to axis. Angles around the axis are
+//measured from x, which is as close to the scene's +x as the axis allows,
+//turning the way rotate_y does.
+#[derive (Copy, Clone, Debug, PartialEq)]
+pub struct Frame{
+    origin: Point3,
+    x: Vec3,
+    y: Vec3,
+    z: Vec3
+}
+
+//An open tube of the given radius running height along the axis from its
+//base, optionally closed at both ends. Only sweep radians of it are kept,
+//with the cut sides left open.
+#[derive (Clone)]
+pub struct Cylinder{
+    frame: Frame,
+    radius: f64,
+    height: f64,
+    sweep: f64,
+    capped: bool,
+    mat: Material
+}
+
+//A flat disk facing along the axis, with a hole of inner_radius making it
+//an annulus
+#[derive (Clone)]
+pub struct Disk{
+    frame: Frame,
+    radius: f64,
+    inner_radius: f64,
+    sweep: f64,
+    mat: Material
+}
+
+//The side of a cone with its base circle at the origin and its tip height
+//along the axis. The base is left open.
+#[derive (Clone)]
+pub struct Cone{
+    frame: Frame,
+    radius: f64,
+    height: f64,
+    sweep: f64,
+    mat: Material
+}
+
+//A hit found in a shape's own coordinates
+struct LocalHit{
+    t: f64,
+    p: Point3,
+    outward_normal: Vec3,
+    u: f64,
+    v: f64
+}
+
+impl Frame{
+    pub fn new(origin: Point3, axis: Vec3) -> Frame{
+        let y = axis.unit_vector();
+        let reference = if y.x().abs() < 0.999 {Vec3::new(1.0, 0.0, 0.0)} else {Vec3::new(0.0, 0.0, -1.0)};
+        let x = (reference - reference.dot(y)*y).unit_vector();
+        Frame{origin, x, y, z: x.cross(y)}
+    }
+
+    fn local(&self, v: Vec3) -> Vec3{
+        Vec3::new(v.dot(self.x), v.dot(self.y), v.dot(self.z))
+    }
+
+    fn world(&self, v: Vec3) -> Vec3{
+        v.x()*self.x + v.y()*self.y + v.z()*self.z
+    }
+
+    fn hit<'a>(&self, r: &Ray, t_min: f64, t_max: f64, mat: &'a Material, hit_local: impl Fn(Point3, Vec3, f64, f64) -> Option<LocalHit>) -> Option<(HitRecord, &'a Material)>{
+        let hit = hit_local(self.local(r.origin() - self.origin), self.local(r.direction()), t_min, t_max)?;
+        let mut rec = HitRecord::new(self.origin + self.world(hit.p), self.world(hit.outward_normal), hit.t, *r, Vec3::default());
+        rec.set_uv(hit.u, hit.v);
+        Some((rec, mat))
+    }
+
+    //Bounds the box between min and max in the shape's coordinates
+    fn bounding_box(&self, min: Point3, max: Point3) -> Aabb{
+        let mut world_min = Point3::new(f64::INFINITY, f64::INFINITY, f64::INFINITY);
+        let mut world_max = -world_min;
+        for i in 0..8{
+            let corner = Vec3::new(if i & 1 == 0 {min.x()} else {max.x()},
+                                   if i & 2 == 0 {min.y()} else {max.y()},
+                                   if i & 4 == 0 {min.z()} else {max.z()});
+            let p = self.origin + self.world(corner);
+            world_min = Point3::new(world_min.x().min(p.x()), world_min.y().min(p.y()), world_min.z().min(p.z()));
+            world_max = Point3::new(world_max.x().max(p.x()), world_max.y().max(p.y()), world_max.z().max(p.z()));
+        }
+        Aabb::new(world_min, world_max)
+    }
+
+    fn sample(&self, p: Point3, outward_normal: Vec3) -> (Point3, Vec3){
+        (self.origin + self.world(p), self.world(outward_normal))
+    }
+}
+
+impl Cylinder{
+    pub fn new(frame: Frame, radius: f64, height: f64, sweep: f64, capped: bool, mat: Material) -> Cylinder{
+        Cylinder{frame, radius, height, sweep: clamp_sweep(sweep), capped, mat}
+    }
+
+    fn hit_local(&self, o: Point3, d: Vec3, t_min: f64, mut t_max: f64) -> Option<LocalHit>{
+        let mut closest = None;
+        let a = d.x()*d.x() + d.z()*d.z();
+        let half_b = o.x()*d.x() + o.z()*d.z();
+        let c = o.x()*o.x() + o.z()*o.z() - self.radius*self.radius;
+        for t in quadratic_roots(a, half_b, c).iter().flatten(){
+            if *t < t_min || *t > t_max{
+                continue;
+            }
+            let p = o + *t*d;
+            let phi = angle(p);
+            if (0.0..=self.height).contains(&p.y()) && phi <= self.sweep{
+                closest = Some(LocalHit{t: *t, p, outward_normal: Vec3::new(p.x(), 0.0, p.z()) / self.radius, u: phi / self.sweep, v: p.y() / self.height});
+                t_max = *t;
+                break;
+            }
+        }
+
+        if self.capped{
+            for &(y, normal) in [(0.0, -1.0), (self.height, 1.0)].iter(){
+                if let Some(hit) = hit_disk(o, d, t_min, t_max, y, normal, self.radius, 0.0, self.sweep){
+                    t_max = hit.t;
+                    closest = Some(hit);
+                }
+            }
+        }
+        closest
+    }
+
+    fn side_area(&self) -> f64{
+        self.sweep * self.radius * self.height
+    }
+
+    fn cap_area(&self) -> f64{
+        0.5 * self.sweep * self.radius * self.radius
+    }
+}
+
+impl Hit for Cylinder{
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, _: &mut Rng) -> Option<(HitRecord, &Material)> {
+        self.frame.hit(r, t_min, t_max, &self.mat, |o, d, t_min, t_max| self.hit_local(o, d, t_min, t_max))
+    }
+
+    fn bounding_box(&self) -> Option<Aabb>{
+        Some(self.frame.bounding_box(Point3::new(-self.radius, 0.0, -self.radius), Point3::new(self.radius, self.height, self.radius)))
+    }
+}
+
+impl Surface for Cylinder{
+    fn area(&self) -> f64{
+        self.side_area() + if self.capped {2.0 * self.cap_area()} else {0.0}
+    }
+
+    fn sample_surface(&self, rng: &mut Rng) -> (Point3, Vec3){
+        let choice = rng.f64() * self.area();
+        let phi = rng.f64() * self.sweep;
+        if choice < self.side_area(){
+            let direction = Vec3::new(phi.cos(), 0.0, -phi.sin());
+            return self.frame.sample(self.radius*direction + Vec3::new(0.0, rng.f64() * self.height, 0.0), direction);
+        }
+        let (y, normal) = if choice < self.side_area() + self.cap_area() {(0.0, -1.0)} else {(self.height, 1.0)};
+        let r = self.radius * rng.f64().sqrt();
+        self.frame.sample(Point3::new(r*phi.cos(), y, -r*phi.sin()), Vec3::new(0.0, normal, 0.0))
+    }
+
+    fn material(&self) -> &Material{
+        &self.mat
+    }
+}
+
+impl Disk{
+    pub fn new(frame: Frame, radius: f64, inner_radius: f64, sweep: f64, mat: Material) -> Disk{
+        Disk{frame, radius, inner_radius: inner_radius.clamp(0.0, radius), sweep: clamp_sweep(sweep), mat}
+    }
+}
+
+impl Hit for Disk{
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, _: &mut Rng) -> Option<(HitRecord, &Material)> {
+        self.frame.hit(r, t_min, t_max, &self.mat, |o, d, t_min, t_max| {
+            hit_disk(o, d, t_min, t_max, 0.0, 1.0, self.radius, self.inner_radius, self.sweep)
+        })
+    }
+
+    fn bounding_box(&self) -> Option<Aabb>{
+        //The bounding box must have a non-zero width in each dimension
+        Some(self.frame.bounding_box(Point3::new(-self.radius, -0.0001, -self.radius), Point3::new(self.radius, 0.0001, self.radius)))
+    }
+}
+
+impl Surface for Disk{
+    fn area(&self) -> f64{
+        0.5 * self.sweep * (self.radius*self.radius - self.inner_radius*self.inner_radius)
+    }
+
+    fn sample_surface(&self, rng: &mut Rng) -> (Point3, Vec3){
+        let inner_squared = self.inner_radius * self.inner_radius;
+        let r = (inner_squared + rng.f64() * (self.radius*self.radius - inner_squared)).sqrt();
+        let phi = rng.f64() * self.sweep;
+        self.frame.sample(Point3::new(r*phi.cos(), 0.0, -r*phi.sin()), Vec3::new(0.0, 1.0, 0.0))
+    }
+
+    fn material(&self) -> &Material{
+        &self.mat
+    }
+}
+
+impl Cone{
+    pub fn new(frame: Frame, radius: f64, height: f64, sweep: f64, mat: Material) -> Cone{
+        Cone{frame, radius, height, sweep: clamp_sweep(sweep), mat}
+    }
+
+    //Points on the side satisfy x^2 + z^2 = k (height - y)^2
+    fn hit_local(&self, o: Point3, d: Vec3, t_min: f64, t_max: f64) -> Option<LocalHit>{
+        let k = (self.radius / self.height).powi(2);
+        let h = self.height - o.y();
+        let a = d.x()*d.x() + d.z()*d.z() - k*d.y()*d.y();
+        let half_b = o.x()*d.x() + o.z()*d.z() + k*d.y()*h;
+        let c = o.x()*o.x() + o.z()*o.z() - k*h*h;
+
+        //Roots are sorted, but one may lie on the mirrored cone above the tip
+        for t in quadratic_roots(a, half_b, c).iter().flatten(){
+            if *t < t_min || *t > t_max{
+                continue;
+            }
+            let p = o + *t*d;
+            let phi = angle(p);
+            if (0.0..=self.height).contains(&p.y()) && phi <= self.sweep{
+                let outward_normal = Vec3::new(p.x(), k*(self.height - p.y()), p.z()).unit_vector();
+                return Some(LocalHit{t: *t, p, outward_normal, u: phi / self.sweep, v: p.y() / self.height});
+            }
+        }
+        None
+    }
+}
+
+impl Hit for Cone{
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, _: &mut Rng) -> Option<(HitRecord, &Material)> {
+        self.frame.hit(r, t_min, t_max, &self.mat, |o, d, t_min, t_max| self.hit_local(o, d, t_min, t_max))
+    }
+
+    fn bounding_box(&self) -> Option<Aabb>{
+        Some(self.frame.bounding_box(Point3::new(-self.radius, 0.0, -self.radius), Point3::new(self.radius, self.height, self.radius)))
+    }
+}
+
+impl Surface for Cone{
+    fn area(&self) -> f64{
+        0.5 * self.sweep * self.radius * (self.radius*self.radius + self.height*self.height).sqrt()
+    }
+
+    //The distance from the tip goes as the square root so that the wider
+    //rings near the base get more points
+    fn sample_surface(&self, rng: &mut Rng) -> (Point3, Vec3){
+        let s = rng.f64().sqrt();
+        let phi = rng.f64() * self.sweep;
+        let (r, y) = (self.radius * s, self.height * (1.0 - s));
+        let normal = Vec3::new(self.height*phi.cos(), self.radius, -self.height*phi.sin()).unit_vector();
+        self.frame.sample(Point3::new(r*phi.cos(), y, -r*phi.sin()), normal)
+    }
+
+    fn material(&self) -> &Material{
+        &self.mat
+    }
+}
+
+fn clamp_sweep(sweep: f64) -> f64{
+    sweep.clamp(1e-6, 2.0*PI)
+}
+
+//The angle of p around the y axis, from 0 at +x up to 2 pi
+fn angle(p: Point3) -> f64{
+    let phi = (-p.z()).atan2(p.x());
+    if phi < 0.0 {phi + 2.0*PI} else {phi}
+}
+
+//The real roots of a t^2 + 2 half_b t + c = 0 in increasing order. This
+//form avoids cancellation, so a ray nearly parallel to the side of a cone
+//still finds its one root accurately when a is close to zero.
+fn quadratic_roots(a: f64, half_b: f64, c: f64) -> [Option<f64>; 2]{
+    let discriminant = half_b*half_b - a*c;
+    if discriminant < 0.0{
+        return [None, None];
+    }
+    let q = -(half_b + half_b.signum()*discriminant.sqrt());
+    let (t0, t1) = (q / a, c / q);
+    let finite = |t: f64| if t.is_finite() {Some(t)} else {None};
+    [finite(t0.min(t1)), finite(t0.max(t1))]
+}
+
+//A hit on the part of the plane at height y that lies between the two radii
+//and within the sweep. The outward normal is sign times y.
+#[allow(clippy::too_many_arguments)]
+fn hit_disk(o: Point3, d: Vec3, t_min: f64, t_max: f64, y: f64, sign: f64, radius: f64, inner_radius: f64, sweep: f64) -> Option<LocalHit>{
+    if d.y() == 0.0{
+        return None;
+    }
+    let t = (y - o.y()) / d.y();
+    if t < t_min || t > t_max{
+        return None;
+    }
+    let p = Point3::new(o.x() + t*d.x(), y, o.z() + t*d.z());
+    let distance = (p.x()*p.x() + p.z()*p.z()).sqrt();
+    let phi = angle(p);
+    if distance > radius || distance < inner_radius || phi > sweep{
+        return None;
+    }
+    let v = if radius > inner_radius {(radius - distance) / (radius - inner_radius)} else {0.0};
+    Some(LocalHit{t, p, outward_normal: Vec3::new(0.0, sign, 0.0), u: phi / sweep, v})
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mat() -> Material{
+        Material::new_lambertian(Color::new(0.5, 0.5, 0.5))
+    }
+
+    fn upright() -> Frame{
+        Frame::new(Point3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 2.0, 0.0))
+    }
+
+    fn assert_near(a: Vec3, b: Vec3){
+        assert!((a - b).length() < 1e-9, "{:?} is not {:?}", a, b);
+    }
+
+    //Points sampled on a surface can be hit by a ray coming in along their
+    //normal, and the normal there matches
+    fn check_samples<S: Hit + Surface>(shape: &S){
+        let mut rng = Rng::new(0);
+        for _ in 0..200{
+            let (point, normal) = shape.sample_surface(&mut rng);
+            let r = Ray::new(point + 0.001*normal, -normal);
+            let (rec, _) = shape.hit(&r, 0.0, 0.002, &mut rng).expect("Samples lie on the surface");
+            assert!(rec.front_face);
+            assert!((rec.normal - normal).length() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn test_frame(){
+        //Case 1: Upright shapes keep the scene's axes
+        let frame = upright();
+        assert_eq!((frame.x, frame.y, frame.z), (Vec3::new(1.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0), Vec3::new(0.0, 0.0, 1.0)));
+
+        //Case 2: Lying along x, angles start from -z
+        let frame = Frame::new(Point3::new(1.0, 2.0, 3.0), Vec3::new(3.0, 0.0, 0.0));
+        assert_near(frame.x, Vec3::new(0.0, 0.0, -1.0));
+        assert_near(frame.z, Vec3::new(0.0, -1.0, 0.0));
+        let v = Vec3::new(0.3, -0.2, 0.9);
+        assert_near(frame.world(frame.local(v)), v);
+    }
+
+    #[test]
+    fn test_cylinder(){
+        let mut rng = Rng::new(0);
+        let cylinder = Cylinder::new(upright(), 1.0, 2.0, 2.0*PI, false, mat());
+
+        //Case 1: Side, with the normal facing out
+        let r = Ray::new(Point3::new(5.0, 1.0, 0.0), Vec3::new(-1.0, 0.0, 0.0));
+        let (rec, _) = cylinder.hit(&r, 0.0, 100.0, &mut rng).unwrap();
+        assert_eq!(rec.t, 4.0);
+        assert_eq!(rec.normal, Vec3::new(1.0, 0.0, 0.0));
+        assert!((rec.v - 0.5).abs() < 1e-12);
+
+        //Case 2: An open tube is seen from inside through its end
+        let r = Ray::new(Point3::new(0.0, 5.0, 0.0), Vec3::new(0.5, -2.0, 0.0));
+        let (rec, _) = cylinder.hit(&r, 0.0, 100.0, &mut rng).unwrap();
+        assert!(!rec.front_face);
+        assert_near(rec.p, Point3::new(1.0, 1.0, 0.0));
+
+        //Case 3: Capped, the end is hit first
+        let capped = Cylinder::new(upright(), 1.0, 2.0, 2.0*PI, true, mat());
+        let (rec, _) = capped.hit(&r, 0.0, 100.0, &mut rng).unwrap();
+        assert_eq!(rec.t, 1.5);
+        assert_eq!(rec.normal, Vec3::new(0.0, 1.0, 0.0));
+
+        //Case 4: Above and past the tube
+        let r = Ray::new(Point3::new(5.0, 2.5, 0.0), Vec3::new(-1.0, 0.0, 0.0));
+        assert!(capped.hit(&r, 0.0, 100.0, &mut rng).is_none());
+
+        //Case 5: A half tube keeps the side towards -z
+        let half = Cylinder::new(upright(), 1.0, 2.0, PI, false, mat());
+        let r = Ray::new(Point3::new(0.0, 1.0, 5.0), Vec3::new(0.0, 0.0, -1.0));
+        let (rec, _) = half.hit(&r, 0.0, 100.0, &mut rng).unwrap();
+        assert_eq!(rec.t, 6.0);
+        assert!(!rec.front_face);
+
+        let bb = capped.bounding_box().unwrap();
+        assert_eq!((bb.min(), bb.max()), (Point3::new(-1.0, 0.0, -1.0), Point3::new(1.0, 2.0, 1.0)));
+        assert!((capped.area() - (4.0*PI + 2.0*PI)).abs() < 1e-12);
+        check_samples(&capped);
+        check_samples(&Cylinder::new(Frame::new(Point3::new(1.0, 2.0, 3.0), Vec3::new(1.0, 1.0, 0.0)), 0.5, 3.0, 1.5*PI, true, mat()));
+    }
+
+    #[test]
+    fn test_disk(){
+        let mut rng = Rng::new(0);
+        let frame = Frame::new(Point3::new(0.0, 1.0, 0.0), Vec3::new(0.0, 0.0, 1.0));
+        let disk = Disk::new(frame, 2.0, 0.0, 2.0*PI, mat());
+
+        //Case 1: Facing the ray
+        let r = Ray::new(Point3::new(0.5, 1.5, 4.0), Vec3::new(0.0, 0.0, -1.0));
+        let (rec, _) = disk.hit(&r, 0.0, 100.0, &mut rng).unwrap();
+        assert_eq!(rec.t, 4.0);
+        assert_near(rec.normal, Vec3::new(0.0, 0.0, 1.0));
+
+        //Case 2: The hole in an annulus
+        let annulus = Disk::new(frame, 2.0, 1.0, 2.0*PI, mat());
+        assert!(annulus.hit(&r, 0.0, 100.0, &mut rng).is_none());
+        let r = Ray::new(Point3::new(1.5, 1.0, 4.0), Vec3::new(0.0, 0.0, -1.0));
+        let (rec, _) = annulus.hit(&r, 0.0, 100.0, &mut rng).unwrap();
+        assert!((rec.v - 0.5).abs() < 1e-12);
+
+        //Case 3: Outside the sweep
+        let quarter = Disk::new(upright(), 2.0, 0.0, 0.5*PI, mat());
+        let r = Ray::new(Point3::new(1.0, 1.0, -1.0), Vec3::new(0.0, -1.0, 0.0));
+        assert!(quarter.hit(&r, 0.0, 100.0, &mut rng).is_some());
+        let r = Ray::new(Point3::new(1.0, 1.0, 1.0), Vec3::new(0.0, -1.0, 0.0));
+        assert!(quarter.hit(&r, 0.0, 100.0, &mut rng).is_none());
+
+        //Case 4: Parallel
+        let r = Ray::new(Point3::new(-5.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0));
+        assert!(quarter.hit(&r, 0.0, 100.0, &mut rng).is_none());
+
+        let bb = disk.bounding_box().unwrap();
+        assert_near(bb.min(), Point3::new(-2.0, -1.0, -0.0001));
+        assert_near(bb.max(), Point3::new(2.0, 3.0, 0.0001));
+        assert!((annulus.area() - 3.0*PI).abs() < 1e-12);
+        check_samples(&annulus);
+        check_samples(&quarter);
+    }
+
+    #[test]
+    fn test_cone(){
+        let mut rng = Rng::new(0);
+        let cone = Cone::new(upright(), 1.0, 1.0, 2.0*PI, mat());
+
+        //Case 1: Side, halfway up
+        let r = Ray::new(Point3::new(5.0, 0.5, 0.0), Vec3::new(-1.0, 0.0, 0.0));
+        let (rec, _) = cone.hit(&r, 0.0, 100.0, &mut rng).unwrap();
+        assert!((rec.t - 4.5).abs() < 1e-12);
+        assert_near(rec.normal, Vec3::new(1.0, 1.0, 0.0).unit_vector());
+
+        //Case 2: Straight down from above the tip, passing the mirrored cone
+        let r = Ray::new(Point3::new(0.1, 5.0, 0.0), Vec3::new(0.0, -1.0, 0.0));
+        let (rec, _) = cone.hit(&r, 0.0, 100.0, &mut rng).unwrap();
+        assert!((rec.t - 4.1).abs() < 1e-9);
+        assert!(rec.front_face);
+
+        //Case 3: The mirrored cone above the tip is not part of it
+        let r = Ray::new(Point3::new(5.0, 1.5, 0.0), Vec3::new(-1.0, 0.0, 0.0));
+        assert!(cone.hit(&r, 0.0, 100.0, &mut rng).is_none());
+
+        //Case 4: Parallel to the side
+        let r = Ray::new(Point3::new(2.0, 0.0, 0.0), Vec3::new(-1.0, 1.0, 0.0));
+        assert!(cone.hit(&r, 0.0, 100.0, &mut rng).is_none());
+
+        assert!((cone.area() - PI*2.0f64.sqrt()).abs() < 1e-12);
+        check_samples(&cone);
+        check_samples(&Cone::new(Frame::new(Point3::new(1.0, 0.0, 0.0), Vec3::new(0.0, -1.0, 1.0)), 2.0, 0.5, PI, mat()));
+    }
+}
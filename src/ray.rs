@@ -1,13 +1,20 @@
 use crate::vec::*;
+//A ray also carries the moment it was sent out, which moving objects use
+//to decide where they are
 #[derive (Copy, Clone, Default, PartialEq, Debug)]
 pub struct Ray{
     pub orig: Point3,
     pub dir: Vec3,
+    pub time: f64,
 }
 
 impl Ray{
     pub fn new(origin: Point3, direction: Vec3) -> Ray{
-        Ray{orig: origin, dir: direction}
+        Ray{orig: origin, dir: direction, time: 0.0}
+    }
+
+    pub fn new_at_time(origin: Point3, direction: Vec3, time: f64) -> Ray{
+        Ray{orig: origin, dir: direction, time}
     }
 
     pub fn origin(&self) -> Point3{
@@ -18,6 +25,10 @@ impl Ray{
         self.dir
     }
 
+    pub fn time(&self) -> f64{
+        self.time
+    }
+
     pub fn at(&self, t:f64) -> Vec3{
         self.orig + self.dir*t
     }
@@ -28,7 +39,7 @@ impl Ray{
         if self.dir.dot(norm) < 0.0{
             offset = -offset;
         }
-        Ray::new(self.orig + offset, self.dir)
+        Ray::new_at_time(self.orig + offset, self.dir, self.time)
     }
     
 
@@ -44,6 +55,8 @@ mod tests {
         let ray = Ray::new(orig, dir);
         assert_eq!(ray.orig, orig);
         assert_eq!(ray.dir, dir);
+        assert_eq!(ray.time, 0.0);
+        assert_eq!(Ray::new_at_time(orig, dir, 0.5).time(), 0.5);
     }
 
     #[test]
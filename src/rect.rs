@@ -3,6 +3,9 @@ use crate::ray::*;
 use crate::traceable::*;
 use crate::bvh::*;
 use crate::material::*;
+use crate::light::*;
+use crate::rng::*;
+use crate::util::*;
 
 #[derive (Copy, Clone)]
 pub enum RectAxes{
@@ -11,7 +14,7 @@ pub enum RectAxes{
     YZ
 }
 
-#[derive (Copy, Clone)]
+#[derive (Clone)]
 pub struct Rect{
     mat: Material,
     axes: RectAxes,
@@ -54,7 +57,7 @@ impl Rect {
 }
 
 impl Hit for Rect {
-    fn hit(&self, r:&Ray, t_min: f64, t_max: f64) -> Option<(HitRecord, &Material)> {
+    fn hit(&self, r:&Ray, t_min: f64, t_max: f64, _: &mut Rng) -> Option<(HitRecord, &Material)> {
         let indices = self.axes_indices();
         let unused = self.unused_axis_index();
 
@@ -67,7 +70,9 @@ impl Hit for Rect {
         if x < self.corner(0) || x > self.corner(1) || y < self.corner(2) || y > self.corner(3){
             return None;
         }
-        let rec = HitRecord::new(r.at(t), self.outward_normal(), t, *r, Vec3::default());
+        let mut rec = HitRecord::new(r.at(t), self.outward_normal(), t, *r, Vec3::default());
+        rec.set_uv((x - self.corner(0)) / (self.corner(1) - self.corner(0)),
+                   (y - self.corner(2)) / (self.corner(3) - self.corner(2)));
         Some((rec, &self.mat))
     }
 
@@ -83,6 +88,25 @@ impl Hit for Rect {
 
 }
 
+impl Surface for Rect{
+    fn area(&self) -> f64{
+        (self.corner(1) - self.corner(0)) * (self.corner(3) - self.corner(2))
+    }
+
+    fn sample_surface(&self, rng: &mut Rng) -> (Point3, Vec3){
+        let mut point = [0.0; 3];
+        let (axis1, axis2) = self.axes_indices();
+        point[axis1] = rand_double(rng, self.corner(0), self.corner(1));
+        point[axis2] = rand_double(rng, self.corner(2), self.corner(3));
+        point[self.unused_axis_index()] = self.k;
+        (Point3::new(point[0], point[1], point[2]), self.outward_normal())
+    }
+
+    fn material(&self) -> &Material{
+        &self.mat
+    }
+}
+
 mod tests {
     use super::*;
     use crate::material::*;
@@ -93,56 +117,57 @@ mod tests {
 
     #[test]
     fn test_hit(){
+        let mut rng = Rng::new(0);
 
         //XY
         let diff_light = Material::new_diffuse_light(Color::new(4.0,4.0,4.0));
-        let rect = Box::new(Rect::new(RectAxes::XY, 3.0, 5.0, 1.0, 3.0, 0.0, diff_light));
+        let rect = Box::new(Rect::new(RectAxes::XY, 3.0, 5.0, 1.0, 3.0, 0.0, diff_light.clone()));
 
         //Case 1: Collision
         let r = Ray::new(Vec3::new(4.0, 2.0, -10.0), Vec3::new( 0.0, 0.0, 1.0));
-        let rec_option = rect.hit(&r, 0.0, 100.0);
+        let rec_option = rect.hit(&r, 0.0, 100.0, &mut rng);
         assert!(rec_option.is_some());
         let (rec, _) = rec_option.unwrap();
         assert_eq!(rec.t, 10.0);
 
         //Case 2: Miss face of rectangle
         let r = Ray::new(Vec3::new(5.01, 2.0, -10.0), Vec3::new( 0.0, 0.0, 1.0));
-        let rec_option = rect.hit(&r, 0.0, 100.0);
+        let rec_option = rect.hit(&r, 0.0, 100.0, &mut rng);
         assert!(rec_option.is_none());
 
         //Case 3: Miss (due to timeout)
         let r = Ray::new(Vec3::new(4.0, 2.0, -10.0), Vec3::new( 0.0, 0.0, 1.0));
-        let rec_option = rect.hit(&r, 0.0, 9.99);
+        let rec_option = rect.hit(&r, 0.0, 9.99, &mut rng);
         assert!(rec_option.is_none());
 
         //Case 4: Miss on infinitely thin edge
         let r = Ray::new(Vec3::new(0.0, 2.0, 0.0), Vec3::new( 1.0, 0.0, 1.0));
-        let rec_option = rect.hit(&r, 0.0, 100.0);
+        let rec_option = rect.hit(&r, 0.0, 100.0, &mut rng);
         assert!(rec_option.is_none());
 
         //XZ
-        let rect = Box::new(Rect::new(RectAxes::XZ, 3.0, 5.0, 1.0, 3.0, 0.0, diff_light));
+        let rect = Box::new(Rect::new(RectAxes::XZ, 3.0, 5.0, 1.0, 3.0, 0.0, diff_light.clone()));
 
         //Case 1: Collision
         let r = Ray::new(Vec3::new(4.0, -10.0, 2.0), Vec3::new( 0.0, 1.0, 0.0));
-        let rec_option = rect.hit(&r, 0.0, 100.0);
+        let rec_option = rect.hit(&r, 0.0, 100.0, &mut rng);
         assert!(rec_option.is_some());
         let (rec, _) = rec_option.unwrap();
         assert_eq!(rec.t, 10.0);
 
         //Case 2: Miss face of rectangle
         let r = Ray::new(Vec3::new(5.01, -10.0, 2.0), Vec3::new( 0.0, 1.0, 0.0));
-        let rec_option = rect.hit(&r, 0.0, 100.0);
+        let rec_option = rect.hit(&r, 0.0, 100.0, &mut rng);
         assert!(rec_option.is_none());
 
         //Case 3: Miss (due to timeout)
         let r = Ray::new(Vec3::new(4.0, -10.0, 2.0), Vec3::new( 0.0, 1.0, 0.0));
-        let rec_option = rect.hit(&r, 0.0, 9.99);
+        let rec_option = rect.hit(&r, 0.0, 9.99, &mut rng);
         assert!(rec_option.is_none());
 
         //Case 4: Miss on infinitely thin edge
         let r = Ray::new(Vec3::new(0.0, 0.0, 2.0), Vec3::new( 1.0, 0.0, 0.0));
-        let rec_option = rect.hit(&r, 0.0, 100.0);
+        let rec_option = rect.hit(&r, 0.0, 100.0, &mut rng);
         assert!(rec_option.is_none());
 
         //YZ
@@ -150,24 +175,24 @@ mod tests {
 
         //Case 1: Collision
         let r = Ray::new(Vec3::new(-10.0, 4.0, 2.0), Vec3::new( 1.0, 0.0, 0.0));
-        let rec_option = rect.hit(&r, 0.0, 100.0);
+        let rec_option = rect.hit(&r, 0.0, 100.0, &mut rng);
         assert!(rec_option.is_some());
         let (rec, _) = rec_option.unwrap();
         assert_eq!(rec.t, 10.0);
 
         //Case 2: Miss face of rectangle
         let r = Ray::new(Vec3::new(-10.0, 5.01, 2.0), Vec3::new( 1.0, 0.0, 0.0));
-        let rec_option = rect.hit(&r, 0.0, 100.0);
+        let rec_option = rect.hit(&r, 0.0, 100.0, &mut rng);
         assert!(rec_option.is_none());
 
         //Case 3: Miss (due to timeout)
         let r = Ray::new(Vec3::new(4.0, -10.0, 2.0), Vec3::new( 0.0, 1.0, 0.0));
-        let rec_option = rect.hit(&r, 0.0, 9.99);
+        let rec_option = rect.hit(&r, 0.0, 9.99, &mut rng);
         assert!(rec_option.is_none());
 
         //Case 4: Miss on infinitely thin edge
         let r = Ray::new(Vec3::new(0.0, 0.0, 2.0), Vec3::new( 0.0, 1.0, 0.0));
-        let rec_option = rect.hit(&r, 0.0, 100.0);
+        let rec_option = rect.hit(&r, 0.0, 100.0, &mut rng);
         assert!(rec_option.is_none());
 
 
@@ -177,7 +202,7 @@ mod tests {
     fn test_bounding_box(){
         //XY
         let diff_light = Material::new_diffuse_light(Color::new(4.0,4.0,4.0));
-        let rect = Box::new(Rect::new(RectAxes::XY, -5.0, -3.0, 1.0, 3.0, 0.0, diff_light));
+        let rect = Box::new(Rect::new(RectAxes::XY, -5.0, -3.0, 1.0, 3.0, 0.0, diff_light.clone()));
         let bb = rect.bounding_box();
         assert!(bb.is_some());
         let bb = bb.unwrap();
@@ -185,7 +210,7 @@ mod tests {
         assert_eq!(bb.max(), Point3::new(-3.0, 3.0, 0.0001));
 
         //XZ
-        let rect = Box::new(Rect::new(RectAxes::XZ, -5.0, -3.0, 1.0, 3.0, 0.0, diff_light));
+        let rect = Box::new(Rect::new(RectAxes::XZ, -5.0, -3.0, 1.0, 3.0, 0.0, diff_light.clone()));
         let bb = rect.bounding_box();
         assert!(bb.is_some());
         let bb = bb.unwrap();
@@ -0,0 +1,741 @@
+use crate::vec::*;
+use crate::ray::*;
+use crate::traceable::*;
+use crate::camera::*;
+use crate::light::*;
+use crate::material::*;
+use crate::environment::*;
+use crate::rng::*;
+use crate::medium::*;
+
+use std::mem;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicI64, AtomicUsize, Ordering};
+use std::thread;
+use std::thread::JoinHandle;
+
+#[derive (Copy, Clone)]
+pub struct ImageData {
+    pub image_width: i32,
+    pub image_height:i32,
+    pub samples_per_pixel: i32,
+    pub max_depth: i32,
+    //Bounces made before Russian roulette can end a path
+    pub roulette_depth: i32,
+    //Renders with the same seed and settings come out identical
+    pub seed: u64,
+    //Stops sampling pixels once they look smooth, or None to give every
+    //pixel samples_per_pixel samples
+    pub adaptive: Option<AdaptiveSampling>
+}
+
+//Settings for giving noisy pixels more samples than smooth ones. A pixel
+//takes at least min_samples samples, and at most the image's
+//samples_per_pixel, stopping once the noise in its brightness as displayed
+//falls below threshold (where 1 is the step from black to white).
+#[derive (Copy, Clone, Debug, PartialEq)]
+pub struct AdaptiveSampling{
+    pub min_samples: i32,
+    pub threshold: f64,
+}
+
+#[derive (Clone)]
+pub struct SceneData<H> where H: Hit{
+    pub world: H,
+    pub lights: LightList,
+    pub background: Background,
+    pub fog: Option<Fog>,
+    pub cam: Camera,    
+}
+
+//A rectangle of the image rendered in one go by a single thread, holding
+//the samples taken in one pass. Pixels are stored row by row from the top
+//of the tile.
+#[derive (Clone)]
+pub struct Tile{
+    pub x0: i32,
+    pub y0: i32,
+    pub width: i32,
+    pub height: i32,
+    pub pixel_colors: Vec<Color>,
+    pub pixel_squares: Vec<f64>,
+    pub pixel_samples: Vec<i32>,
+}
+
+//Tiles waiting to be rendered. Threads claim the next tile with an atomic
+//counter, so a thread that finishes a cheap tile simply takes another.
+pub struct TileQueue{
+    tiles: Vec<(i32, i32, i32, i32)>,
+    next: AtomicUsize,
+}
+
+pub struct Progress{
+    current_calculations: AtomicI64,
+    total_calculations: i64,
+    progress: AtomicI64,
+}
+
+//The finished render. Pixels are stored row by row from the top of the
+//image and hold the sum of every sample taken, not the average, along with
+//the sum of the squares of the samples' brightness and the number of
+//samples. Every pixel has samples_per_pixel samples unless adaptive
+//sampling stopped it sooner.
+#[derive (Clone)]
+pub struct Framebuffer{
+    pub image_width: i32,
+    pub image_height: i32,
+    pub samples_per_pixel: i32,
+    pub pixel_colors: Vec<Color>,
+    pub pixel_squares: Vec<f64>,
+    pub pixel_samples: Vec<i32>,
+}
+
+impl Framebuffer{
+    pub fn new(image_width: i32, image_height: i32, samples_per_pixel: i32, pixel_colors: Vec<Color>, pixel_squares: Vec<f64>, pixel_samples: Vec<i32>) -> Framebuffer{
+        let pixels = (image_width * image_height) as usize;
+        assert!(pixel_colors.len() == pixels && pixel_squares.len() == pixels && pixel_samples.len() == pixels,
+                "The pixel count does not match the image dimensions");
+        Framebuffer{image_width, image_height, samples_per_pixel, pixel_colors, pixel_squares, pixel_samples}
+    }
+
+    //A framebuffer with no samples taken yet
+    pub fn empty(image_width: i32, image_height: i32) -> Framebuffer{
+        let pixels = (image_width * image_height) as usize;
+        Framebuffer::new(image_width, image_height, 0, vec![Color::new(0.0,0.0,0.0); pixels], vec![0.0; pixels], vec![0; pixels])
+    }
+
+    //Average color of the pixel in column i, row j (counted from the top)
+    pub fn pixel(&self, i: i32, j: i32) -> Color{
+        let index = (j*self.image_width + i) as usize;
+        self.pixel_colors[index] / (self.pixel_samples[index] as f64)
+    }
+
+    //Gamma corrected 8 bit RGB, row by row from the top
+    pub fn to_rgb8(&self) -> Vec<u8>{
+        self.pixel_colors.iter()
+                         .zip(self.pixel_samples.iter())
+                         .flat_map(|(pixel, &samples)| pixel.to_rgb8(samples))
+                         .collect()
+    }
+
+    //Mean number of samples taken in each pixel
+    pub fn average_samples(&self) -> f64{
+        self.pixel_samples.iter().map(|&samples| samples as f64).sum::<f64>() / self.pixel_samples.len().max(1) as f64
+    }
+
+    pub fn add_tile(&mut self, tile: &Tile){
+        for tj in 0..tile.height{
+            let start = ((tile.y0 + tj)*self.image_width + tile.x0) as usize;
+            let tile_start = (tj*tile.width) as usize;
+            for ti in 0..tile.width as usize{
+                self.pixel_colors[start + ti] = self.pixel_colors[start + ti] + tile.pixel_colors[tile_start + ti];
+                self.pixel_squares[start + ti] += tile.pixel_squares[tile_start + ti];
+                self.pixel_samples[start + ti] += tile.pixel_samples[tile_start + ti];
+            }
+        }
+    }
+}
+
+impl Tile{
+    pub fn new(x0: i32, y0: i32, width: i32, height: i32) -> Tile{
+        let pixels = (width * height) as usize;
+        Tile{x0, y0, width, height, pixel_colors: vec![Color::new(0.0,0.0,0.0); pixels], pixel_squares: vec![0.0; pixels], pixel_samples: vec![0; pixels]}
+    }
+}
+
+impl AdaptiveSampling{
+    pub fn new(min_samples: i32, threshold: f64) -> AdaptiveSampling{
+        AdaptiveSampling{min_samples, threshold}
+    }
+
+    //Whether a pixel needs no more samples, given the sum of its samples,
+    //the sum of their squared brightness and how many there are. The noise
+    //is the standard error of the mean brightness, carried through the
+    //gamma 2 correction used for display.
+    pub fn converged(&self, color: Color, squares: f64, samples: i32) -> bool{
+        if samples < self.min_samples.max(2){
+            return false;
+        }
+        let n = samples as f64;
+        let mean = color.luminance() / n;
+        let variance = ((squares - mean*mean*n) / (n - 1.0)).max(0.0);
+        let standard_error = (variance / n).sqrt();
+
+        //The slope of sqrt(x) is 1/(2 sqrt(x))
+        standard_error <= self.threshold * 2.0 * mean.sqrt()
+    }
+}
+
+impl TileQueue{
+    pub fn new(image_width: i32, image_height: i32, tile_size: i32) -> TileQueue{
+        let mut tiles = vec![];
+        for y0 in (0..image_height).step_by(tile_size as usize){
+            for x0 in (0..image_width).step_by(tile_size as usize){
+                tiles.push((x0, y0, tile_size.min(image_width - x0), tile_size.min(image_height - y0)));
+            }
+        }
+        TileQueue{tiles, next: AtomicUsize::new(0)}
+    }
+
+    pub fn len(&self) -> usize{
+        self.tiles.len()
+    }
+
+    pub fn is_empty(&self) -> bool{
+        self.tiles.is_empty()
+    }
+
+    pub fn next_tile(&self) -> Option<Tile>{
+        let index = self.next.fetch_add(1, Ordering::Relaxed);
+        self.tiles.get(index).map(|&(x0, y0, width, height)| Tile::new(x0, y0, width, height))
+    }
+}
+
+impl Progress{
+    pub fn new(total_calculations: i64) -> Progress{
+        Progress{current_calculations: AtomicI64::new(0), total_calculations, progress: AtomicI64::new(0)}
+    }
+
+    //Records finished samples and prints the percentage whenever it ticks over
+    pub fn report(&self, calculations: i64){
+        let current_calculations = self.current_calculations.fetch_add(calculations, Ordering::Relaxed) + calculations;
+        let new_progress = current_calculations * 100 / self.total_calculations.max(1);
+        let progress = self.progress.fetch_max(new_progress, Ordering::Relaxed);
+        if new_progress > progress {
+            println!("{}", new_progress);
+        }
+    }
+}
+
+pub const TILE_SIZE: i32 = 16;
+
+//Renders the scene on num_threads threads (including the calling thread)
+pub fn render<H>(image_data: ImageData, scene_data: SceneData<H>, num_threads: i32) -> Framebuffer
+where H: Hit + 'static {
+    render_progressive(image_data, scene_data, num_threads, image_data.samples_per_pixel, |_| true)
+}
+
+//Adds another `samples` samples to every pixel of the framebuffer, or to
+//every pixel that still needs them when sampling adaptively
+pub fn render_pass<H>(framebuffer: &mut Framebuffer, image_data: ImageData, scene_data: Arc<SceneData<H>>, samples: i32, num_threads: i32, progress: Arc<Progress>)
+where H: Hit + 'static {
+    let queue = Arc::new(TileQueue::new(image_data.image_width, image_data.image_height, TILE_SIZE));
+
+    //The threads read the samples taken so far while rendering, so the
+    //framebuffer is shared with them for the pass and taken back afterwards
+    let target_samples = framebuffer.samples_per_pixel + samples;
+    let earlier = Arc::new(mem::replace(framebuffer, Framebuffer::empty(0, 0)));
+
+    //Threading
+    let handles = initialise_threads(image_data, Arc::clone(&scene_data), Arc::clone(&earlier), target_samples, Arc::clone(&queue), Arc::clone(&progress), num_threads.max(1));
+    let mut tiles = render_tiles(image_data, scene_data, Arc::clone(&earlier), target_samples, queue, progress);
+    for handle in handles {
+        tiles.append(&mut handle.join().unwrap());
+    }
+
+    *framebuffer = Arc::try_unwrap(earlier).ok().expect("The render threads have finished with the framebuffer");
+    for tile in tiles.iter() {
+        framebuffer.add_tile(tile);
+    }
+    framebuffer.samples_per_pixel = target_samples;
+}
+
+//Renders samples_per_pass samples at a time until the image has
+//samples_per_pixel samples, handing the framebuffer to on_pass after every
+//pass so it can be displayed. Stops early if on_pass returns false.
+pub fn render_progressive<H, F>(image_data: ImageData, scene_data: SceneData<H>, num_threads: i32, samples_per_pass: i32, on_pass: F) -> Framebuffer
+where H: Hit + 'static, F: FnMut(&Framebuffer) -> bool {
+    let framebuffer = Framebuffer::empty(image_data.image_width, image_data.image_height);
+    render_progressive_from(framebuffer, image_data, scene_data, num_threads, samples_per_pass, on_pass)
+}
+
+//As render_progressive, but carries on adding samples to a framebuffer that
+//already holds some, such as one loaded from a checkpoint. Fresh samples are
+//independent of the earlier ones, so the result matches a render made in one go.
+pub fn render_progressive_from<H, F>(mut framebuffer: Framebuffer, image_data: ImageData, scene_data: SceneData<H>, num_threads: i32, samples_per_pass: i32, mut on_pass: F) -> Framebuffer
+where H: Hit + 'static, F: FnMut(&Framebuffer) -> bool {
+    assert_eq!((framebuffer.image_width, framebuffer.image_height), (image_data.image_width, image_data.image_height),
+               "The framebuffer does not match the image dimensions");
+    let remaining_samples = (image_data.samples_per_pixel - framebuffer.samples_per_pixel).max(0);
+    let total_calculations = (image_data.image_width * image_data.image_height) as i64 * remaining_samples as i64;
+    let progress = Arc::new(Progress::new(total_calculations));
+    let scene_data = Arc::new(scene_data);
+
+    while framebuffer.samples_per_pixel < image_data.samples_per_pixel {
+        let samples = samples_per_pass.max(1).min(image_data.samples_per_pixel - framebuffer.samples_per_pixel);
+        render_pass(&mut framebuffer, image_data, Arc::clone(&scene_data), samples, num_threads, Arc::clone(&progress));
+        if !on_pass(&framebuffer) {
+            break;
+        }
+    }
+    framebuffer
+}
+
+//Follows the path of r through the scene, carrying the fraction of light
+//that survives each bounce. Once roulette_depth bounces have been made a
+//path continues with probability equal to its brightest channel and is
+//scaled up to make up for the paths that stop. Rays passing through fog
+//may scatter in it before they reach the next surface.
+#[allow(clippy::too_many_arguments)]
+pub fn ray_color<T>(r: &Ray, background: &Background, fog: Option<&Fog>, world: &T, lights: &LightList, max_depth: i32, roulette_depth: i32, rng: &mut Rng) -> Color where T: Hit {
+    let mut color = Color::new(0.0,0.0,0.0);
+    let mut throughput = Color::new(1.0,1.0,1.0);
+    let mut ray = *r;
+
+    //The density with which the material at the previous bounce chose ray,
+    //or None for camera rays and specular bounces. Light reached that way is
+    //weighted against the chance of having sampled it directly.
+    let mut bsdf_pdf: Option<f64> = None;
+
+    let environment = match background{
+        Background::Map(map) => Some(map.as_ref()),
+        Background::Constant(_) => None
+    };
+
+    //No more light is gathered once the ray bounce limit is reached.
+    for depth in 0..max_depth{
+        let mut hit = world.hit(&ray, 0.001, f64::INFINITY, rng);
+        if let Some(fog) = fog{
+            let t_max = hit.as_ref().map_or(f64::INFINITY, |(rec, _)| rec.t);
+            hit = fog.hit(&ray, t_max, rng).or(hit);
+        }
+        let (rec, mat) = match hit{
+            Some(hit) => hit,
+            None => {
+                let mut emitted = background.value(ray.direction());
+                if let (Some(bsdf_pdf), Some(environment)) = (bsdf_pdf, environment){
+                    emitted = emitted * power_heuristic(bsdf_pdf, environment.pdf(ray.direction()));
+                }
+                return color + throughput.elementwise_mult(&emitted)
+            }
+        };
+
+        let mut emitted = mat.emit();
+        if let Some(bsdf_pdf) = bsdf_pdf{
+            if emitted != Color::default(){
+                //Light that sampling the lights could never have found keeps its full weight
+                let light_pdf = if rec.light_sampled {lights.pdf(ray.origin(), rec.p, rec.normal)} else {0.0};
+                emitted = emitted * power_heuristic(bsdf_pdf, light_pdf);
+            }
+        }
+        color = color + throughput.elementwise_mult(&emitted);
+
+        let (attenuation, scattered) = match mat.scatter(&ray, &rec, rng){
+            Some(scatter) => scatter,
+            None => break
+        };
+
+        if mat.is_specular() || (lights.is_empty() && environment.is_none()){
+            bsdf_pdf = None;
+        } else{
+            if !lights.is_empty(){
+                let direct = sample_light(&ray, &rec, mat, fog, world, lights, rng);
+                color = color + throughput.elementwise_mult(&direct);
+            }
+            if let Some(environment) = environment{
+                let direct = sample_environment(&ray, &rec, mat, fog, world, environment, rng);
+                color = color + throughput.elementwise_mult(&direct);
+            }
+            bsdf_pdf = Some(mat.scattering_pdf(&ray, &rec, &scattered));
+        }
+        throughput = throughput.elementwise_mult(&attenuation);
+
+        if depth + 1 >= roulette_depth{
+            let survival = throughput.x().max(throughput.y()).max(throughput.z()).min(0.95);
+            if rng.f64() >= survival{
+                break;
+            }
+            throughput = throughput / survival;
+        }
+        //Materials ignore time, so the path stays at the moment it started
+        ray = Ray::new_at_time(scattered.origin(), scattered.direction(), ray.time());
+    }
+    color
+}
+
+//Light arriving directly from a point chosen on one of the lights,
+//weighted against the chance of the material scattering towards it
+fn sample_light<T>(r: &Ray, rec: &HitRecord, mat: &Material, fog: Option<&Fog>, world: &T, lights: &LightList, rng: &mut Rng) -> Color where T: Hit {
+    let (point, normal, light_emitted) = lights.sample(rng);
+    let light_pdf = lights.pdf(rec.p, point, normal);
+    if light_pdf <= 0.0{
+        return Color::new(0.0,0.0,0.0);
+    }
+
+    let to_light = point - rec.p;
+    let distance = to_light.length();
+    let shadow_ray = Ray::new_at_time(rec.p, to_light / distance, r.time());
+    let bsdf = mat.eval(r, rec, &shadow_ray);
+    if bsdf == Color::default() || world.hit(&shadow_ray, 0.001, distance - 0.001, rng).is_some(){
+        return Color::new(0.0,0.0,0.0);
+    }
+
+    let weight = power_heuristic(light_pdf, mat.scattering_pdf(r, rec, &shadow_ray));
+    let transmittance = fog.map_or(1.0, |fog| fog.transmittance(&shadow_ray, distance));
+    bsdf.elementwise_mult(&light_emitted) * (transmittance * weight / light_pdf)
+}
+
+//Light arriving from a direction chosen on the environment map, weighted
+//against the chance of the material scattering that way
+fn sample_environment<T>(r: &Ray, rec: &HitRecord, mat: &Material, fog: Option<&Fog>, world: &T, environment: &EnvironmentMap, rng: &mut Rng) -> Color where T: Hit {
+    let (direction, radiance, environment_pdf) = match environment.sample(rng){
+        Some(sample) => sample,
+        None => return Color::new(0.0,0.0,0.0)
+    };
+
+    let shadow_ray = Ray::new_at_time(rec.p, direction, r.time());
+    let bsdf = mat.eval(r, rec, &shadow_ray);
+    if bsdf == Color::default() || world.hit(&shadow_ray, 0.001, f64::INFINITY, rng).is_some(){
+        return Color::new(0.0,0.0,0.0);
+    }
+
+    let weight = power_heuristic(environment_pdf, mat.scattering_pdf(r, rec, &shadow_ray));
+    let transmittance = fog.map_or(1.0, |fog| fog.transmittance(&shadow_ray, f64::INFINITY));
+    bsdf.elementwise_mult(&radiance) * (transmittance * weight / environment_pdf)
+}
+
+//Multiple importance sampling weight for a sample drawn with density
+//pdf when other_pdf could also have produced it
+pub fn power_heuristic(pdf: f64, other_pdf: f64) -> f64{
+    let pdf_squared = pdf*pdf;
+    let total = pdf_squared + other_pdf*other_pdf;
+    if total == 0.0{
+        return 0.0;
+    }
+    pdf_squared / total
+}
+
+pub fn initialise_threads<H>(image_data: ImageData, scene_data: Arc<SceneData<H>>, earlier: Arc<Framebuffer>, target_samples: i32, queue: Arc<TileQueue>, progress: Arc<Progress>, num_threads: i32) -> Vec<JoinHandle<Vec<Tile>>>
+where H: Hit + 'static {
+    let mut handles = vec![];
+    for _ in 0..num_threads - 1 {
+        let scene_data = Arc::clone(&scene_data);
+        let earlier = Arc::clone(&earlier);
+        let queue = Arc::clone(&queue);
+        let progress = Arc::clone(&progress);
+        let handle = thread::spawn(move || render_tiles(image_data, scene_data, earlier, target_samples, queue, progress));
+        handles.push(handle);
+    }
+    handles
+}
+
+//Takes tiles from the queue until it is empty, rendering every sample of a
+//tile before moving on. Returns the finished tiles. Each pixel carries on
+//from the samples it has in earlier until it has target_samples, or until
+//it converges when sampling adaptively. The index of a sample together
+//with the pixel and the seed fixes the random numbers it uses.
+pub fn render_tiles<H>(image_data: ImageData, scene_data: Arc<SceneData<H>>, earlier: Arc<Framebuffer>, target_samples: i32, queue: Arc<TileQueue>, progress: Arc<Progress>) -> Vec<Tile>
+where H: Hit + 'static {
+
+    let image_height = image_data.image_height as f64;
+    let image_width = image_data.image_width as f64;
+    let mut finished = vec![];
+    while let Some(mut tile) = queue.next_tile(){
+        for tj in 0..tile.height{
+            for ti in 0..tile.width{
+                let i = (tile.x0 + ti) as f64;
+                let j = (tile.y0 + tj) as f64;
+                let pixel = ((tile.y0 + tj) * image_data.image_width + tile.x0 + ti) as usize;
+                let index = (tj*tile.width + ti) as usize;
+                let mut pixel_color = earlier.pixel_colors[pixel];
+                let mut pixel_squares = earlier.pixel_squares[pixel];
+                for sample in earlier.pixel_samples[pixel]..target_samples{
+                    if image_data.adaptive.is_some_and(|adaptive| adaptive.converged(pixel_color, pixel_squares, sample)){
+                        break;
+                    }
+                    let mut rng = Rng::for_sample(image_data.seed, pixel as u64, sample as u64);
+                    let u = (rng.f64() + i)/(image_width - 1.0);
+                    let v = (rng.f64() + image_height - 1.0 - j)/(image_height - 1.0);
+                    let r = scene_data.cam.get_ray(u, v, &mut rng);
+                    let color = ray_color(&r, &scene_data.background, scene_data.fog.as_ref(), &scene_data.world, &scene_data.lights, image_data.max_depth, image_data.roulette_depth, &mut rng);
+                    let square = color.luminance() * color.luminance();
+                    pixel_color = pixel_color + color;
+                    pixel_squares += square;
+                    tile.pixel_colors[index] = tile.pixel_colors[index] + color;
+                    tile.pixel_squares[index] += square;
+                    tile.pixel_samples[index] += 1;
+                }
+            }
+        }
+        //Samples skipped by converged pixels count as done
+        progress.report((tile.width * tile.height) as i64 * (target_samples - earlier.samples_per_pixel) as i64);
+        finished.push(tile);
+    }
+    finished
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::primitive::*;
+    use crate::bvh::*;
+    use crate::rect::*;
+    use crate::transform::*;
+
+    #[test]
+    fn test_tile_queue(){
+        let queue = TileQueue::new(40, 20, 16);
+        assert_eq!(queue.len(), 6);
+
+        //Every pixel is covered by exactly one tile
+        let mut covered = vec![0; 40*20];
+        while let Some(tile) = queue.next_tile(){
+            for j in tile.y0..tile.y0 + tile.height{
+                for i in tile.x0..tile.x0 + tile.width{
+                    covered[(j*40 + i) as usize] += 1;
+                }
+            }
+        }
+        assert!(covered.iter().all(|&count| count == 1));
+        assert!(queue.next_tile().is_none());
+    }
+
+    #[test]
+    fn test_render_pass(){
+        let image_data = ImageData{image_width: 37, image_height: 21, samples_per_pixel: 3, max_depth: 5, roulette_depth: 5, seed: 0, adaptive: None};
+        let background = Color::new(0.25, 0.5, 1.0);
+        let cam = CameraSettings::new(Point3::new(0.0, 0.0, 1.0), Point3::new(0.0, 0.0, 0.0)).to_camera(37.0/21.0);
+        let scene_data = SceneData{world: TraceableList::new(), lights: LightList::default(), background: background.into(), fog: None, cam};
+
+        //Case 1: A full render of an empty world sees only the background
+        let mut framebuffer = render(image_data, scene_data.clone(), 4);
+        assert_eq!(framebuffer.samples_per_pixel, 3);
+        assert!(framebuffer.pixel_colors.iter().all(|&pixel| pixel == background*3.0));
+
+        //Case 2: Another pass accumulates on top
+        let progress = Arc::new(Progress::new(0));
+        render_pass(&mut framebuffer, image_data, Arc::new(scene_data), 2, 3, progress);
+        assert_eq!(framebuffer.samples_per_pixel, 5);
+        assert_eq!(framebuffer.pixel(36, 20), background);
+    }
+
+    #[test]
+    fn test_render_seeded(){
+        let mut world = TraceableList::new();
+        world.add(Primitive::new_sphere(Point3::new(0.0, 0.0, 0.0), 0.5, Material::new_lambertian(Color::new(0.5, 0.5, 0.5))));
+        world.add(Primitive::new_sphere(Point3::new(0.0, 2.0, 0.0), 0.5, Material::new_diffuse_light(Color::new(4.0, 4.0, 4.0))));
+        let lights = LightList::new(&world);
+        let cam = CameraSettings::new(Point3::new(0.0, 0.0, 5.0), Point3::new(0.0, 0.0, 0.0)).to_camera(2.0);
+        let scene_data = SceneData{world, lights, background: Color::new(0.2, 0.2, 0.2).into(), fog: None, cam};
+        let image_data = ImageData{image_width: 20, image_height: 10, samples_per_pixel: 6, max_depth: 5, roulette_depth: 2, seed: 7, adaptive: None};
+
+        //Case 1: The thread count and pass size make no difference
+        let framebuffer = render(image_data, scene_data.clone(), 1);
+        let progressive = render_progressive(image_data, scene_data.clone(), 3, 2, |_| true);
+        assert_eq!(render(image_data, scene_data.clone(), 4).pixel_colors, framebuffer.pixel_colors);
+        for (a, b) in progressive.pixel_colors.iter().zip(framebuffer.pixel_colors.iter()){
+            assert!((*a - *b).length() < 1e-12);
+        }
+
+        //Case 2: Another seed gives another image
+        let reseeded = render(ImageData{seed: 8, ..image_data}, scene_data, 4);
+        assert_ne!(reseeded.pixel_colors, framebuffer.pixel_colors);
+    }
+
+    #[test]
+    fn test_converged(){
+        let adaptive = AdaptiveSampling::new(4, 0.01);
+
+        //Case 1: Never before min_samples, even when every sample agrees
+        assert!(!adaptive.converged(Color::new(3.0, 3.0, 3.0), 3.0, 3));
+        assert!(adaptive.converged(Color::new(4.0, 4.0, 4.0), 4.0, 4));
+        assert!(adaptive.converged(Color::default(), 0.0, 4));
+
+        //Case 2: Half the samples are 0 and half 2, so the mean is 1, the
+        //variance about 1 and the noise falls below 0.01 at 2500 samples
+        let split = |n: i32| (Color::new(n as f64, n as f64, n as f64), 2.0 * n as f64);
+        let (color, squares) = split(2400);
+        assert!(!adaptive.converged(color, squares, 2400));
+        let (color, squares) = split(2600);
+        assert!(adaptive.converged(color, squares, 2600));
+
+        //Case 3: Broken samples never converge
+        assert!(!adaptive.converged(Color::new(f64::NAN, 0.0, 0.0), 1.0, 100));
+    }
+
+    #[test]
+    fn test_render_adaptive(){
+        let mut world = TraceableList::new();
+        world.add(Primitive::new_sphere(Point3::new(0.0, 0.0, 0.0), 0.5, Material::new_lambertian(Color::new(0.5, 0.5, 0.5))));
+        world.add(Primitive::new_sphere(Point3::new(0.0, 2.0, 0.0), 0.5, Material::new_diffuse_light(Color::new(4.0, 4.0, 4.0))));
+        let lights = LightList::new(&world);
+        let cam = CameraSettings::new(Point3::new(0.0, 0.0, 5.0), Point3::new(0.0, 0.0, 0.0)).to_camera(2.0);
+        let scene_data = SceneData{world, lights, background: Color::new(0.2, 0.2, 0.2).into(), fog: None, cam};
+        let adaptive = Some(AdaptiveSampling::new(4, 0.01));
+        let image_data = ImageData{image_width: 20, image_height: 10, samples_per_pixel: 40, max_depth: 5, roulette_depth: 2, seed: 3, adaptive};
+
+        //Case 1: The flat background stops at the minimum while the sphere
+        //takes more, and the averages still see the background
+        let framebuffer = render(image_data, scene_data.clone(), 4);
+        assert_eq!(framebuffer.samples_per_pixel, 40);
+        assert_eq!(framebuffer.pixel_samples[0], 4);
+        assert_eq!(framebuffer.pixel(0, 0), Color::new(0.2, 0.2, 0.2));
+        assert!(framebuffer.pixel_samples.contains(&40));
+        assert!(framebuffer.pixel_samples.iter().all(|&samples| (4..=40).contains(&samples)));
+        assert!(framebuffer.average_samples() < 40.0);
+
+        //Case 2: Every pixel stops at the same sample whatever the passes
+        let progressive = render_progressive(image_data, scene_data.clone(), 3, 5, |_| true);
+        assert_eq!(progressive.pixel_samples, framebuffer.pixel_samples);
+
+        //Case 3: Without adaptive sampling every pixel takes every sample
+        let framebuffer = render(ImageData{adaptive: None, ..image_data}, scene_data, 4);
+        assert!(framebuffer.pixel_samples.iter().all(|&samples| samples == 40));
+    }
+
+    #[test]
+    fn test_render_progressive_from(){
+        let image_data = ImageData{image_width: 8, image_height: 4, samples_per_pixel: 7, max_depth: 5, roulette_depth: 5, seed: 0, adaptive: None};
+        let background = Color::new(0.25, 0.5, 1.0);
+        let cam = CameraSettings::new(Point3::new(0.0, 0.0, 1.0), Point3::new(0.0, 0.0, 0.0)).to_camera(2.0);
+        let scene_data = SceneData{world: TraceableList::new(), lights: LightList::default(), background: background.into(), fog: None, cam};
+
+        //Case 1: Picks up where an earlier render stopped
+        let partial = render_progressive(image_data, scene_data.clone(), 2, 3, |_| false);
+        assert_eq!(partial.samples_per_pixel, 3);
+        let mut passes = vec![];
+        let framebuffer = render_progressive_from(partial, image_data, scene_data.clone(), 2, 3, |framebuffer| {
+            passes.push(framebuffer.samples_per_pixel);
+            true
+        });
+        assert_eq!(passes, vec![6, 7]);
+        assert!(framebuffer.pixel_colors.iter().all(|&pixel| pixel == background*7.0));
+
+        //Case 2: Already finished
+        let framebuffer = render_progressive_from(framebuffer, image_data, scene_data, 2, 3, |_| panic!("No pass should run"));
+        assert_eq!(framebuffer.samples_per_pixel, 7);
+    }
+
+    #[test]
+    fn test_ray_color_roulette(){
+        //A convex object under a uniform sky is lit by the sky alone after one bounce
+        let mut world = TraceableList::new();
+        world.add(Primitive::new_sphere(Point3::new(0.0, 0.0, 0.0), 1.0, Material::new_lambertian(Color::new(0.5, 0.5, 0.5))));
+        let r = Ray::new(Point3::new(0.0, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0));
+        let background = Background::Constant(Color::new(1.0, 1.0, 1.0));
+
+        //Case 1: Without roulette every path sees albedo * sky
+        let mut rng = Rng::new(0);
+        let color = ray_color(&r, &background, None, &world, &LightList::default(), 50, 50, &mut rng);
+        assert_eq!(color, Color::new(0.5, 0.5, 0.5));
+
+        //Case 2: Paths survive half the time and are doubled, keeping the mean
+        let samples = 4000;
+        let mut total = 0.0;
+        for _ in 0..samples{
+            let color = ray_color(&r, &background, None, &world, &LightList::default(), 50, 1, &mut rng);
+            assert!(color == Color::new(0.0, 0.0, 0.0) || color == Color::new(1.0, 1.0, 1.0));
+            total += color.x();
+        }
+        assert!((total/samples as f64 - 0.5).abs() < 0.05);
+    }
+
+    #[test]
+    fn test_ray_color_environment(){
+        //A uniform environment map lights a diffuse sphere the same as a
+        //constant sky, once its samples and the material's are combined
+        let mut world = TraceableList::new();
+        world.add(Primitive::new_sphere(Point3::new(0.0, 0.0, 0.0), 1.0, Material::new_lambertian(Color::new(0.5, 0.5, 0.5))));
+        let r = Ray::new(Point3::new(0.0, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0));
+        let background = Background::new_map(EnvironmentMap::new(16, 8, vec![Color::new(1.0, 1.0, 1.0); 16*8], 1.0, 0.0));
+
+        let mut rng = Rng::new(0);
+        assert_eq!(ray_color(&Ray::new(r.origin(), -r.direction()), &background, None, &world, &LightList::default(), 50, 50, &mut rng), Color::new(1.0, 1.0, 1.0));
+        let samples = 4000;
+        let mut total = 0.0;
+        for _ in 0..samples{
+            total += ray_color(&r, &background, None, &world, &LightList::default(), 50, 50, &mut rng).x();
+        }
+        assert!((total/samples as f64 - 0.5).abs() < 0.02);
+    }
+
+    #[test]
+    fn test_ray_color_unsampled_light(){
+        //A diffuse sphere inside a glowing shell that is not among the lights,
+        //with the only light outside the shell. Every bounce off the sphere
+        //sees the shell at full weight.
+        let glow = Material::new_diffuse_light(Color::new(1.0, 1.0, 1.0));
+        let check = |shell: Primitive| {
+            let mut world = TraceableList::new();
+            world.add(Primitive::new_sphere(Point3::new(0.0, 0.0, 0.0), 1.0, Material::new_lambertian(Color::new(0.5, 0.5, 0.5))));
+            world.add(shell);
+            world.add(Primitive::new_rect(RectAxes::XZ, -1.0, 1.0, -1.0, 1.0, 20.0, Material::new_diffuse_light(Color::new(4.0, 4.0, 4.0))));
+            let lights = LightList::new(&world);
+            assert_eq!(lights.len(), 1);
+
+            let r = Ray::new_at_time(Point3::new(0.0, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0), 0.5);
+            let mut rng = Rng::new(0);
+            for _ in 0..100{
+                let color = ray_color(&r, &Color::default().into(), None, &world, &lights, 50, 50, &mut rng);
+                assert_eq!(color, Color::new(0.5, 0.5, 0.5));
+            }
+        };
+
+        //Case 1: The shell is an instance
+        let mut shell = TraceableList::new();
+        shell.add(Primitive::new_sphere(Point3::new(0.0, 0.0, 0.0), -10.0, glow.clone()));
+        check(Primitive::new_instance(Arc::new(FlatBvh::new(shell)), Transform::identity()));
+
+        //Case 2: The shell is moving
+        check(Primitive::new_moving_sphere(Point3::new(0.0, 0.0, 0.0), Point3::new(0.5, 0.0, 0.0), 0.0, 1.0, -10.0, glow));
+    }
+
+    #[test]
+    fn test_ray_color_instanced_light(){
+        //A square mesh light above a floor, either placed directly, where it
+        //is sampled as a light, or as an instance, where it is only found by
+        //bouncing into it. Both must light the floor the same on average. A
+        //small far away light means there is always a light to sample.
+        let lamp = Material::new_diffuse_light(Color::new(1.0, 1.0, 1.0));
+        let corners = [Point3::new(-1.0, 1.0, -1.0), Point3::new(1.0, 1.0, -1.0), Point3::new(1.0, 1.0, 1.0), Point3::new(-1.0, 1.0, 1.0)];
+        let down = [Vec3::new(0.0, -1.0, 0.0); 3];
+        let mut mesh = TraceableList::new();
+        mesh.add(Primitive::new_triangle([corners[0], corners[1], corners[2]], down, lamp.clone()));
+        mesh.add(Primitive::new_triangle([corners[0], corners[2], corners[3]], down, lamp));
+        let floor = Primitive::new_quad(Point3::new(-10.0, 0.0, 10.0), Vec3::new(20.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -20.0),
+                                        Material::new_lambertian(Color::new(0.5, 0.5, 0.5)));
+
+        let far_light = Primitive::new_rect(RectAxes::XZ, 20.0, 21.0, 20.0, 21.0, 30.0, Material::new_diffuse_light(Color::new(1.0, 1.0, 1.0)));
+
+        let mut direct = mesh.clone();
+        direct.add(floor.clone());
+        direct.add(far_light.clone());
+        let mut instanced = TraceableList::new();
+        instanced.add(Primitive::new_instance(Arc::new(FlatBvh::new(mesh)), Transform::identity()));
+        instanced.add(floor);
+        instanced.add(far_light);
+
+        let r = Ray::new(Point3::new(0.0, 0.5, 0.0), Vec3::new(0.0, -1.0, 0.0));
+        let mean = |world: &TraceableList| {
+            let lights = LightList::new(world);
+            let mut rng = Rng::new(0);
+            let samples = 20000;
+            (0..samples).map(|_| ray_color(&r, &Color::default().into(), None, world, &lights, 50, 50, &mut rng).x()).sum::<f64>() / samples as f64
+        };
+        assert_eq!(LightList::new(&instanced).len(), 1);
+        let (direct, instanced) = (mean(&direct), mean(&instanced));
+        assert!(direct > 0.1);
+        assert!((direct - instanced).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_render_progressive(){
+        let image_data = ImageData{image_width: 8, image_height: 4, samples_per_pixel: 7, max_depth: 5, roulette_depth: 5, seed: 0, adaptive: None};
+        let cam = CameraSettings::new(Point3::new(0.0, 0.0, 1.0), Point3::new(0.0, 0.0, 0.0)).to_camera(2.0);
+        let scene_data = SceneData{world: TraceableList::new(), lights: LightList::default(), background: Color::new(0.5, 0.5, 0.5).into(), fog: None, cam};
+
+        //Case 1: Passes are capped at the requested sample count
+        let mut passes = vec![];
+        let framebuffer = render_progressive(image_data, scene_data.clone(), 2, 3, |framebuffer| {
+            passes.push(framebuffer.samples_per_pixel);
+            true
+        });
+        assert_eq!(passes, vec![3, 6, 7]);
+        assert_eq!(framebuffer.samples_per_pixel, 7);
+        assert_eq!(framebuffer.to_rgb8().len(), 8*4*3);
+
+        //Case 2: Stopped after the first pass
+        let framebuffer = render_progressive(image_data, scene_data, 2, 3, |_| false);
+        assert_eq!(framebuffer.samples_per_pixel, 3);
+    }
+}
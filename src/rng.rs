@@ -0,0 +1,97 @@
+//The random number generator threaded through rendering. Every sample of
+//every pixel gets its own generator, seeded from the render seed and the
+//sample's position, so a render is the same whichever thread takes each
+//sample. The generator is wyrand, which is small and fast but not suitable
+//for anything needing secure random numbers.
+#[derive (Debug, Clone, PartialEq)]
+pub struct Rng{
+    state: u64,
+}
+
+impl Rng{
+    pub fn new(seed: u64) -> Rng{
+        Rng{state: splitmix64(seed)}
+    }
+
+    //The generator for one sample of the pixel at index pixel
+    pub fn for_sample(seed: u64, pixel: u64, sample: u64) -> Rng{
+        Rng{state: splitmix64(splitmix64(splitmix64(seed) ^ pixel) ^ sample)}
+    }
+
+    pub fn next_u64(&mut self) -> u64{
+        self.state = self.state.wrapping_add(0xA076_1D64_78BD_642F);
+        let t = (self.state as u128).wrapping_mul((self.state ^ 0xE703_7ED1_A0B4_28DB) as u128);
+        (t >> 64) as u64 ^ t as u64
+    }
+
+    //Uniform in [0, 1), using the top 53 bits so every value is equally likely
+    pub fn f64(&mut self) -> f64{
+        (self.next_u64() >> 11) as f64 * (1.0 / (1u64 << 53) as f64)
+    }
+
+    //Uniform in [0, n)
+    pub fn usize(&mut self, n: usize) -> usize{
+        ((self.next_u64() as u128 * n as u128) >> 64) as usize
+    }
+
+    //Fisher-Yates
+    pub fn shuffle<T>(&mut self, values: &mut [T]){
+        for i in (1..values.len()).rev(){
+            let j = self.usize(i + 1);
+            values.swap(i, j);
+        }
+    }
+}
+
+//Scrambles nearby seeds into unrelated states
+fn splitmix64(x: u64) -> u64{
+    let mut z = x.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_repeatable(){
+        let mut a = Rng::new(7);
+        let mut b = Rng::new(7);
+        let mut c = Rng::new(8);
+        let first: Vec<u64> = (0..10).map(|_| a.next_u64()).collect();
+        assert_eq!(first, (0..10).map(|_| b.next_u64()).collect::<Vec<u64>>());
+        assert_ne!(first, (0..10).map(|_| c.next_u64()).collect::<Vec<u64>>());
+
+        //Neighbouring samples and pixels get unrelated generators
+        let x = Rng::for_sample(1, 5, 0).next_u64();
+        assert_ne!(x, Rng::for_sample(1, 5, 1).next_u64());
+        assert_ne!(x, Rng::for_sample(1, 6, 0).next_u64());
+        assert_ne!(x, Rng::for_sample(2, 5, 0).next_u64());
+        assert_eq!(x, Rng::for_sample(1, 5, 0).next_u64());
+    }
+
+    #[test]
+    fn test_distribution(){
+        let mut rng = Rng::new(0);
+        let samples = 100_000;
+        let mut buckets = [0i32; 10];
+        let mut total = 0.0;
+        for _ in 0..samples{
+            let x = rng.f64();
+            assert!((0.0..1.0).contains(&x));
+            buckets[(x * 10.0) as usize] += 1;
+            total += x;
+        }
+        assert!((total / samples as f64 - 0.5).abs() < 0.01);
+        assert!(buckets.iter().all(|&count| (count - samples/10).abs() < samples/100));
+
+        let mut values: Vec<usize> = (0..20).collect();
+        rng.shuffle(&mut values);
+        assert_ne!(values, (0..20).collect::<Vec<usize>>());
+        values.sort_unstable();
+        assert_eq!(values, (0..20).collect::<Vec<usize>>());
+        assert!((0..1000).all(|_| rng.usize(3) < 3));
+    }
+}
@@ -0,0 +1,1104 @@
+use crate::vec::*;
+use crate::traceable::*;
+use crate::material::*;
+use crate::primitive::*;
+use crate::camera::*;
+use crate::rect::*;
+use crate::util::*;
+use crate::texture::*;
+use crate::environment::*;
+use crate::transform::*;
+use crate::bvh::*;
+use crate::medium::*;
+use crate::volume::*;
+
+use serde::Deserialize;
+use toml::Spanned;
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+//A scene loaded from a TOML description. The camera is kept as settings
+//because its aspect ratio comes from the render rather than the scene.
+pub struct SceneFile {
+    pub world: TraceableList,
+    pub background: Background,
+    pub fog: Option<Fog>,
+    pub camera: CameraSettings,
+}
+
+#[derive (Debug)]
+pub struct SceneError {
+    pub path: String,
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+    pub message: String,
+}
+
+#[derive (Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SceneDesc {
+    background: Option<BackgroundDesc>,
+    fog: Option<FogDesc>,
+    camera: CameraDesc,
+    #[serde(default)]
+    materials: HashMap<String, MaterialDesc>,
+    #[serde(default)]
+    spheres: Vec<SphereDesc>,
+    #[serde(default)]
+    rects: Vec<RectDesc>,
+    #[serde(default)]
+    quads: Vec<QuadDesc>,
+    #[serde(default)]
+    boxes: Vec<BoxDesc>,
+    #[serde(default)]
+    cylinders: Vec<CylinderDesc>,
+    #[serde(default)]
+    disks: Vec<DiskDesc>,
+    #[serde(default)]
+    cones: Vec<ConeDesc>,
+    #[serde(default)]
+    triangles: Vec<TriangleDesc>,
+    #[serde(default)]
+    meshes: Vec<MeshDesc>,
+    #[serde(default)]
+    media: Vec<MediumDesc>,
+    #[serde(default)]
+    volumes: Vec<VolumeDesc>,
+}
+
+#[derive (Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CameraDesc {
+    look_from: [f64; 3],
+    look_at: [f64; 3],
+    v_up: Option<[f64; 3]>,
+    v_fov: Option<f64>,
+    aperture: Option<f64>,
+    focus_dist: Option<f64>,
+    shutter: Option<Spanned<[f64; 2]>>,
+}
+
+//A plain color or an environment map, with its rotation about +y in degrees
+#[derive (Deserialize)]
+#[serde(untagged)]
+enum BackgroundDesc {
+    Constant([f64; 3]),
+    Map(EnvironmentDesc),
+}
+
+#[derive (Deserialize)]
+#[serde(deny_unknown_fields)]
+struct EnvironmentDesc {
+    file: String,
+    intensity: Option<f64>,
+    rotation: Option<f64>,
+}
+
+#[derive (Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum MaterialDesc {
+    Lambertian{albedo: TextureDesc},
+    Metal{albedo: TextureDesc, #[serde(default)] fuzz: f64},
+    Conductor{albedo: TextureDesc, roughness: f64, roughness_v: Option<f64>},
+    Dielectric{index_of_refraction: f64},
+    DiffuseLight{color: [f64; 3]},
+    Isotropic{albedo: TextureDesc, #[serde(default)] g: f64},
+}
+
+//A plain color or a table describing a texture
+#[derive (Deserialize)]
+#[serde(untagged)]
+enum TextureDesc {
+    Constant([f64; 3]),
+    Texture(TextureKindDesc),
+}
+
+#[derive (Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum TextureKindDesc {
+    Image{file: String, #[serde(default)] wrap: WrapDesc},
+    Checker{even: Box<TextureDesc>, odd: Box<TextureDesc>, scale: f64},
+    Noise{scale: f64},
+}
+
+#[derive (Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "snake_case")]
+enum WrapDesc {
+    #[default]
+    Repeat,
+    MirroredRepeat,
+    ClampToEdge,
+}
+
+//A sphere moves from center to center1 over time, which defaults to [0, 1]
+#[derive (Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SphereDesc {
+    center: [f64; 3],
+    center1: Option<[f64; 3]>,
+    time: Option<Spanned<[f64; 2]>>,
+    radius: f64,
+    material: Spanned<String>,
+}
+
+#[derive (Deserialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+enum AxesDesc {
+    XY,
+    XZ,
+    YZ,
+}
+
+#[derive (Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RectDesc {
+    axes: AxesDesc,
+    axis1: [f64; 2],
+    axis2: [f64; 2],
+    k: f64,
+    material: Spanned<String>,
+}
+
+//A parallelogram with a corner and two sides
+#[derive (Deserialize)]
+#[serde(deny_unknown_fields)]
+struct QuadDesc {
+    corner: [f64; 3],
+    u: Spanned<[f64; 3]>,
+    v: Spanned<[f64; 3]>,
+    material: Spanned<String>,
+}
+
+//An axis-aligned box between two opposite corners
+#[derive (Deserialize)]
+#[serde(deny_unknown_fields)]
+struct BoxDesc {
+    min: [f64; 3],
+    max: [f64; 3],
+    material: Spanned<String>,
+}
+
+//Shapes around an axis start at a point and extend along the axis, which
+//points up by default. Sweep is the angle kept around the axis in degrees.
+#[derive (Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CylinderDesc {
+    base: [f64; 3],
+    axis: Option<Spanned<[f64; 3]>>,
+    radius: Spanned<f64>,
+    height: Spanned<f64>,
+    sweep: Option<Spanned<f64>>,
+    #[serde(default)]
+    capped: bool,
+    material: Spanned<String>,
+}
+
+//A disk, or an annulus when it has an inner radius
+#[derive (Deserialize)]
+#[serde(deny_unknown_fields)]
+struct DiskDesc {
+    center: [f64; 3],
+    axis: Option<Spanned<[f64; 3]>>,
+    radius: Spanned<f64>,
+    #[serde(default)]
+    inner_radius: f64,
+    sweep: Option<Spanned<f64>>,
+    material: Spanned<String>,
+}
+
+#[derive (Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ConeDesc {
+    base: [f64; 3],
+    axis: Option<Spanned<[f64; 3]>>,
+    radius: Spanned<f64>,
+    height: Spanned<f64>,
+    sweep: Option<Spanned<f64>>,
+    material: Spanned<String>,
+}
+
+#[derive (Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TriangleDesc {
+    vertices: [[f64; 3]; 3],
+    normals: Option<[[f64; 3]; 3]>,
+    material: Spanned<String>,
+}
+
+#[derive (Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MeshDesc {
+    file: Spanned<String>,
+    material: Option<Spanned<String>>,
+    translate: Option<[f64; 3]>,
+    rotate: Option<[f64; 3]>,
+    scale: Option<Spanned<ScaleDesc>>,
+    keyframes: Option<Vec<KeyframeDesc>>,
+}
+
+//Where a moving mesh is at one time, placed as in MeshDesc
+#[derive (Deserialize)]
+#[serde(deny_unknown_fields)]
+struct KeyframeDesc {
+    time: f64,
+    translate: Option<[f64; 3]>,
+    rotate: Option<[f64; 3]>,
+    scale: Option<Spanned<ScaleDesc>>,
+}
+
+//A medium filling the inside of a closed boundary
+#[derive (Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MediumDesc {
+    boundary: Spanned<BoundaryDesc>,
+    density: Spanned<f64>,
+    material: Spanned<String>,
+}
+
+//A sphere, box or mesh, placed as in MeshDesc. This is one struct and not
+//a tagged enum because tagged enums are buffered before they are read,
+//which loses the spans, so check makes sure the fields suit the type.
+#[derive (Deserialize)]
+#[serde(deny_unknown_fields)]
+struct BoundaryDesc {
+    #[serde(rename = "type")]
+    kind: String,
+    center: Option<[f64; 3]>,
+    radius: Option<f64>,
+    min: Option<[f64; 3]>,
+    max: Option<[f64; 3]>,
+    file: Option<Spanned<String>>,
+    translate: Option<[f64; 3]>,
+    rotate: Option<[f64; 3]>,
+    scale: Option<Spanned<ScaleDesc>>,
+}
+
+//A medium of varying density, which glows where it absorbs if it has an
+//emission color
+#[derive (Deserialize)]
+#[serde(deny_unknown_fields)]
+struct VolumeDesc {
+    density: Spanned<FieldDesc>,
+    scale: Spanned<f64>,
+    material: Spanned<String>,
+    absorption: Option<Spanned<f64>>,
+    emission: Option<[f64; 3]>,
+    temperature: Option<Spanned<FieldDesc>>,
+}
+
+#[derive (Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum FieldDesc {
+    Grid{file: String, min: [f64; 3], max: [f64; 3]},
+    Noise{min: [f64; 3], max: [f64; 3], #[serde(default = "default_frequency")] frequency: f64},
+}
+
+fn default_frequency() -> f64 {
+    1.0
+}
+
+impl BoundaryDesc {
+    fn check(&self) -> Result<(), String> {
+        let (required, optional): (&[&str], &[&str]) = match self.kind.as_str() {
+            "sphere" => (&["center", "radius"], &[]),
+            "box" => (&["min", "max"], &[]),
+            "mesh" => (&["file"], &["translate", "rotate", "scale"]),
+            kind => return Err(format!("unknown type '{}', expected sphere, box or mesh", kind)),
+        };
+        let given = [("center", self.center.is_some()), ("radius", self.radius.is_some()), ("min", self.min.is_some()),
+                     ("max", self.max.is_some()), ("file", self.file.is_some()), ("translate", self.translate.is_some()),
+                     ("rotate", self.rotate.is_some()), ("scale", self.scale.is_some())];
+        for (field, is_given) in given.iter() {
+            if *is_given && !required.contains(field) && !optional.contains(field) {
+                return Err(format!("a {} boundary does not take {}", self.kind, field));
+            }
+            if !*is_given && required.contains(field) {
+                return Err(format!("a {} boundary needs {}", self.kind, field));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl FieldDesc {
+    fn to_field(&self, scene_dir: &Path) -> Result<DensityField, String> {
+        let (min, max) = match self {
+            FieldDesc::Grid{min, max, ..} | FieldDesc::Noise{min, max, ..} => (to_vec(*min), to_vec(*max)),
+        };
+        if (0..3).any(|axis| max[axis] <= min[axis]) {
+            return Err("max must be above min on every axis".to_string());
+        }
+        let bb = Aabb::new(min, max);
+        match self {
+            FieldDesc::Grid{file, ..} => {
+                let file = scene_dir.join(file);
+                let grid = VoxelGrid::load(&file, bb).map_err(|err| format!("cannot load '{}': {}", file.display(), err))?;
+                Ok(DensityField::new_grid(grid))
+            }
+            FieldDesc::Noise{frequency, ..} if *frequency <= 0.0 => Err("frequency must be positive".to_string()),
+            FieldDesc::Noise{frequency, ..} => Ok(DensityField::new_noise(bb, *frequency)),
+        }
+    }
+}
+
+//Fog filling the scene below height, or everywhere if no height is given
+#[derive (Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FogDesc {
+    density: Spanned<f64>,
+    albedo: [f64; 3],
+    g: Option<Spanned<f64>>,
+    height: Option<f64>,
+}
+
+#[derive (Deserialize, Clone, Copy)]
+#[serde(untagged)]
+enum ScaleDesc {
+    Uniform(f64),
+    Axes([f64; 3]),
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (self.line, self.column) {
+            (Some(line), Some(column)) => write!(f, "{}:{}:{}: {}", self.path, line, column, self.message),
+            (Some(line), None) => write!(f, "{}:{}: {}", self.path, line, self.message),
+            _ => write!(f, "{}: {}", self.path, self.message),
+        }
+    }
+}
+
+impl std::error::Error for SceneError {}
+
+impl SceneError {
+    fn new(path: &str, message: String) -> SceneError {
+        SceneError{path: path.to_string(), line: None, column: None, message}
+    }
+
+    //Errors about a value whose position in the source is known
+    fn at(path: &str, source: &str, offset: usize, message: String) -> SceneError {
+        let preceding = &source[..offset.min(source.len())];
+        let line = preceding.matches('\n').count() + 1;
+        let column = offset - preceding.rfind('\n').map(|i| i + 1).unwrap_or(0) + 1;
+        SceneError{path: path.to_string(), line: Some(line), column: Some(column), message}
+    }
+
+    fn from_toml(path: &str, err: toml::de::Error) -> SceneError {
+        let mut message = err.to_string();
+        match err.line_col() {
+            Some((line, column)) => {
+                //The position is reported separately, so drop toml's own suffix
+                if let Some(index) = message.rfind(" at line ") {
+                    message.truncate(index);
+                }
+                SceneError{path: path.to_string(), line: Some(line + 1), column: Some(column + 1), message}
+            }
+            None => SceneError::new(path, message),
+        }
+    }
+}
+
+impl BackgroundDesc {
+    //Environment maps are resolved relative to scene_dir
+    fn to_background(&self, scene_dir: &Path) -> Result<Background, String> {
+        Ok(match self {
+            BackgroundDesc::Constant(color) => Background::Constant(to_vec(*color)),
+            BackgroundDesc::Map(map) => {
+                let file = scene_dir.join(&map.file);
+                let rotation = deg_to_rad(map.rotation.unwrap_or(0.0));
+                let map = EnvironmentMap::open(&file, map.intensity.unwrap_or(1.0), rotation).map_err(|err| {
+                    format!("cannot load '{}': {}", file.display(), err)
+                })?;
+                Background::new_map(map)
+            }
+        })
+    }
+}
+
+impl MaterialDesc {
+    //Image files are resolved relative to scene_dir
+    fn to_material(&self, scene_dir: &Path) -> Result<Material, String> {
+        Ok(match self {
+            MaterialDesc::Lambertian{albedo} => Material::new_textured_lambertian(albedo.to_texture(scene_dir)?),
+            MaterialDesc::Metal{albedo, fuzz} => Material::new_textured_metal(albedo.to_texture(scene_dir)?, *fuzz),
+            MaterialDesc::Conductor{albedo, roughness, roughness_v} =>
+                Material::new_textured_conductor(albedo.to_texture(scene_dir)?, *roughness, roughness_v.unwrap_or(*roughness)),
+            MaterialDesc::Dielectric{index_of_refraction} => Material::new_dielectric(*index_of_refraction),
+            MaterialDesc::DiffuseLight{color} => Material::new_diffuse_light(to_vec(*color)),
+            MaterialDesc::Isotropic{albedo, g} => {
+                if *g <= -1.0 || *g >= 1.0 {
+                    return Err("g must be between -1 and 1".to_string());
+                }
+                Material::new_henyey_greenstein(albedo.to_texture(scene_dir)?, *g)
+            }
+        })
+    }
+}
+
+impl TextureDesc {
+    fn to_texture(&self, scene_dir: &Path) -> Result<Texture, String> {
+        Ok(match self {
+            TextureDesc::Constant(color) => Texture::Constant(to_vec(*color)),
+            TextureDesc::Texture(TextureKindDesc::Image{file, wrap}) => {
+                let file = scene_dir.join(file);
+                let image = ImageTexture::open(&file, wrap.to_wrap_mode()).map_err(|err| {
+                    format!("cannot load '{}': {}", file.display(), err)
+                })?;
+                Texture::new_image(image)
+            }
+            TextureDesc::Texture(TextureKindDesc::Checker{even, odd, scale}) => {
+                Texture::new_checker(even.to_texture(scene_dir)?, odd.to_texture(scene_dir)?, *scale)
+            }
+            TextureDesc::Texture(TextureKindDesc::Noise{scale}) => Texture::new_noise(*scale),
+        })
+    }
+}
+
+impl WrapDesc {
+    fn to_wrap_mode(self) -> WrapMode {
+        match self {
+            WrapDesc::Repeat => WrapMode::Repeat,
+            WrapDesc::MirroredRepeat => WrapMode::MirroredRepeat,
+            WrapDesc::ClampToEdge => WrapMode::ClampToEdge,
+        }
+    }
+}
+
+impl AxesDesc {
+    fn to_axes(self) -> RectAxes {
+        match self {
+            AxesDesc::XY => RectAxes::XY,
+            AxesDesc::XZ => RectAxes::XZ,
+            AxesDesc::YZ => RectAxes::YZ,
+        }
+    }
+}
+
+//Scale, then rotate about x, y and z (in degrees), then translate.
+//None if a scale factor is zero.
+fn mesh_transform(translate: Option<[f64; 3]>, rotate: Option<[f64; 3]>, scale: Option<ScaleDesc>) -> Option<Transform> {
+    mesh_keyframe(0.0, translate, rotate, scale).map(|keyframe| keyframe.transform())
+}
+
+fn mesh_keyframe(time: f64, translate: Option<[f64; 3]>, rotate: Option<[f64; 3]>, scale: Option<ScaleDesc>) -> Option<Keyframe> {
+    let scale = match scale {
+        Some(ScaleDesc::Uniform(s)) => Vec3::new(s, s, s),
+        Some(ScaleDesc::Axes(s)) => to_vec(s),
+        None => Vec3::new(1.0, 1.0, 1.0),
+    };
+    if scale.x() == 0.0 || scale.y() == 0.0 || scale.z() == 0.0 {
+        return None;
+    }
+    let rotate = rotate.map(to_vec).unwrap_or_default();
+    let rotate = Vec3::new(deg_to_rad(rotate.x()), deg_to_rad(rotate.y()), deg_to_rad(rotate.z()));
+    Some(Keyframe::new(time, scale, rotate, translate.map(to_vec).unwrap_or_default()))
+}
+
+//toml only spans the part of a float after its decimal point, so this walks
+//back to where the number starts. Other values already start at their span.
+fn float_start<T>(source: &str, value: &Spanned<T>) -> usize {
+    let before = &source[..value.start()];
+    before.trim_end_matches(|c: char| c.is_ascii_alphanumeric() || "+-._".contains(c)).len()
+}
+
+//The axis and sweep, in radians, of a shape around an axis, or the offset
+//of the bad value and the problem with it
+fn axis_and_sweep(source: &str, axis: &Option<Spanned<[f64; 3]>>, sweep: &Option<Spanned<f64>>) -> Result<(Vec3, f64), (usize, &'static str)> {
+    let (axis, sweep) = (axis.as_ref(), sweep.as_ref());
+    if let Some(axis) = axis.filter(|axis| to_vec(*axis.get_ref()).near_zero()) {
+        return Err((axis.start(), "axis must be non-zero"));
+    }
+    if let Some(sweep) = sweep.filter(|sweep| *sweep.get_ref() <= 0.0 || *sweep.get_ref() > 360.0) {
+        return Err((float_start(source, sweep), "sweep must be above 0 and at most 360 degrees"));
+    }
+    let axis = axis.map(|axis| to_vec(*axis.get_ref())).unwrap_or_else(|| Vec3::new(0.0, 1.0, 0.0));
+    Ok((axis, deg_to_rad(sweep.map(|sweep| *sweep.get_ref()).unwrap_or(360.0))))
+}
+
+//Where to report a scale that has a zero factor. Without a scale nothing is
+//zero, so the fallback is never used.
+fn scale_start(source: &str, scale: Option<&Spanned<ScaleDesc>>, fallback: &Spanned<String>) -> usize {
+    scale.map_or(fallback.start(), |scale| float_start(source, scale))
+}
+
+//The first of the sizes that is not positive
+fn non_positive(source: &str, sizes: &[&Spanned<f64>]) -> Option<usize> {
+    sizes.iter().find(|size| *size.get_ref() <= 0.0).map(|size| float_start(source, size))
+}
+
+fn to_vec(v: [f64; 3]) -> Vec3 {
+    Vec3::new(v[0], v[1], v[2])
+}
+
+pub fn load_scene(path: &str) -> Result<SceneFile, SceneError> {
+    let source = fs::read_to_string(path).map_err(|err| SceneError::new(path, err.to_string()))?;
+    parse_scene(&source, path)
+}
+
+//Builds a scene from TOML source. The path is used in error messages and
+//mesh files are resolved relative to its directory.
+pub fn parse_scene(source: &str, path: &str) -> Result<SceneFile, SceneError> {
+    let desc: SceneDesc = toml::from_str(source).map_err(|err| SceneError::from_toml(path, err))?;
+
+    let scene_dir = Path::new(path).parent().unwrap_or_else(|| Path::new(""));
+    let mut materials: HashMap<&str, Material> = HashMap::new();
+    for (name, mat) in desc.materials.iter() {
+        let material = mat.to_material(scene_dir).map_err(|err| {
+            SceneError::new(path, format!("materials.{}: {}", name, err))
+        })?;
+        materials.insert(name.as_str(), material);
+    }
+    let lookup = |field: String, name: &Spanned<String>| -> Result<Material, SceneError> {
+        materials.get(name.get_ref().as_str()).cloned().ok_or_else(|| {
+            SceneError::at(path, source, name.start(), format!("{}: unknown material '{}'", field, name.get_ref()))
+        })
+    };
+
+    let mut world = TraceableList::new();
+    for (i, sphere) in desc.spheres.iter().enumerate() {
+        let mat = lookup(format!("spheres[{}].material", i), &sphere.material)?;
+        match (sphere.center1, &sphere.time) {
+            (Some(center1), time) => {
+                let [time0, time1] = time.as_ref().map(|time| *time.get_ref()).unwrap_or([0.0, 1.0]);
+                world.add(Primitive::new_moving_sphere(to_vec(sphere.center), to_vec(center1), time0, time1, sphere.radius, mat));
+            }
+            (None, Some(time)) => return Err(SceneError::at(path, source, time.start(), format!("spheres[{}]: time is only used with center1", i))),
+            (None, None) => world.add(Primitive::new_sphere(to_vec(sphere.center), sphere.radius, mat)),
+        }
+    }
+
+    for (i, rect) in desc.rects.iter().enumerate() {
+        let mat = lookup(format!("rects[{}].material", i), &rect.material)?;
+        world.add(Primitive::new_rect(rect.axes.to_axes(), rect.axis1[0], rect.axis1[1], rect.axis2[0], rect.axis2[1], rect.k, mat));
+    }
+
+    for (i, quad) in desc.quads.iter().enumerate() {
+        let mat = lookup(format!("quads[{}].material", i), &quad.material)?;
+        let (u, v) = (to_vec(*quad.u.get_ref()), to_vec(*quad.v.get_ref()));
+        if u.cross(v).near_zero() {
+            return Err(SceneError::at(path, source, quad.v.start(), format!("quads[{}]: u and v must be non-zero and not parallel", i)));
+        }
+        world.add(Primitive::new_quad(to_vec(quad.corner), u, v, mat));
+    }
+
+    for (i, cuboid) in desc.boxes.iter().enumerate() {
+        let mat = lookup(format!("boxes[{}].material", i), &cuboid.material)?;
+        world.add(Primitive::new_cuboid(to_vec(cuboid.min), to_vec(cuboid.max), mat));
+    }
+
+    for (i, cylinder) in desc.cylinders.iter().enumerate() {
+        let mat = lookup(format!("cylinders[{}].material", i), &cylinder.material)?;
+        let (axis, sweep) = axis_and_sweep(source, &cylinder.axis, &cylinder.sweep)
+            .map_err(|(offset, err)| SceneError::at(path, source, offset, format!("cylinders[{}]: {}", i, err)))?;
+        if let Some(offset) = non_positive(source, &[&cylinder.radius, &cylinder.height]) {
+            return Err(SceneError::at(path, source, offset, format!("cylinders[{}]: radius and height must be positive", i)));
+        }
+        world.add(Primitive::new_cylinder(to_vec(cylinder.base), axis, *cylinder.radius.get_ref(), *cylinder.height.get_ref(), sweep, cylinder.capped, mat));
+    }
+
+    for (i, disk) in desc.disks.iter().enumerate() {
+        let mat = lookup(format!("disks[{}].material", i), &disk.material)?;
+        let (axis, sweep) = axis_and_sweep(source, &disk.axis, &disk.sweep)
+            .map_err(|(offset, err)| SceneError::at(path, source, offset, format!("disks[{}]: {}", i, err)))?;
+        if disk.inner_radius < 0.0 || disk.inner_radius >= *disk.radius.get_ref() {
+            return Err(SceneError::at(path, source, float_start(source, &disk.radius), format!("disks[{}]: radius must be above inner_radius, which must not be negative", i)));
+        }
+        world.add(Primitive::new_annulus(to_vec(disk.center), axis, disk.inner_radius, *disk.radius.get_ref(), sweep, mat));
+    }
+
+    for (i, cone) in desc.cones.iter().enumerate() {
+        let mat = lookup(format!("cones[{}].material", i), &cone.material)?;
+        let (axis, sweep) = axis_and_sweep(source, &cone.axis, &cone.sweep)
+            .map_err(|(offset, err)| SceneError::at(path, source, offset, format!("cones[{}]: {}", i, err)))?;
+        if let Some(offset) = non_positive(source, &[&cone.radius, &cone.height]) {
+            return Err(SceneError::at(path, source, offset, format!("cones[{}]: radius and height must be positive", i)));
+        }
+        world.add(Primitive::new_cone(to_vec(cone.base), axis, *cone.radius.get_ref(), *cone.height.get_ref(), sweep, mat));
+    }
+
+    for (i, tri) in desc.triangles.iter().enumerate() {
+        let mat = lookup(format!("triangles[{}].material", i), &tri.material)?;
+        let vertices = [to_vec(tri.vertices[0]), to_vec(tri.vertices[1]), to_vec(tri.vertices[2])];
+        let normals = match tri.normals {
+            Some(n) => [to_vec(n[0]).unit_vector(), to_vec(n[1]).unit_vector(), to_vec(n[2]).unit_vector()],
+            None => [(vertices[1] - vertices[0]).cross(vertices[2] - vertices[0]).unit_vector(); 3],
+        };
+        world.add(Primitive::new_triangle(vertices, normals, mat));
+    }
+
+    //Each mesh is loaded once per material and placed as an instance, so
+    //repeating a mesh costs no more memory than using it once
+    let mut loaded_meshes: HashMap<(PathBuf, Option<&str>), Arc<FlatBvh>> = HashMap::new();
+    for (i, mesh) in desc.meshes.iter().enumerate() {
+        let file = scene_dir.join(mesh.file.get_ref());
+        let key = (file.clone(), mesh.material.as_ref().map(|name| name.get_ref().as_str()));
+        let object = match loaded_meshes.get(&key) {
+            Some(object) => Arc::clone(object),
+            None => {
+                let (models, obj_materials) = try_import_obj(&file.to_string_lossy()).map_err(|err| {
+                    SceneError::at(path, source, mesh.file.start(), format!("meshes[{}].file: cannot load '{}': {}", i, file.display(), err))
+                })?;
+
+                let mut mesh_list = TraceableList::new();
+                match &mesh.material {
+                    Some(name) => {
+                        let mat = lookup(format!("meshes[{}].material", i), name)?;
+                        for model in models.iter() {
+                            mesh_list.add_mesh(&model.mesh, &mat);
+                        }
+                    }
+                    None => mesh_list.add_obj(models, obj_materials, file.parent().unwrap_or(scene_dir)).map_err(|err| {
+                        SceneError::at(path, source, mesh.file.start(), format!("meshes[{}].file: cannot load texture: {}", i, err))
+                    })?,
+                }
+                if mesh_list.empty() {
+                    return Err(SceneError::at(path, source, mesh.file.start(), format!("meshes[{}].file: '{}' has no faces", i, file.display())));
+                }
+
+                let object = Arc::new(FlatBvh::new(mesh_list));
+                loaded_meshes.insert(key, Arc::clone(&object));
+                object
+            }
+        };
+
+        let keyframes = match &mesh.keyframes {
+            None => None,
+            Some(_) if mesh.translate.is_some() || mesh.rotate.is_some() || mesh.scale.is_some() => {
+                return Err(SceneError::at(path, source, mesh.file.start(),
+                                          format!("meshes[{}]: give either keyframes or translate, rotate and scale", i)));
+            }
+            Some(keyframes) if keyframes.is_empty() => {
+                return Err(SceneError::at(path, source, mesh.file.start(), format!("meshes[{}].keyframes: there must be at least one", i)));
+            }
+            Some(keyframes) => Some(keyframes.iter().enumerate().map(|(j, keyframe)| {
+                let scale = keyframe.scale.as_ref();
+                mesh_keyframe(keyframe.time, keyframe.translate, keyframe.rotate, scale.map(|scale| *scale.get_ref())).ok_or_else(|| {
+                    SceneError::at(path, source, scale_start(source, scale, &mesh.file), format!("meshes[{}].keyframes[{}].scale: scale factors must not be zero", i, j))
+                })
+            }).collect::<Result<Vec<Keyframe>, SceneError>>()?),
+        };
+        match keyframes {
+            Some(keyframes) => world.add(Primitive::new_animated_instance(object, AnimatedTransform::new(keyframes))),
+            None => {
+                let scale = mesh.scale.as_ref();
+                let transform = mesh_transform(mesh.translate, mesh.rotate, scale.map(|scale| *scale.get_ref())).ok_or_else(|| {
+                    SceneError::at(path, source, scale_start(source, scale, &mesh.file), format!("meshes[{}].scale: scale factors must not be zero", i))
+                })?;
+                world.add(Primitive::new_instance(object, transform));
+            }
+        }
+    }
+
+    for (i, medium) in desc.media.iter().enumerate() {
+        let mat = lookup(format!("media[{}].material", i), &medium.material)?;
+        if *medium.density.get_ref() <= 0.0 {
+            return Err(SceneError::at(path, source, float_start(source, &medium.density), format!("media[{}]: density must be positive", i)));
+        }
+        let desc = medium.boundary.get_ref();
+        desc.check().map_err(|err| SceneError::at(path, source, medium.boundary.start(), format!("media[{}].boundary: {}", i, err)))?;
+        let mut boundary = TraceableList::new();
+        match (desc.center, desc.radius, desc.min, desc.max, &desc.file) {
+            (Some(center), Some(radius), ..) => boundary.add(Primitive::new_sphere(to_vec(center), radius, mat.clone())),
+            (_, _, Some(min), Some(max), _) => boundary.add(Primitive::new_cuboid(to_vec(min), to_vec(max), mat.clone())),
+            (.., Some(name)) => {
+                let file = scene_dir.join(name.get_ref());
+                let (models, _) = try_import_obj(&file.to_string_lossy()).map_err(|err| {
+                    SceneError::at(path, source, name.start(), format!("media[{}].boundary.file: cannot load '{}': {}", i, file.display(), err))
+                })?;
+                let mut mesh_list = TraceableList::new();
+                for model in models.iter() {
+                    mesh_list.add_mesh(&model.mesh, &mat);
+                }
+                if mesh_list.empty() {
+                    return Err(SceneError::at(path, source, name.start(), format!("media[{}].boundary.file: '{}' has no faces", i, file.display())));
+                }
+                let scale = desc.scale.as_ref();
+                let transform = mesh_transform(desc.translate, desc.rotate, scale.map(|scale| *scale.get_ref())).ok_or_else(|| {
+                    SceneError::at(path, source, scale_start(source, scale, name), format!("media[{}].boundary.scale: scale factors must not be zero", i))
+                })?;
+                boundary.add(Primitive::new_instance(Arc::new(FlatBvh::new(mesh_list)), transform));
+            }
+            _ => unreachable!("BoundaryDesc::check makes sure every type has its fields"),
+        }
+        world.add(Primitive::new_constant_medium(Arc::new(FlatBvh::new(boundary)), *medium.density.get_ref(), mat));
+    }
+
+    for (i, volume) in desc.volumes.iter().enumerate() {
+        let mat = lookup(format!("volumes[{}].material", i), &volume.material)?;
+        if *volume.scale.get_ref() <= 0.0 {
+            return Err(SceneError::at(path, source, float_start(source, &volume.scale), format!("volumes[{}]: scale must be positive", i)));
+        }
+        if let Some(absorption) = volume.absorption.as_ref().filter(|absorption| !(0.0..=1.0).contains(absorption.get_ref())) {
+            return Err(SceneError::at(path, source, float_start(source, absorption), format!("volumes[{}]: absorption must be between 0 and 1", i)));
+        }
+        let density = volume.density.get_ref().to_field(scene_dir)
+            .map_err(|err| SceneError::at(path, source, volume.density.start(), format!("volumes[{}].density: {}", i, err)))?;
+        let temperature = match &volume.temperature {
+            Some(temperature) => Some(temperature.get_ref().to_field(scene_dir)
+                .map_err(|err| SceneError::at(path, source, temperature.start(), format!("volumes[{}].temperature: {}", i, err)))?),
+            None => None,
+        };
+        let absorption = volume.absorption.as_ref().map(|absorption| *absorption.get_ref()).unwrap_or(0.0);
+        let emission = volume.emission.map(to_vec).unwrap_or_default();
+        let medium = HeterogeneousMedium::new(density, *volume.scale.get_ref(), mat).with_emission(absorption, emission, temperature);
+        world.add(Primitive::HeterogeneousMedium(medium));
+    }
+
+    let fog = match &desc.fog {
+        Some(fog) if *fog.density.get_ref() < 0.0 => {
+            return Err(SceneError::at(path, source, float_start(source, &fog.density), "fog: density must not be negative".to_string()));
+        }
+        Some(FogDesc{g: Some(g), ..}) if *g.get_ref() <= -1.0 || *g.get_ref() >= 1.0 => {
+            return Err(SceneError::at(path, source, float_start(source, g), "fog: g must be between -1 and 1".to_string()));
+        }
+        Some(fog) => {
+            let g = fog.g.as_ref().map(|g| *g.get_ref()).unwrap_or(0.0);
+            let phase_function = Material::new_henyey_greenstein(Texture::Constant(to_vec(fog.albedo)), g);
+            Some(Fog::new(*fog.density.get_ref(), fog.height.unwrap_or(f64::INFINITY), phase_function))
+        }
+        None => None,
+    };
+
+    let cam = &desc.camera;
+    let mut camera = CameraSettings::new(to_vec(cam.look_from), to_vec(cam.look_at));
+    camera.v_up = cam.v_up.map(to_vec).unwrap_or(camera.v_up);
+    camera.v_fov = cam.v_fov.unwrap_or(camera.v_fov);
+    camera.aperture = cam.aperture.unwrap_or(camera.aperture);
+    camera.focus_dist = cam.focus_dist.unwrap_or(camera.focus_dist);
+    if let Some(shutter) = &cam.shutter {
+        let [open, close] = *shutter.get_ref();
+        if close < open {
+            return Err(SceneError::at(path, source, shutter.start(), "camera.shutter: the shutter must close after it opens".to_string()));
+        }
+        camera.shutter_open = open;
+        camera.shutter_close = close;
+    }
+
+    let background = match &desc.background {
+        Some(background) => background.to_background(scene_dir).map_err(|err| SceneError::new(path, format!("background: {}", err)))?,
+        None => Background::default(),
+    };
+    if world.empty() {
+        return Err(SceneError::new(path, "the scene has nothing in it to render".to_string()));
+    }
+    Ok(SceneFile{world, background, fog, camera})
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ray::*;
+
+    #[test]
+    fn test_parse_example(){
+        let source = include_str!("../resources/scenes/light_test.toml");
+        let scene = parse_scene(source, "light_test.toml").unwrap();
+        assert_eq!(scene.world.len(), 3);
+        assert_eq!(scene.background, Background::Constant(Color::new(0.05, 0.05, 0.05)));
+        assert_eq!(scene.camera.look_from, Point3::new(26.0, 3.0, 6.0));
+        assert_eq!(scene.camera.v_fov, 20.0);
+    }
+
+    #[test]
+    fn test_textures(){
+        let source = "[camera]\nlook_from = [0.0, 0.0, 0.0]\nlook_at = [1.0, 0.0, 0.0]\n\n\
+                      [materials.floor]\ntype = 'lambertian'\n\
+                      albedo = { type = 'checker', even = [1.0, 1.0, 1.0], odd = { type = 'noise', scale = 4.0 }, scale = 2.0 }\n\n\
+                      [materials.steel]\ntype = 'metal'\nalbedo = [0.7, 0.7, 0.7]\n\n\
+                      [materials.brushed]\ntype = 'conductor'\nalbedo = [0.9, 0.6, 0.3]\nroughness = 0.2\nroughness_v = 0.6\n\n\
+                      [[spheres]]\ncenter = [2.0, 0.0, 0.0]\nradius = 1.0\nmaterial = 'floor'\n";
+        assert!(parse_scene(source, "textures.toml").is_ok());
+
+        //Missing image files are reported against the material
+        let source = "[camera]\nlook_from = [0.0, 0.0, 0.0]\nlook_at = [1.0, 0.0, 0.0]\n\n\
+                      [materials.earth]\ntype = 'lambertian'\nalbedo = { type = 'image', file = 'missing.png' }\n";
+        let err = parse_scene(source, "bad.toml").err().unwrap();
+        assert!(err.message.starts_with("materials.earth: cannot load 'missing.png'"));
+    }
+
+    #[test]
+    fn test_environment(){
+        let dir = std::env::temp_dir().join(format!("ray_trace_environment_scene_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        exr::prelude::write_rgb_file(dir.join("sky.exr"), 4, 2, |_, _| (1.0f32, 1.0f32, 1.0f32)).unwrap();
+        let source = "background = { file = 'sky.exr', intensity = 2.0, rotation = 90.0 }\n\n\
+                      [camera]\nlook_from = [0.0, 0.0, 0.0]\nlook_at = [1.0, 0.0, 0.0]\n\n\
+                      [materials.white]\ntype = 'lambertian'\nalbedo = [0.7, 0.7, 0.7]\n\n\
+                      [[spheres]]\ncenter = [2.0, 0.0, 0.0]\nradius = 1.0\nmaterial = 'white'\n";
+        let scene = parse_scene(source, &dir.join("sky.toml").to_string_lossy());
+        fs::remove_dir_all(&dir).unwrap();
+        let background = scene.unwrap().background;
+        assert!(matches!(background, Background::Map(_)));
+        assert_eq!(background.value(Vec3::new(0.0, 0.0, 1.0)), Color::new(2.0, 2.0, 2.0));
+
+        //Missing maps are reported against the background
+        let source = "background = { file = 'missing.hdr' }\n\n[camera]\nlook_from = [0.0, 0.0, 0.0]\nlook_at = [1.0, 0.0, 0.0]\n";
+        let err = parse_scene(source, "bad.toml").err().unwrap();
+        assert!(err.message.starts_with("background: cannot load 'missing.hdr'"));
+    }
+
+    #[test]
+    fn test_mesh_instances(){
+        let dir = std::env::temp_dir().join(format!("ray_trace_instances_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("tri.obj"), "v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 3\n").unwrap();
+        let source = "[camera]\nlook_from = [0.0, 0.0, 5.0]\nlook_at = [0.0, 0.0, 0.0]\n\n\
+                      [[meshes]]\nfile = 'tri.obj'\n\n\
+                      [[meshes]]\nfile = 'tri.obj'\ntranslate = [2.0, 0.0, 0.0]\n";
+        let scene = parse_scene(source, &dir.join("instances.toml").to_string_lossy()).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        //Both copies share the one loaded mesh
+        assert_eq!(scene.world.len(), 2);
+        match (scene.world.get(0), scene.world.get(1)) {
+            (Primitive::Instance(a), Primitive::Instance(b)) => {
+                assert!(Arc::ptr_eq(a.object(), b.object()));
+                assert_eq!(b.bounding_box().unwrap().min().x(), 2.0 - 0.001);
+            }
+            _ => panic!("Meshes should be added as instances"),
+        }
+    }
+
+    #[test]
+    fn test_motion(){
+        let dir = std::env::temp_dir().join(format!("ray_trace_motion_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("tri.obj"), "v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 3\n").unwrap();
+        let header = "[camera]\nlook_from = [0.0, 0.0, 5.0]\nlook_at = [0.0, 0.0, 0.0]\nshutter = [0.0, 0.5]\n\n\
+                      [materials.white]\ntype = 'lambertian'\nalbedo = [0.7, 0.7, 0.7]\n\n";
+        let parse = |shapes: &str| parse_scene(&format!("{}{}", header, shapes), &dir.join("motion.toml").to_string_lossy());
+        let scene = parse("[[spheres]]\ncenter = [0.0, 0.0, 0.0]\ncenter1 = [0.0, 2.0, 0.0]\nradius = 1.0\nmaterial = 'white'\n\n\
+                           [[meshes]]\nfile = 'tri.obj'\n\
+                           keyframes = [{time = 0.0}, {time = 1.0, translate = [3.0, 0.0, 0.0], rotate = [0.0, 0.0, 90.0]}]\n");
+
+        //Case 1: Moving shapes, with boxes covering their motion
+        let scene = scene.unwrap();
+        assert_eq!((scene.camera.shutter_open, scene.camera.shutter_close), (0.0, 0.5));
+        assert!(matches!(scene.world.get(0), Primitive::MovingSphere(_)));
+        assert_eq!(scene.world.get(0).bounding_box().unwrap().max().y(), 3.0);
+        match scene.world.get(1) {
+            Primitive::Instance(instance) => {
+                let p = instance.transform(1.0).point(Point3::new(1.0, 0.0, 0.0));
+                assert!((p - Point3::new(3.0, 1.0, 0.0)).length() < 1e-9);
+                assert!(instance.bounding_box().unwrap().max().x() >= 3.0);
+            }
+            _ => panic!("Meshes should be added as instances"),
+        }
+
+        //Case 2: Mistakes
+        let cases = [("[[spheres]]\ncenter = [0.0, 0.0, 0.0]\ntime = [0.0, 1.0]\nradius = 1.0\nmaterial = 'white'\n",
+                      "spheres[0]: time is only used with center1"),
+                     ("[[meshes]]\nfile = 'tri.obj'\nscale = 2.0\nkeyframes = [{time = 0.0}]\n",
+                      "meshes[0]: give either keyframes or translate, rotate and scale"),
+                     ("[[meshes]]\nfile = 'tri.obj'\nkeyframes = []\n",
+                      "meshes[0].keyframes: there must be at least one"),
+                     ("[[meshes]]\nfile = 'tri.obj'\nkeyframes = [{time = 0.0}, {time = 1.0, scale = 0.0}]\n",
+                      "meshes[0].keyframes[1].scale: scale factors must not be zero")];
+        for (shapes, message) in cases.iter() {
+            assert_eq!(&parse(shapes).err().unwrap().message, message);
+        }
+        let err = parse(cases[0].0).err().unwrap();
+        assert_eq!((err.line, err.column), (Some(12), Some(8)));
+        let err = parse(cases[3].0).err().unwrap();
+        assert_eq!((err.line, err.column), (Some(12), Some(49)));
+        let err = parse("[[meshes]]\nfile = 'tri.obj'\nscale = [1.0, 0.0, 1.0]\n").err().unwrap();
+        assert_eq!((err.message.as_str(), err.line, err.column), ("meshes[0].scale: scale factors must not be zero", Some(12), Some(9)));
+        let source = "[camera]\nlook_from = [0.0, 0.0, 5.0]\nlook_at = [0.0, 0.0, 0.0]\nshutter = [1.0, 0.5]\n";
+        let err = parse_scene(source, "bad.toml").err().unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(err.to_string(), "bad.toml:4:11: camera.shutter: the shutter must close after it opens");
+    }
+
+    #[test]
+    fn test_media_and_fog(){
+        let dir = std::env::temp_dir().join(format!("ray_trace_media_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("tetra.obj"), "v 0 0 0\nv 1 0 0\nv 0 1 0\nv 0 0 1\nf 1 3 2\nf 1 2 4\nf 1 4 3\nf 2 3 4\n").unwrap();
+        let header = "[camera]\nlook_from = [0.0, 0.0, 5.0]\nlook_at = [0.0, 0.0, 0.0]\n\n\
+                      [materials.smoke]\ntype = 'isotropic'\nalbedo = [0.7, 0.7, 0.7]\ng = 0.3\n\n";
+        //Top-level keys like fog must come before any table
+        let parse = |top: &str, rest: &str| parse_scene(&format!("{}{}{}", top, header, rest), &dir.join("media.toml").to_string_lossy());
+        let scene = parse("fog = {density = 0.1, albedo = [1.0, 1.0, 1.0], height = 2.0}\n",
+                          "[[media]]\nboundary = {type = 'sphere', center = [0.0, 1.0, 0.0], radius = 1.0}\ndensity = 0.5\nmaterial = 'smoke'\n\n\
+                           [[media]]\nboundary = {type = 'box', min = [2.0, 0.0, 0.0], max = [3.0, 1.0, 1.0]}\ndensity = 2.0\nmaterial = 'smoke'\n\n\
+                           [[media]]\nboundary = {type = 'mesh', file = 'tetra.obj', translate = [-3.0, 0.0, 0.0]}\ndensity = 1.0\nmaterial = 'smoke'\n");
+
+        //Case 1: Media take the bounds of their boundaries, and the fog is kept apart from the world
+        let scene = scene.unwrap();
+        assert_eq!(scene.world.len(), 3);
+        for i in 0..3 {
+            assert!(matches!(scene.world.get(i), Primitive::ConstantMedium(_)));
+        }
+        let bb = scene.world.get(1).bounding_box().unwrap();
+        assert_eq!((bb.min(), bb.max()), (Point3::new(2.0, 0.0, 0.0), Point3::new(3.0, 1.0, 1.0)));
+        assert!((scene.world.get(2).bounding_box().unwrap().min().x() + 3.0).abs() < 1e-3);
+        let fog = scene.fog.unwrap();
+        assert!(matches!(fog.phase_function(), Material::Isotropic(_)));
+        let r = Ray::new(Point3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0));
+        assert!((fog.transmittance(&r, f64::INFINITY) - (-0.2f64).exp()).abs() < 1e-12);
+
+        //Case 2: Mistakes
+        let cases = [("", "[[media]]\nboundary = {type = 'sphere', center = [0.0, 0.0, 0.0], radius = 1.0}\ndensity = 0.0\nmaterial = 'smoke'\n",
+                      "media[0]: density must be positive".to_string(), 12, 11),
+                     ("", "[[media]]\nboundary = {type = 'mesh', file = 'tetra.obj', scale = 0.0}\ndensity = 1.0\nmaterial = 'smoke'\n",
+                      "media[0].boundary.scale: scale factors must not be zero".to_string(), 11, 56),
+                     ("", "[[media]]\nboundary = {type = 'sphere', center = [0.0, 0.0, 0.0], radius = 1.0, file = 'tetra.obj'}\ndensity = 1.0\nmaterial = 'smoke'\n",
+                      "media[0].boundary: a sphere boundary does not take file".to_string(), 11, 12),
+                     ("", "[[media]]\nboundary = {type = 'box', min = [0.0, 0.0, 0.0]}\ndensity = 1.0\nmaterial = 'smoke'\n",
+                      "media[0].boundary: a box boundary needs max".to_string(), 11, 12),
+                     ("", "[[media]]\nboundary = {type = 'mesh', file = 'media.toml'}\ndensity = 1.0\nmaterial = 'smoke'\n",
+                      format!("media[0].boundary.file: '{}' has no faces", dir.join("media.toml").display()), 11, 35),
+                     ("fog = {density = -1.0, albedo = [1.0, 1.0, 1.0]}\n", "",
+                      "fog: density must not be negative".to_string(), 1, 18),
+                     ("fog = {density = 1.0, albedo = [1.0, 1.0, 1.0], g = 1.0}\n", "",
+                      "fog: g must be between -1 and 1".to_string(), 1, 53)];
+        fs::write(dir.join("media.toml"), "[camera]\n").unwrap();
+        for (top, rest, message, line, column) in cases.iter() {
+            let err = parse(top, rest).err().unwrap();
+            assert_eq!((&err.message, err.line, err.column), (message, Some(*line), Some(*column)));
+        }
+        let err = parse("", "[materials.bad]\ntype = 'isotropic'\nalbedo = [0.7, 0.7, 0.7]\ng = -1.5\n").err().unwrap();
+        assert_eq!(err.message, "materials.bad: g must be between -1 and 1");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_volumes(){
+        let dir = std::env::temp_dir().join(format!("ray_trace_volumes_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let unit = Aabb::new(Point3::new(0.0, 0.0, 0.0), Point3::new(1.0, 1.0, 1.0));
+        VoxelGrid::new([2, 2, 2], vec![0.0, 1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0], unit).save(dir.join("smoke.grid")).unwrap();
+        let header = "[camera]\nlook_from = [0.0, 0.0, 5.0]\nlook_at = [0.0, 0.0, 0.0]\n\n\
+                      [materials.smoke]\ntype = 'isotropic'\nalbedo = [0.7, 0.7, 0.7]\n\n";
+        let parse = |rest: &str| parse_scene(&format!("{}{}", header, rest), &dir.join("volumes.toml").to_string_lossy());
+        let scene = parse("[[volumes]]\ndensity = {type = 'grid', file = 'smoke.grid', min = [-1.0, 0.0, -1.0], max = [1.0, 3.0, 1.0]}\n\
+                           scale = 2.0\nmaterial = 'smoke'\n\n\
+                           [[volumes]]\ndensity = {type = 'noise', min = [2.0, 0.0, 0.0], max = [4.0, 2.0, 2.0], frequency = 3.0}\n\
+                           scale = 5.0\nmaterial = 'smoke'\nabsorption = 0.5\nemission = [8.0, 3.0, 1.0]\n\
+                           temperature = {type = 'grid', file = 'smoke.grid', min = [2.0, 0.0, 0.0], max = [4.0, 2.0, 2.0]}\n");
+
+        //Case 1: Volumes are bounded by their density fields
+        let scene = scene.unwrap();
+        assert_eq!(scene.world.len(), 2);
+        assert!(matches!(scene.world.get(0), Primitive::HeterogeneousMedium(_)));
+        let bb = scene.world.get(0).bounding_box().unwrap();
+        assert_eq!((bb.min(), bb.max()), (Point3::new(-1.0, 0.0, -1.0), Point3::new(1.0, 3.0, 1.0)));
+        let bb = scene.world.get(1).bounding_box().unwrap();
+        assert_eq!((bb.min(), bb.max()), (Point3::new(2.0, 0.0, 0.0), Point3::new(4.0, 2.0, 2.0)));
+
+        //Case 2: Mistakes
+        let volume = |density: &str, extra: &str| {
+            format!("[[volumes]]\ndensity = {}\nscale = 1.0\nmaterial = 'smoke'\n{}", density, extra)
+        };
+        let noise = "{type = 'noise', min = [0.0, 0.0, 0.0], max = [1.0, 1.0, 1.0]}";
+        let cases = [(volume(noise, "absorption = 1.5\n"), "volumes[0]: absorption must be between 0 and 1".to_string(), 13, 14),
+                     (volume("{type = 'noise', min = [0.0, 0.0, 0.0], max = [1.0, 0.0, 1.0]}", ""),
+                      "volumes[0].density: max must be above min on every axis".to_string(), 10, 11),
+                     (volume("{type = 'noise', min = [0.0, 0.0, 0.0], max = [1.0, 1.0, 1.0], frequency = 0.0}", ""),
+                      "volumes[0].density: frequency must be positive".to_string(), 10, 11),
+                     (volume(noise, "temperature = {type = 'grid', file = 'volumes.toml', min = [0.0, 0.0, 0.0], max = [1.0, 1.0, 1.0]}\n"),
+                      format!("volumes[0].temperature: cannot load '{}': not a voxel grid file", dir.join("volumes.toml").display()), 13, 15),
+                     (volume(noise, "").replace("scale = 1.0", "scale = 0.0"), "volumes[0]: scale must be positive".to_string(), 11, 9)];
+        fs::write(dir.join("volumes.toml"), "[camera]\n").unwrap();
+        for (rest, message, line, column) in cases.iter() {
+            let err = parse(rest).err().unwrap();
+            assert_eq!((&err.message, err.line, err.column), (message, Some(*line), Some(*column)));
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_quads_and_boxes(){
+        let source = "[camera]\nlook_from = [0.0, 0.0, 5.0]\nlook_at = [0.0, 0.0, 0.0]\n\n\
+                      [materials.white]\ntype = 'lambertian'\nalbedo = [0.7, 0.7, 0.7]\n\n\
+                      [[quads]]\ncorner = [-1.0, -1.0, 0.0]\nu = [2.0, 0.0, 0.0]\nv = [0.0, 2.0, 1.0]\nmaterial = 'white'\n\n\
+                      [[boxes]]\nmin = [2.0, 0.0, 0.0]\nmax = [3.0, 1.0, 2.0]\nmaterial = 'white'\n";
+        let scene = parse_scene(source, "shapes.toml").unwrap();
+        assert_eq!(scene.world.len(), 2);
+        assert!(matches!(scene.world.get(0), Primitive::Quad(_)));
+        let bb = scene.world.get(1).bounding_box().unwrap();
+        assert_eq!((bb.min(), bb.max()), (Point3::new(2.0, 0.0, 0.0), Point3::new(3.0, 1.0, 2.0)));
+
+        //A quad must have some area
+        let source = "[camera]\nlook_from = [0.0, 0.0, 5.0]\nlook_at = [0.0, 0.0, 0.0]\n\n\
+                      [materials.white]\ntype = 'lambertian'\nalbedo = [0.7, 0.7, 0.7]\n\n\
+                      [[quads]]\ncorner = [0.0, 0.0, 0.0]\nu = [1.0, 0.0, 0.0]\nv = [2.0, 0.0, 0.0]\nmaterial = 'white'\n";
+        let err = parse_scene(source, "bad.toml").err().unwrap();
+        assert_eq!(err.to_string(), "bad.toml:12:5: quads[0]: u and v must be non-zero and not parallel");
+    }
+
+    #[test]
+    fn test_cylinders_disks_and_cones(){
+        let source = "[camera]\nlook_from = [0.0, 0.0, 5.0]\nlook_at = [0.0, 0.0, 0.0]\n\n\
+                      [materials.white]\ntype = 'lambertian'\nalbedo = [0.7, 0.7, 0.7]\n\n\
+                      [[cylinders]]\nbase = [0.0, 0.0, 0.0]\nradius = 1.0\nheight = 2.0\ncapped = true\nmaterial = 'white'\n\n\
+                      [[disks]]\ncenter = [0.0, 0.0, 0.0]\naxis = [0.0, 0.0, 1.0]\nradius = 2.0\ninner_radius = 1.0\nsweep = 90.0\nmaterial = 'white'\n\n\
+                      [[cones]]\nbase = [1.0, 0.0, 0.0]\naxis = [0.0, -1.0, 0.0]\nradius = 0.5\nheight = 3.0\nmaterial = 'white'\n";
+        let scene = parse_scene(source, "shapes.toml").unwrap();
+        assert_eq!(scene.world.len(), 3);
+        assert!(matches!(scene.world.get(0), Primitive::Cylinder(_)));
+        assert!(matches!(scene.world.get(1), Primitive::Disk(_)));
+        let bb = scene.world.get(2).bounding_box().unwrap();
+        assert_eq!((bb.min(), bb.max()), (Point3::new(0.5, -3.0, -0.5), Point3::new(1.5, 0.0, 0.5)));
+
+        //Case 1: Bad sizes, axes and sweeps
+        let cases = [("[[cylinders]]\nbase = [0.0, 0.0, 0.0]\nradius = 1.0\nheight = 0.0\nmaterial = 'white'\n",
+                      "bad.toml:12:10: cylinders[0]: radius and height must be positive"),
+                     ("[[disks]]\ncenter = [0.0, 0.0, 0.0]\nradius = 1.0\ninner_radius = 1.0\nmaterial = 'white'\n",
+                      "bad.toml:11:10: disks[0]: radius must be above inner_radius, which must not be negative"),
+                     ("[[cones]]\nbase = [0.0, 0.0, 0.0]\naxis = [0.0, 0.0, 0.0]\nradius = 1.0\nheight = 1.0\nmaterial = 'white'\n",
+                      "bad.toml:11:8: cones[0]: axis must be non-zero"),
+                     ("[[cones]]\nbase = [0.0, 0.0, 0.0]\nradius = 1.0\nheight = 1.0\nsweep = 400.0\nmaterial = 'white'\n",
+                      "bad.toml:13:9: cones[0]: sweep must be above 0 and at most 360 degrees")];
+        for (shape, message) in cases.iter() {
+            let source = format!("[camera]\nlook_from = [0.0, 0.0, 5.0]\nlook_at = [0.0, 0.0, 0.0]\n\n\
+                                  [materials.white]\ntype = 'lambertian'\nalbedo = [0.7, 0.7, 0.7]\n\n{}", shape);
+            let err = parse_scene(&source, "bad.toml").err().unwrap();
+            assert_eq!(&err.to_string(), message);
+        }
+    }
+
+    #[test]
+    fn test_mesh_transform(){
+        let transform = mesh_transform(Some([1.0, 0.0, 0.0]), Some([0.0, 90.0, 0.0]), Some(ScaleDesc::Uniform(2.0))).unwrap();
+        let p = transform.point(Vec3::new(1.0, 0.0, 0.0));
+        assert!((p - Point3::new(1.0, 0.0, -2.0)).near_zero());
+        let n = transform.normal(Vec3::new(0.0, 0.0, 1.0)).unit_vector();
+        assert!((n - Vec3::new(1.0, 0.0, 0.0)).near_zero());
+
+        assert!(mesh_transform(None, None, Some(ScaleDesc::Axes([1.0, 0.0, 1.0]))).is_none());
+    }
+
+    #[test]
+    fn test_errors(){
+        //Case 1: Syntax error
+        let err = parse_scene("[camera\nlook_from = [0.0, 0.0, 0.0]", "bad.toml").err().unwrap();
+        assert_eq!(err.line, Some(1));
+
+        //Case 2: Wrong type for a field
+        let source = "[camera]\nlook_from = [0.0, 0.0, 0.0]\nlook_at = 'origin'\n";
+        let err = parse_scene(source, "bad.toml").err().unwrap();
+        assert_eq!(err.line, Some(3));
+        assert!(err.message.contains("camera.look_at"));
+
+        //Case 3: Unknown material
+        let source = "[camera]\nlook_from = [0.0, 0.0, 0.0]\nlook_at = [1.0, 0.0, 0.0]\n\n[[spheres]]\ncenter = [0.0, 0.0, 0.0]\nradius = 1.0\nmaterial = 'chrome'\n";
+        let err = parse_scene(source, "bad.toml").err().unwrap();
+        assert_eq!(err.to_string(), "bad.toml:8:12: spheres[0].material: unknown material 'chrome'");
+
+        //Case 4: Missing mesh file
+        let source = "[camera]\nlook_from = [0.0, 0.0, 0.0]\nlook_at = [1.0, 0.0, 0.0]\n\n[[meshes]]\nfile = 'missing.obj'\n";
+        let err = parse_scene(source, "bad.toml").err().unwrap();
+        assert_eq!(err.line, Some(6));
+        assert!(err.message.starts_with("meshes[0].file"));
+
+        //Case 5: Nothing to render
+        let source = "[camera]\nlook_from = [0.0, 0.0, 0.0]\nlook_at = [1.0, 0.0, 0.0]\n";
+        let err = parse_scene(source, "empty.toml").err().unwrap();
+        assert_eq!(err.to_string(), "empty.toml: the scene has nothing in it to render");
+    }
+}
@@ -6,9 +6,42 @@ use crate::traceable::*;
 use crate::material::*;
 use crate::rect::*;
 use crate::util::*;
+use crate::rng::*;
+use crate::bvh::*;
 use crate::triangle::*;
+use crate::camera::*;
+use crate::transform::*;
+
+use std::path::Path;
+use std::sync::Arc;
+
+pub type Scene = (TraceableList, Color, Point3, Point3);
+
+//The model obj_test loads
+pub const CAR_OBJ: &str = "C:/Users/Charlie/Ray_Tracer/ray-tracer/car.obj";
+
+pub const NAMES: [&str; 7] = ["sphere_world", "light_test", "triangle_test", "triangle_bb_test", "obj_test", "mesh_test", "instance_test"];
+
+//Looks up a built-in scene by the name of its function
+pub fn by_name(name: &str) -> Option<fn() -> Scene> {
+    match name {
+        "sphere_world" => Some(sphere_world),
+        "light_test" => Some(light_test),
+        "triangle_test" => Some(triangle_test),
+        "triangle_bb_test" => Some(triangle_bb_test),
+        "obj_test" => Some(obj_test),
+        "mesh_test" => Some(mesh_test),
+        "instance_test" => Some(instance_test),
+        _ => None
+    }
+}
+
+//The camera every built-in scene is framed for
+pub fn default_camera(look_from: Point3, look_at: Point3, aspect_ratio: f64) -> Camera {
+    CameraSettings::new(look_from, look_at).to_camera(aspect_ratio)
+}
 
-pub fn sphere_world() -> (TraceableList, Color, Point3, Point3) {
+pub fn sphere_world() -> Scene {
     let mut world = TraceableList::new();
     let background = Color::new(0.7, 0.8, 1.0);
     let look_from = Point3::new(13.0, 2.0, 3.0);
@@ -18,19 +51,21 @@ pub fn sphere_world() -> (TraceableList, Color, Point3, Point3) {
     let ground = Primitive::new_sphere(Point3::new(0.0,-1000.0,0.0), 1000.0, mat_ground);
     world.add(ground);
 
+    //A fixed seed, so the scene is the same every time it is built
+    let mut rng = Rng::new(0);
     for a in -11..12{
         for b in -11..12{
-            let choose_mat = rand_double(0.0, 1.0);
-            let center = Point3::new(a as f64 + 0.9*rand_double(0.0, 1.0), 0.2, b as f64 + 0.9*rand_double(0.0, 1.0));
+            let choose_mat = rng.f64();
+            let center = Point3::new(a as f64 + 0.9*rng.f64(), 0.2, b as f64 + 0.9*rng.f64());
 
             if choose_mat < 0.6{
-                let albedo = Color::rand(0.0, 1.0).elementwise_mult(&Color::rand(0.0, 1.0));
+                let albedo = Color::rand(&mut rng, 0.0, 1.0).elementwise_mult(&Color::rand(&mut rng, 0.0, 1.0));
                 let sphere_material = Material::new_lambertian(albedo);
                 let sphere = Primitive::new_sphere(center, 0.2, sphere_material);
                 world.add(sphere);
             } else if choose_mat < 0.9{
-                let albedo = Color::rand(0.5, 1.0);
-                let fuzz = rand_double(0.0, 0.5);
+                let albedo = Color::rand(&mut rng, 0.5, 1.0);
+                let fuzz = rand_double(&mut rng, 0.0, 0.5);
                 let sphere_material = Material::new_metal(albedo, fuzz);
                 let sphere = Primitive::new_sphere(center, 0.2, sphere_material);
                 world.add(sphere);
@@ -57,7 +92,7 @@ pub fn sphere_world() -> (TraceableList, Color, Point3, Point3) {
     (world, background, look_from, look_at)
 }
 
-pub fn light_test() -> (TraceableList, Color, Point3, Point3) {
+pub fn light_test() -> Scene {
     let mut world = TraceableList::new();
     let background = Color::new(0.9, 0.9, 0.9);
     let look_from = Point3::new(26.0, 3.0, 6.0);
@@ -77,7 +112,7 @@ pub fn light_test() -> (TraceableList, Color, Point3, Point3) {
 
 }
 
-pub fn triangle_test() -> (TraceableList, Color, Point3, Point3) {
+pub fn triangle_test() -> Scene {
     let mut world = TraceableList::new();
     let background = Color::new(0.9, 0.9, 0.9);
     let look_from = Point3::new(0.0, 2.0, 26.0);
@@ -99,7 +134,7 @@ pub fn triangle_test() -> (TraceableList, Color, Point3, Point3) {
 
 }
 
-pub fn triangle_bb_test() -> (TraceableList, Color, Point3, Point3) {
+pub fn triangle_bb_test() -> Scene {
     let mut world = TraceableList::new();
     let background = Color::new(0.9, 0.9, 0.9);
     let look_from = Point3::new(0.0, 2.0, 26.0);
@@ -122,7 +157,7 @@ pub fn triangle_bb_test() -> (TraceableList, Color, Point3, Point3) {
 
 }
 
-pub fn obj_test() -> (TraceableList, Color, Point3, Point3) {
+pub fn obj_test() -> Scene {
     let mut world = TraceableList::new(); 
     let background = Color::new(0.9, 0.9, 0.9);
     let look_from = Point3::new(-20.0, 5.0, 20.0);
@@ -131,17 +166,17 @@ pub fn obj_test() -> (TraceableList, Color, Point3, Point3) {
     let mut mesh = TraceableList::new(); 
     let mat = Material::new_lambertian(Color::new(0.4, 0.2, 0.1));
     let ground = Primitive::Sphere(Sphere::new(Point3::new(0.0, -1000.0, 0.0), 1000.0, mat));
-    let (mut models, materials) = import_obj("C:/Users/Charlie/Ray_Tracer/ray-tracer/car.obj");
+    let (models, materials) = import_obj(CAR_OBJ);
     let diff_light = Material::new_diffuse_light(Color::new(4.0,4.0,4.0));
     let rect = Primitive::Rect(Rect::new(RectAxes::XY, -4.0, -2.0, 1.0, 8.0, 4.0, diff_light));
-    mesh.add_obj(models, materials);
+    mesh.add_obj(models, materials, Path::new(CAR_OBJ).parent().unwrap()).expect("Failed to load the car textures");
     mesh.add(ground);
     //mesh.add(rect);
     
     (mesh, background, look_from, look_at)
 }
 
-pub fn mesh_test() -> (TraceableList, Color, Point3, Point3) {
+pub fn mesh_test() -> Scene {
     let mut world = TraceableList::new(); 
     let background = Color::new(0.9, 0.9, 0.9);
     let look_from = Point3::new(26.0, 10.0, 10.0);
@@ -190,8 +225,35 @@ pub fn mesh_test() -> (TraceableList, Color, Point3, Point3) {
 
 
     let test = vec!(test_1, test_2, test_3);
-    world.add_obj(test, None);
+    world.add_obj(test, None, Path::new(".")).expect("Meshes without materials load no textures");
 
     (world, background, look_from, look_at)
 
-}
\ No newline at end of file
+}
+
+//A hundred turned and scaled copies of one small object
+pub fn instance_test() -> Scene {
+    let mut world = TraceableList::new();
+    let background = Color::new(0.7, 0.8, 1.0);
+    let look_from = Point3::new(20.0, 12.0, 20.0);
+    let look_at = Point3::new(0.0, 0.0, 0.0);
+
+    let mat = Material::new_lambertian(Color::new(0.4, 0.2, 0.1));
+    world.add(Primitive::new_sphere(Point3::new(0.0, -1000.0, 0.0), 1000.0, mat));
+
+    let mut object = TraceableList::new();
+    object.add(Primitive::new_rect(RectAxes::XY, -0.5, 0.5, 0.0, 1.0, 0.0, Material::new_metal(Color::new(0.7, 0.6, 0.5), 0.1)));
+    object.add(Primitive::new_sphere(Point3::new(0.0, 1.3, 0.0), 0.3, Material::new_lambertian(Color::new(0.2, 0.4, 0.8))));
+    let object = Arc::new(FlatBvh::new(object));
+
+    for i in 0..10{
+        for j in 0..10{
+            let transform = Transform::identity().scale(Vec3::new(1.0, 1.0, 1.0) * (1.0 + 0.1*((i + j) % 5) as f64))
+                                                 .rotate_y(deg_to_rad(9.0*(10*i + j) as f64))
+                                                 .translate(Vec3::new(2.0*i as f64 - 9.0, 0.0, 2.0*j as f64 - 9.0));
+            world.add(Primitive::new_instance(Arc::clone(&object), transform));
+        }
+    }
+
+    (world, background, look_from, look_at)
+}
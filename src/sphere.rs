@@ -3,14 +3,30 @@ use crate::ray::*;
 use crate::traceable::*;
 use crate::bvh::*;
 use crate::material::*;
+use crate::light::*;
+use crate::rng::*;
 
-#[derive (Copy, Clone)]
+use std::f64::consts::PI;
+
+#[derive (Clone)]
 pub struct Sphere {
     center: Point3,
     radius: f64,
     material: Material
 }
 
+//A sphere moving in a straight line from center0 at time0 to center1 at
+//time1. It rests at either end outside that interval.
+#[derive (Clone)]
+pub struct MovingSphere {
+    center0: Point3,
+    center1: Point3,
+    time0: f64,
+    time1: f64,
+    radius: f64,
+    material: Material
+}
+
 impl Sphere{
     pub fn new(cen: Point3, rad: f64, mat: Material) -> Sphere{
         Sphere{center: cen, radius: rad, material: mat}
@@ -19,39 +35,115 @@ impl Sphere{
     pub fn center(&self) -> Point3{
         self.center
     }
+
+    //Longitude and latitude of a point on the unit sphere, both in [0, 1].
+    //u is measured around y starting from -x, and v runs from -y up to +y.
+    pub fn get_uv(p: Point3) -> (f64, f64){
+        let theta = (-p.y()).clamp(-1.0, 1.0).acos();
+        let phi = (-p.z()).atan2(p.x()) + PI;
+        (phi / (2.0*PI), theta / PI)
+    }
 }
 
-impl Hit for Sphere{
-    fn hit(&self, r:&Ray, t_min: f64, t_max: f64) -> Option<(HitRecord, &Material)> {
-        let oc = r.origin() - self.center;
-        let a = r.direction().length_squared();
-        let half_b = oc.dot(r.direction());
-        let c = oc.length_squared() - self.radius*self.radius;
-        let discriminant = half_b*half_b - a*c;
-        if discriminant < 0.0{
-            None
-        }else{
-            let sqrtd = discriminant.sqrt();
-            let mut root = (-half_b - sqrtd)/a;
+impl MovingSphere{
+    pub fn new(center0: Point3, center1: Point3, time0: f64, time1: f64, rad: f64, mat: Material) -> MovingSphere{
+        MovingSphere{center0, center1, time0, time1, radius: rad, material: mat}
+    }
+
+    pub fn center(&self, time: f64) -> Point3{
+        if self.time1 <= self.time0{
+            return self.center0;
+        }
+        let f = ((time - self.time0) / (self.time1 - self.time0)).clamp(0.0, 1.0);
+        self.center0 + f*(self.center1 - self.center0)
+    }
+}
+
+fn hit_sphere<'a>(center: Point3, radius: f64, material: &'a Material, r: &Ray, t_min: f64, t_max: f64) -> Option<(HitRecord, &'a Material)> {
+    let oc = r.origin() - center;
+    let a = r.direction().length_squared();
+    let half_b = oc.dot(r.direction());
+    let c = oc.length_squared() - radius*radius;
+    let discriminant = half_b*half_b - a*c;
+    if discriminant < 0.0{
+        None
+    }else{
+        let sqrtd = discriminant.sqrt();
+        let mut root = (-half_b - sqrtd)/a;
+        if root < t_min || t_max < root{
+            root = (-half_b + sqrtd)/a;
             if root < t_min || t_max < root{
-                root = (-half_b + sqrtd)/a;
-                if root < t_min || t_max < root{
-                    return None
-                }
+                return None
             }
-
-            let t = root;
-            let p = r.at(t);
-            let outward_normal = (p - self.center)/self.radius;
-            let new_rec = HitRecord::new(p, outward_normal, root, *r, Vec3::default());
-            Some((new_rec, &self.material))
         }
+
+        let t = root;
+        let p = r.at(t);
+        let outward_normal = (p - center)/radius;
+        let mut new_rec = HitRecord::new(p, outward_normal, root, *r, Vec3::default());
+        let (u, v) = Sphere::get_uv(outward_normal);
+        new_rec.set_uv(u, v);
+        Some((new_rec, material))
+    }
+}
+
+fn sphere_box(center: Point3, radius: f64) -> Aabb {
+    Aabb::new(center - Vec3::new(radius, radius, radius),
+              center + Vec3::new(radius, radius, radius))
+}
+
+impl Hit for Sphere{
+    fn hit(&self, r:&Ray, t_min: f64, t_max: f64, _: &mut Rng) -> Option<(HitRecord, &Material)> {
+        hit_sphere(self.center, self.radius, &self.material, r, t_min, t_max)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(sphere_box(self.center, self.radius))
+    }
+}
+
+impl Hit for MovingSphere{
+    fn hit(&self, r:&Ray, t_min: f64, t_max: f64, _: &mut Rng) -> Option<(HitRecord, &Material)> {
+        let (mut rec, mat) = hit_sphere(self.center(r.time()), self.radius, &self.material, r, t_min, t_max)?;
+        rec.light_sampled = false;
+        Some((rec, mat))
     }
 
+    //The sphere moves in a straight line, so the boxes at either end hold
+    //every position between them
     fn bounding_box(&self) -> Option<Aabb> {
-        let output_box = Aabb::new(self.center - Vec3::new(self.radius, self.radius, self.radius),
-                                   self.center + Vec3::new(self.radius, self.radius, self.radius));
-        Some(output_box)
+        Some(Aabb::surrounding_box(sphere_box(self.center0, self.radius), sphere_box(self.center1, self.radius)))
+    }
+}
+
+impl Surface for Sphere{
+    fn area(&self) -> f64{
+        4.0*PI*self.radius*self.radius
+    }
+
+    fn sample_surface(&self, rng: &mut Rng) -> (Point3, Vec3){
+        let normal = Vec3::rand_unit_vec(rng);
+        (self.center + self.radius*normal, normal)
+    }
+
+    fn material(&self) -> &Material{
+        &self.material
+    }
+}
+
+//Moving lights are not sampled directly, and their hits are marked so that
+//light found by bouncing into them is not weighted down
+impl Surface for MovingSphere{
+    fn area(&self) -> f64{
+        0.0
+    }
+
+    fn sample_surface(&self, _: &mut Rng) -> (Point3, Vec3){
+        (self.center0, Vec3::default())
+    }
+
+    fn material(&self) -> &Material{
+        &self.material
     }
 }
 
@@ -72,16 +164,17 @@ mod tests {
 
     #[test]
     fn test_hit(){
+        let mut rng = Rng::new(0);
 
         //Case 1: Intersection from outside of sphere
         let center = Vec3::new(0.0, 0.0, 0.0);
         let radius = 5.0;
         let mat = Material::Lambertian(Lambertian::default());
-        let s = Sphere::new(center, radius, mat);
+        let s = Sphere::new(center, radius, mat.clone());
         let r = Ray::new(Vec3::new(-10.0, 0.0, 0.0), Vec3::new( 1.0, 0.0, 0.0));
         let t_min = 0.0;
         let t_max = 100.0;
-        let rec_wrapper = s.hit(&r, t_min, t_max);
+        let rec_wrapper = s.hit(&r, t_min, t_max, &mut rng);
         assert!(rec_wrapper.is_some());
         let (rec, _) = rec_wrapper.unwrap();
         assert_eq!(rec.t(), 5.0);
@@ -91,7 +184,7 @@ mod tests {
 
         //Case 2: Intersection from inside of sphere
         let r = Ray::new(Vec3::new(1.0, 0.0, 0.0), Vec3::new( -2.0, 0.0, 0.0));
-        let rec_wrapper = s.hit(&r, t_min, t_max);
+        let rec_wrapper = s.hit(&r, t_min, t_max, &mut rng);
         assert!(rec_wrapper.is_some());
         let rec = rec_wrapper.unwrap().0;
         assert_eq!(rec.t(), 3.0);
@@ -101,7 +194,7 @@ mod tests {
 
         //Case 3: Intersection tangent to sphere
         let r = Ray::new(Vec3::new(-5.0, 5.0, 0.0), Vec3::new( 0.0, -1.0, 0.0));
-        let rec_wrapper = s.hit(&r, t_min, t_max);
+        let rec_wrapper = s.hit(&r, t_min, t_max, &mut rng);
         assert!(rec_wrapper.is_some());
         let (rec, _) = rec_wrapper.unwrap();
         assert_eq!(rec.t(), 5.0);
@@ -112,7 +205,7 @@ mod tests {
         //Case 4: Intersection of inverted sphere (negative radius)
         let s = Sphere::new(center, -radius, mat);
         let r = Ray::new(Vec3::new(0.0, -10.0, 0.0), Vec3::new( 0.0, 1.0, 0.0));
-        let rec_wrapper = s.hit(&r, t_min, t_max);
+        let rec_wrapper = s.hit(&r, t_min, t_max, &mut rng);
         assert!(rec_wrapper.is_some());
         let (rec, _) = rec_wrapper.unwrap();
         assert_eq!(rec.t(), 5.0);
@@ -131,4 +224,40 @@ mod tests {
         assert_eq!(bb.min(), Point3::new(-5.0, -8.0, -3.0));
         assert_eq!(bb.max(), Point3::new(5.0, 2.0, 7.0));
     } 
+
+    #[test]
+    fn test_moving_sphere(){
+        let mut rng = Rng::new(0);
+        let mat = Material::Lambertian(Lambertian::default());
+        let s = MovingSphere::new(Point3::new(0.0, 0.0, 0.0), Point3::new(4.0, 0.0, 0.0), 1.0, 2.0, 1.0, mat);
+
+        //Case 1: The center moves over the interval and rests outside it
+        assert_eq!(s.center(1.5), Point3::new(2.0, 0.0, 0.0));
+        assert_eq!(s.center(0.0), Point3::new(0.0, 0.0, 0.0));
+        assert_eq!(s.center(3.0), Point3::new(4.0, 0.0, 0.0));
+
+        //Case 2: Hits depend on the ray's time
+        let r = Ray::new_at_time(Point3::new(2.0, 0.0, 10.0), Vec3::new(0.0, 0.0, -1.0), 1.5);
+        let (rec, _) = s.hit(&r, 0.0, 100.0, &mut rng).unwrap();
+        assert_eq!(rec.t(), 9.0);
+        assert_eq!(rec.normal(), Vec3::new(0.0, 0.0, 1.0));
+        assert!(!rec.light_sampled);
+        let r = Ray::new_at_time(Point3::new(2.0, 0.0, 10.0), Vec3::new(0.0, 0.0, -1.0), 1.0);
+        assert!(s.hit(&r, 0.0, 100.0, &mut rng).is_none());
+
+        //Case 3: The box covers both ends
+        let bb = s.bounding_box().unwrap();
+        assert_eq!(bb.min(), Point3::new(-1.0, -1.0, -1.0));
+        assert_eq!(bb.max(), Point3::new(5.0, 1.0, 1.0));
+        assert_eq!(s.area(), 0.0);
+    }
+
+    #[test]
+    fn test_get_uv(){
+        let close = |a: (f64, f64), b: (f64, f64)| (a.0 - b.0).abs() < 1e-12 && (a.1 - b.1).abs() < 1e-12;
+        assert!(close(Sphere::get_uv(Point3::new(1.0, 0.0, 0.0)), (0.5, 0.5)));
+        assert!(close(Sphere::get_uv(Point3::new(0.0, 1.0, 0.0)), (0.5, 1.0)));
+        assert!(close(Sphere::get_uv(Point3::new(0.0, 0.0, 1.0)), (0.25, 0.5)));
+        assert!(close(Sphere::get_uv(Point3::new(-1.0, 0.0, 0.0)), (0.0, 0.5)));
+    }
 }
\ No newline at end of file
@@ -0,0 +1,287 @@
+use crate::vec::*;
+use crate::rng::*;
+
+use image::ImageResult;
+
+use std::path::Path;
+use std::sync::Arc;
+
+//Where a surface's color comes from. Textures that hold a lot of data are
+//shared between the materials that use them.
+#[derive (Clone, PartialEq, Debug)]
+pub enum Texture{
+    Constant(Color),
+    Image(Arc<ImageTexture>),
+    Checker(Arc<Checker>),
+    Noise(Arc<Noise>),
+}
+
+//How image lookups outside [0, 1] are brought back onto the image
+#[derive (Copy, Clone, PartialEq, Debug)]
+pub enum WrapMode{
+    Repeat,
+    MirroredRepeat,
+    ClampToEdge,
+}
+
+//Linear colors, stored row by row from the top of the image
+#[derive (Clone, PartialEq, Debug)]
+pub struct ImageTexture{
+    width: usize,
+    height: usize,
+    pixels: Vec<Color>,
+    wrap: WrapMode,
+}
+
+//A solid checkerboard of cubes with sides 1/scale, so it needs no UVs
+#[derive (Clone, PartialEq, Debug)]
+pub struct Checker{
+    even: Texture,
+    odd: Texture,
+    scale: f64,
+}
+
+//Marble-like veins made from Perlin turbulence
+#[derive (Clone, PartialEq, Debug)]
+pub struct Noise{
+    perlin: Perlin,
+    scale: f64,
+}
+
+const POINT_COUNT: usize = 256;
+
+#[derive (Clone, PartialEq, Debug)]
+pub struct Perlin{
+    random_vectors: Vec<Vec3>,
+    perm_x: Vec<usize>,
+    perm_y: Vec<usize>,
+    perm_z: Vec<usize>,
+}
+
+impl Default for Texture{
+    fn default() -> Texture{
+        Texture::Constant(Color::default())
+    }
+}
+
+impl Texture{
+    //Color at surface coordinates (u, v) of the point p
+    pub fn value(&self, u: f64, v: f64, p: Point3) -> Color{
+        match self{
+            Texture::Constant(color) => *color,
+            Texture::Image(image) => image.value(u, v),
+            Texture::Checker(checker) => checker.value(u, v, p),
+            Texture::Noise(noise) => noise.value(p),
+        }
+    }
+
+    pub fn new_image(image: ImageTexture) -> Texture{
+        Texture::Image(Arc::new(image))
+    }
+
+    pub fn new_checker(even: Texture, odd: Texture, scale: f64) -> Texture{
+        Texture::Checker(Arc::new(Checker{even, odd, scale}))
+    }
+
+    pub fn new_noise(scale: f64) -> Texture{
+        Texture::Noise(Arc::new(Noise{perlin: Perlin::new(), scale}))
+    }
+}
+
+impl ImageTexture{
+    pub fn new(width: usize, height: usize, pixels: Vec<Color>, wrap: WrapMode) -> ImageTexture{
+        assert_eq!(pixels.len(), width*height, "The pixel count does not match the image dimensions");
+        ImageTexture{width, height, pixels, wrap}
+    }
+
+    //Loads any format the image crate can read. Pixel values are squared
+    //to undo the gamma of 2 that images are written with.
+    pub fn open<P: AsRef<Path>>(path: P, wrap: WrapMode) -> ImageResult<ImageTexture>{
+        let image = image::open(path)?.to_rgb8();
+        let (width, height) = image.dimensions();
+        let pixels = image.pixels()
+                          .map(|pixel| {
+                              let [r, g, b] = pixel.0;
+                              let linear = |c: u8| (c as f64 / 255.0).powi(2);
+                              Color::new(linear(r), linear(g), linear(b))
+                          })
+                          .collect();
+        Ok(ImageTexture::new(width as usize, height as usize, pixels, wrap))
+    }
+
+    fn texel(&self, x: i64, y: i64) -> Color{
+        let x = wrap(x, self.width as i64, self.wrap);
+        let y = wrap(y, self.height as i64, self.wrap);
+        self.pixels[y as usize * self.width + x as usize]
+    }
+
+    //Bilinear filtering between the four nearest pixel centres. v runs
+    //from the bottom of the image to the top.
+    pub fn value(&self, u: f64, v: f64) -> Color{
+        let x = u * self.width as f64 - 0.5;
+        let y = (1.0 - v) * self.height as f64 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as i64, y0 as i64);
+
+        let top = (1.0 - fx) * self.texel(x0, y0) + fx * self.texel(x0 + 1, y0);
+        let bottom = (1.0 - fx) * self.texel(x0, y0 + 1) + fx * self.texel(x0 + 1, y0 + 1);
+        (1.0 - fy) * top + fy * bottom
+    }
+}
+
+fn wrap(coord: i64, size: i64, mode: WrapMode) -> i64{
+    match mode{
+        WrapMode::Repeat => coord.rem_euclid(size),
+        WrapMode::ClampToEdge => coord.max(0).min(size - 1),
+        WrapMode::MirroredRepeat => {
+            let coord = coord.rem_euclid(2*size);
+            if coord < size {coord} else {2*size - 1 - coord}
+        }
+    }
+}
+
+impl Checker{
+    pub fn value(&self, u: f64, v: f64, p: Point3) -> Color{
+        let cell = (self.scale*p.x()).floor() + (self.scale*p.y()).floor() + (self.scale*p.z()).floor();
+        if (cell as i64).rem_euclid(2) == 0{
+            self.even.value(u, v, p)
+        } else{
+            self.odd.value(u, v, p)
+        }
+    }
+}
+
+impl Noise{
+    pub fn value(&self, p: Point3) -> Color{
+        let shade = 0.5*(1.0 + (self.scale*p.z() + 10.0*self.perlin.turbulence(p, 7)).sin());
+        Color::new(shade, shade, shade)
+    }
+}
+
+impl Perlin{
+    //The lattice is built from a fixed seed, so noise looks the same in every render
+    pub fn new() -> Perlin{
+        let mut rng = Rng::new(0);
+        let random_vectors = (0..POINT_COUNT).map(|_| Vec3::rand(&mut rng, -1.0, 1.0).unit_vector()).collect();
+        Perlin{random_vectors, perm_x: Perlin::permutation(&mut rng), perm_y: Perlin::permutation(&mut rng), perm_z: Perlin::permutation(&mut rng)}
+    }
+
+    fn permutation(rng: &mut Rng) -> Vec<usize>{
+        let mut p: Vec<usize> = (0..POINT_COUNT).collect();
+        rng.shuffle(&mut p);
+        p
+    }
+
+    //Smoothly varying noise in roughly [-1, 1]
+    pub fn noise(&self, p: Point3) -> f64{
+        let (u, v, w) = (p.x() - p.x().floor(), p.y() - p.y().floor(), p.z() - p.z().floor());
+        let (i, j, k) = (p.x().floor() as i64, p.y().floor() as i64, p.z().floor() as i64);
+
+        let mut accum = 0.0;
+        let (uu, vv, ww) = (u*u*(3.0 - 2.0*u), v*v*(3.0 - 2.0*v), w*w*(3.0 - 2.0*w));
+        for di in 0..2{
+            for dj in 0..2{
+                for dk in 0..2{
+                    let index = self.perm_x[((i + di) & 255) as usize]
+                              ^ self.perm_y[((j + dj) & 255) as usize]
+                              ^ self.perm_z[((k + dk) & 255) as usize];
+                    let (fi, fj, fk) = (di as f64, dj as f64, dk as f64);
+                    let weight = Vec3::new(u - fi, v - fj, w - fk);
+                    accum += (fi*uu + (1.0 - fi)*(1.0 - uu))
+                           * (fj*vv + (1.0 - fj)*(1.0 - vv))
+                           * (fk*ww + (1.0 - fk)*(1.0 - ww))
+                           * self.random_vectors[index].dot(weight);
+                }
+            }
+        }
+        accum
+    }
+
+    //Sum of depth octaves of noise, each at double the frequency and half
+    //the weight of the last
+    pub fn turbulence(&self, p: Point3, depth: i32) -> f64{
+        let mut accum = 0.0;
+        let mut p = p;
+        let mut weight = 1.0;
+        for _ in 0..depth{
+            accum += weight*self.noise(p);
+            weight *= 0.5;
+            p = 2.0*p;
+        }
+        accum.abs()
+    }
+}
+
+impl Default for Perlin{
+    fn default() -> Perlin{
+        Perlin::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_image(wrap: WrapMode) -> ImageTexture{
+        //Black and white on the top row, red and blue below
+        let pixels = vec![Color::new(0.0, 0.0, 0.0), Color::new(1.0, 1.0, 1.0),
+                          Color::new(1.0, 0.0, 0.0), Color::new(0.0, 0.0, 1.0)];
+        ImageTexture::new(2, 2, pixels, wrap)
+    }
+
+    #[test]
+    fn test_image_value(){
+        let image = test_image(WrapMode::ClampToEdge);
+
+        //Case 1: Pixel centres
+        assert_eq!(image.value(0.25, 0.75), Color::new(0.0, 0.0, 0.0));
+        assert_eq!(image.value(0.75, 0.25), Color::new(0.0, 0.0, 1.0));
+
+        //Case 2: Halfway between all four
+        assert_eq!(image.value(0.5, 0.5), Color::new(0.5, 0.25, 0.5));
+
+        //Case 3: Clamped outside the image
+        assert_eq!(image.value(-3.0, 0.75), Color::new(0.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn test_wrap(){
+        assert_eq!(wrap(-1, 4, WrapMode::Repeat), 3);
+        assert_eq!(wrap(9, 4, WrapMode::Repeat), 1);
+        assert_eq!(wrap(-1, 4, WrapMode::MirroredRepeat), 0);
+        assert_eq!(wrap(5, 4, WrapMode::MirroredRepeat), 2);
+        assert_eq!(wrap(-1, 4, WrapMode::ClampToEdge), 0);
+        assert_eq!(wrap(9, 4, WrapMode::ClampToEdge), 3);
+
+        let image = test_image(WrapMode::Repeat);
+        assert_eq!(image.value(1.25, 0.75), image.value(0.25, 0.75));
+    }
+
+    #[test]
+    fn test_checker(){
+        let even = Texture::Constant(Color::new(1.0, 1.0, 1.0));
+        let odd = Texture::Constant(Color::new(0.0, 0.0, 0.0));
+        let checker = Texture::new_checker(even, odd, 2.0);
+        assert_eq!(checker.value(0.0, 0.0, Point3::new(0.1, 0.1, 0.1)), Color::new(1.0, 1.0, 1.0));
+        assert_eq!(checker.value(0.0, 0.0, Point3::new(0.6, 0.1, 0.1)), Color::new(0.0, 0.0, 0.0));
+        assert_eq!(checker.value(0.0, 0.0, Point3::new(-0.1, 0.1, 0.1)), Color::new(0.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn test_noise(){
+        let perlin = Perlin::new();
+
+        //Zero on the lattice and continuous in between
+        assert_eq!(perlin.noise(Point3::new(3.0, -2.0, 7.0)), 0.0);
+        let a = perlin.noise(Point3::new(0.5, 0.5, 0.5));
+        let b = perlin.noise(Point3::new(0.5, 0.5, 0.5001));
+        assert!((a - b).abs() < 1e-3);
+
+        let noise = Texture::new_noise(4.0);
+        for i in 0..20{
+            let shade = noise.value(0.0, 0.0, Point3::new(i as f64 * 0.37, 1.3, -0.2)).x();
+            assert!((0.0..=1.0).contains(&shade));
+        }
+    }
+}
@@ -1,4 +1,3 @@
-extern crate fastrand;
 
 use crate::vec::*;
 use crate::ray::*;
@@ -6,12 +5,18 @@ use crate::bvh::*;
 use crate::material::*;
 use crate::triangle::*;
 use crate::primitive::*;
+use crate::texture::*;
+use crate::rng::*;
 use crate::enum_dispatch::*;
 
 use std::clone;
 use std::ops::Index;
 use core::cmp::Ordering;
 use std::convert::TryFrom;
+use std::sync::Arc;
+use std::collections::HashMap;
+use std::path::Path;
+use image::ImageResult;
 
 #[derive (Copy, Clone)]
 pub struct HitRecord{
@@ -21,6 +26,13 @@ pub struct HitRecord{
     pub t: f64,
     pub front_face: bool,
     pub p_err: Vec3,
+    //Surface coordinates used to look up textures
+    pub u: f64,
+    pub v: f64,
+    //Whether LightList could have chosen this point. Only surfaces with an
+    //area at the top of the scene are sampled, so hits inside instances
+    //and media clear it.
+    pub light_sampled: bool,
 }
 
 #[derive (Default, Clone)]
@@ -36,7 +48,7 @@ pub enum TraceResult{
 
 impl HitRecord{
     pub fn new(p: Point3, normal: Vec3, t: f64, r: Ray, p_err: Vec3) -> HitRecord{
-        let mut rec = HitRecord{p, normal, t, front_face: true, p_err};
+        let mut rec = HitRecord{p, normal, t, front_face: true, p_err, u: 0.0, v: 0.0, light_sampled: true};
         rec.set_face_normal(&r, &normal);
         rec      
     }
@@ -50,6 +62,11 @@ impl HitRecord{
         }
     }
 
+    pub fn set_uv(&mut self, u: f64, v: f64){
+        self.u = u;
+        self.v = v;
+    }
+
     pub fn p(&self) -> Vec3{
         self.p
     }
@@ -83,7 +100,7 @@ impl TraceableList{
     }
 
     pub fn get(&self, index: usize) -> Primitive {
-        self.list[index]
+        self.list[index].clone()
     }
 
     pub fn len(&self) -> usize {
@@ -149,54 +166,106 @@ impl TraceableList{
         TraceableList{list: self.list.split_off(at)}
     }
 
+    pub fn iter(&self) -> std::slice::Iter<'_, Primitive>{
+        self.list.iter()
+    }
+
+    //Splits into the primitives for which f is true and those for which it is false
+    pub fn partition<F>(self, f: F) -> (TraceableList, TraceableList)
+    where
+        F: FnMut(&Primitive) -> bool,
+    {
+        let (left, right) = self.list.into_iter().partition(f);
+        (TraceableList{list: left}, TraceableList{list: right})
+    }
+
     pub fn to_Bvh(self) -> BvhNode {
         BvhNode::new(self)
     }
 
-    pub fn add_obj(&mut self, models: Vec<tobj::Model>, mut materials_opt: Option<Vec<tobj::Material>>){
+    //Adds every model, colored by its MTL diffuse color or diffuse texture
+    //map. Texture paths are relative to texture_dir and each image is only
+    //loaded once.
+    pub fn add_obj(&mut self, models: Vec<tobj::Model>, materials_opt: Option<Vec<tobj::Material>>, texture_dir: &Path) -> ImageResult<()>{
+        let mut textures: HashMap<String, Texture> = HashMap::new();
         for  m in models.iter(){
            //if m.name == "wheel_fr_Circle.050_MAIN"{
                 let mesh = &m.mesh;
-                let pos = &mesh.positions;
-                let norms = &mesh.normals;
-                let model_color:Color;
+                let albedo: Texture;
                 match &materials_opt{
                     Some(mat) =>{
                         let mat_id = mesh.material_id.unwrap();
-                        model_color = Color::new(mat[mat_id].diffuse[0] as f64, mat[mat_id].diffuse[1] as f64, mat[mat_id].diffuse[2] as f64);
+                        let texture_file = &mat[mat_id].diffuse_texture;
+                        if texture_file.is_empty(){
+                            albedo = Texture::Constant(Color::new(mat[mat_id].diffuse[0] as f64, mat[mat_id].diffuse[1] as f64, mat[mat_id].diffuse[2] as f64));
+                        } else if let Some(texture) = textures.get(texture_file){
+                            albedo = texture.clone();
+                        } else{
+                            albedo = Texture::new_image(ImageTexture::open(texture_dir.join(texture_file), WrapMode::Repeat)?);
+                            textures.insert(texture_file.clone(), albedo.clone());
+                        }
                     }
                     None =>{
-                        model_color = Vec3::new(0.5, 0.5, 0.5);
+                        albedo = Texture::Constant(Vec3::new(0.5, 0.5, 0.5));
                     }
                 }
-                for face_indices in mesh.indices.chunks(3){
-                    let mut tri_vert = [Point3::default();3];
-                    let mut tri_norm = [Vec3::default(); 3];
-                    for vertex in 0..3{
-                        tri_vert[vertex] = Point3::new(pos[usize::try_from(face_indices[vertex]*3    ).unwrap()].into(),
-                                                    pos[usize::try_from(face_indices[vertex]*3 + 1).unwrap()].into(),
-                                                    pos[usize::try_from(face_indices[vertex]*3 + 2).unwrap()].into());
-                        tri_norm[vertex] = Vec3::new(norms[usize::try_from(face_indices[vertex]*3    ).unwrap()].into(),
-                                                    norms[usize::try_from(face_indices[vertex]*3 + 1).unwrap()].into(),
-                                                    norms[usize::try_from(face_indices[vertex]*3 + 2).unwrap()].into());
-                    }
+                self.add_mesh(mesh, &Material::new_textured_lambertian(albedo));
+            //}
+        }
+        Ok(())
+    }
 
-                    let tri = Triangle::new(tri_vert, tri_norm, Material::Lambertian(Lambertian::new(model_color)));
-                    self.add(Primitive::Triangle(tri));
+    //Adds every face of a triangulated mesh. Meshes without vertex
+    //normals are given flat face normals.
+    pub fn add_mesh(&mut self, mesh: &tobj::Mesh, mat: &Material){
+        let pos = &mesh.positions;
+        let norms = &mesh.normals;
+        let texcoords = &mesh.texcoords;
+        for face_indices in mesh.indices.chunks(3){
+            let mut tri_vert = [Point3::default();3];
+            let mut tri_norm = [Vec3::default(); 3];
+            let mut tri_texcoords = [[0.0, 0.0], [1.0, 0.0], [0.0, 1.0]];
+            for vertex in 0..3{
+                if !texcoords.is_empty(){
+                    let index = usize::try_from(face_indices[vertex]*2).unwrap();
+                    tri_texcoords[vertex] = [texcoords[index].into(), texcoords[index + 1].into()];
                 }
-            //}
+                tri_vert[vertex] = Point3::new(pos[usize::try_from(face_indices[vertex]*3    ).unwrap()].into(),
+                                            pos[usize::try_from(face_indices[vertex]*3 + 1).unwrap()].into(),
+                                            pos[usize::try_from(face_indices[vertex]*3 + 2).unwrap()].into());
+                if !norms.is_empty(){
+                    tri_norm[vertex] = Vec3::new(norms[usize::try_from(face_indices[vertex]*3    ).unwrap()].into(),
+                                                norms[usize::try_from(face_indices[vertex]*3 + 1).unwrap()].into(),
+                                                norms[usize::try_from(face_indices[vertex]*3 + 2).unwrap()].into());
+                }
+            }
+            if norms.is_empty(){
+                tri_norm = [(tri_vert[1] - tri_vert[0]).cross(tri_vert[2] - tri_vert[0]).unit_vector(); 3];
+            }
+
+            let tri = Triangle::new_textured(tri_vert, tri_norm, tri_texcoords, mat.clone());
+            self.add(Primitive::Triangle(tri));
         }
     }
 }
 
+impl IntoIterator for TraceableList{
+    type Item = Primitive;
+    type IntoIter = std::vec::IntoIter<Primitive>;
+
+    fn into_iter(self) -> Self::IntoIter{
+        self.list.into_iter()
+    }
+}
+
 impl Hit for TraceableList{
-    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<(HitRecord, &Material)> {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rng: &mut Rng) -> Option<(HitRecord, &Material)> {
         
         let mut closest_so_far = t_max;
         let mut hit_out: Option<(HitRecord, &Material)> = None;
 
         for traceable in &self.list{
-            if let Some(hit_temp) = traceable.hit(r, t_min, closest_so_far){
+            if let Some(hit_temp) = traceable.hit(r, t_min, closest_so_far, rng){
                 hit_out = Some(hit_temp);
                 closest_so_far = hit_temp.0.t;
             }
@@ -228,12 +297,13 @@ impl Hit for TraceableList{
 
 #[enum_dispatch]
 pub trait Hit: Send + Sync{
-    fn hit(&self ,r: &Ray, t_min: f64, t_max: f64) -> Option<(HitRecord, &Material)>;
+    //rng is the sample's generator, for things hit at random points such as media
+    fn hit(&self ,r: &Ray, t_min: f64, t_max: f64, rng: &mut Rng) -> Option<(HitRecord, &Material)>;
     fn bounding_box(&self) -> Option<Aabb>;
 
-    fn trace(&self, r: &Ray, t_min: f64, t_max: f64) -> TraceResult{
-        if let Some((hit_rec, mat)) = self.hit(r, t_min, t_max) {
-            if let Some((attenuation, scattered)) = mat.scatter(r, &hit_rec){
+    fn trace(&self, r: &Ray, t_min: f64, t_max: f64, rng: &mut Rng) -> TraceResult{
+        if let Some((hit_rec, mat)) = self.hit(r, t_min, t_max, rng) {
+            if let Some((attenuation, scattered)) = mat.scatter(r, &hit_rec, rng){
                 TraceResult::Scattered((mat.emit() + attenuation, scattered))
             } else{
                 TraceResult::Absorbed(mat.emit())
@@ -244,6 +314,18 @@ pub trait Hit: Send + Sync{
     }
 }
 
+//Lets several renders share one world, e.g. when the GUI restarts the
+//render after the camera moves
+impl<H: Hit> Hit for Arc<H>{
+    fn hit(&self ,r: &Ray, t_min: f64, t_max: f64, rng: &mut Rng) -> Option<(HitRecord, &Material)>{
+        (**self).hit(r, t_min, t_max, rng)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb>{
+        (**self).bounding_box()
+    }
+}
+
 
 #[cfg(test)]
 mod tests {
@@ -289,6 +371,7 @@ mod tests {
 
     #[test]
     fn test_hit(){
+        let mut rng = Rng::new(0);
         let mut list = TraceableList::new();
         let r = Ray::new(Vec3::new(-10.0, 0.0, 0.0), Vec3::new( 1.0, 0.0, 0.0));
         let t_min = 0.0;
@@ -300,7 +383,7 @@ mod tests {
         let mat = Material::Lambertian(Lambertian::default());
         let s = Primitive::Sphere(Sphere::new(center, radius, mat));
         list.add(s);
-        let hit = list.hit(&r, t_min, t_max);
+        let hit = list.hit(&r, t_min, t_max, &mut rng);
         assert!(hit.is_none());
 
         //Case 2: One intersection
@@ -309,7 +392,7 @@ mod tests {
         let mat = Material::Lambertian(Lambertian::default());
         let s = Primitive::Sphere(Sphere::new(center, radius, mat));
         list.add(s);
-        let hit = list.hit(&r, t_min, t_max);
+        let hit = list.hit(&r, t_min, t_max, &mut rng);
         assert!(hit.is_some());
         let (rec, _) = hit.unwrap();
         assert_eq!(rec.t, 5.0);  
@@ -320,7 +403,7 @@ mod tests {
         let mat = Material::Lambertian(Lambertian::default());
         let s = Primitive::Sphere(Sphere::new(center, radius, mat));
         list.add(s);
-        let hit = list.hit(&r, t_min, t_max);
+        let hit = list.hit(&r, t_min, t_max, &mut rng);
         assert!(hit.is_some());
         let (rec, _) = hit.unwrap();
         assert_eq!(rec.t, 3.0); 
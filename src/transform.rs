@@ -0,0 +1,440 @@
+use crate::vec::*;
+use crate::ray::*;
+use crate::bvh::*;
+
+use std::ops;
+
+//A 4x4 matrix acting on column vectors, stored row by row
+#[derive (Debug, Copy, Clone, PartialEq)]
+pub struct Mat4{
+    m: [[f64; 4]; 4],
+}
+
+//An affine transform kept together with its inverse, so rays can be taken
+//into object space and hits brought back out without inverting per ray.
+//Transforms built with the chained methods are applied in call order, e.g.
+//Transform::identity().scale(s).rotate_y(a).translate(t) scales first.
+#[derive (Debug, Copy, Clone, PartialEq)]
+pub struct Transform{
+    matrix: Mat4,
+    inverse: Mat4,
+}
+
+//Where something is at one moment: scaled, rotated anticlockwise about x,
+//then y, then z by the angles in radians, then translated
+#[derive (Debug, Copy, Clone, PartialEq)]
+pub struct Keyframe{
+    pub time: f64,
+    pub scale: Vec3,
+    pub rotate: Vec3,
+    pub translate: Vec3,
+}
+
+//A transform that moves between keyframes. The scale, rotation and
+//translation are interpolated separately, so objects turn rather than
+//shear between keyframes, and hold still before the first keyframe and
+//after the last.
+#[derive (Debug, Clone, PartialEq)]
+pub struct AnimatedTransform{
+    keyframes: Vec<Keyframe>,
+}
+
+impl Mat4{
+    pub fn new(m: [[f64; 4]; 4]) -> Mat4{
+        Mat4{m}
+    }
+
+    pub fn identity() -> Mat4{
+        Mat4::new([[1.0, 0.0, 0.0, 0.0],
+                   [0.0, 1.0, 0.0, 0.0],
+                   [0.0, 0.0, 1.0, 0.0],
+                   [0.0, 0.0, 0.0, 1.0]])
+    }
+
+    pub fn translation(offset: Vec3) -> Mat4{
+        Mat4::new([[1.0, 0.0, 0.0, offset.x()],
+                   [0.0, 1.0, 0.0, offset.y()],
+                   [0.0, 0.0, 1.0, offset.z()],
+                   [0.0, 0.0, 0.0, 1.0]])
+    }
+
+    pub fn scaling(factors: Vec3) -> Mat4{
+        Mat4::new([[factors.x(), 0.0, 0.0, 0.0],
+                   [0.0, factors.y(), 0.0, 0.0],
+                   [0.0, 0.0, factors.z(), 0.0],
+                   [0.0, 0.0, 0.0, 1.0]])
+    }
+
+    //Rotations are anticlockwise about the axis, with the angle in radians
+    pub fn rotation_x(angle: f64) -> Mat4{
+        let (s, c) = angle.sin_cos();
+        Mat4::new([[1.0, 0.0, 0.0, 0.0],
+                   [0.0, c, -s, 0.0],
+                   [0.0, s, c, 0.0],
+                   [0.0, 0.0, 0.0, 1.0]])
+    }
+
+    pub fn rotation_y(angle: f64) -> Mat4{
+        let (s, c) = angle.sin_cos();
+        Mat4::new([[c, 0.0, s, 0.0],
+                   [0.0, 1.0, 0.0, 0.0],
+                   [-s, 0.0, c, 0.0],
+                   [0.0, 0.0, 0.0, 1.0]])
+    }
+
+    pub fn rotation_z(angle: f64) -> Mat4{
+        let (s, c) = angle.sin_cos();
+        Mat4::new([[c, -s, 0.0, 0.0],
+                   [s, c, 0.0, 0.0],
+                   [0.0, 0.0, 1.0, 0.0],
+                   [0.0, 0.0, 0.0, 1.0]])
+    }
+
+    pub fn get(&self, row: usize, col: usize) -> f64{
+        self.m[row][col]
+    }
+
+    pub fn transpose(&self) -> Mat4{
+        let mut t = [[0.0; 4]; 4];
+        for (row, t_row) in t.iter_mut().enumerate(){
+            for (col, value) in t_row.iter_mut().enumerate(){
+                *value = self.m[col][row];
+            }
+        }
+        Mat4::new(t)
+    }
+
+    //Gauss-Jordan elimination with partial pivoting. None if the matrix is singular.
+    pub fn inverse(&self) -> Option<Mat4>{
+        let mut a = self.m;
+        let mut inv = Mat4::identity().m;
+        for col in 0..4{
+            let pivot = (col..4).max_by(|&i, &j| a[i][col].abs().partial_cmp(&a[j][col].abs()).unwrap())?;
+            if a[pivot][col].abs() < 1e-12{
+                return None;
+            }
+            a.swap(col, pivot);
+            inv.swap(col, pivot);
+
+            let scale = 1.0/a[col][col];
+            for k in 0..4{
+                a[col][k] *= scale;
+                inv[col][k] *= scale;
+            }
+            for row in 0..4{
+                if row != col{
+                    let factor = a[row][col];
+                    for k in 0..4{
+                        a[row][k] -= factor*a[col][k];
+                        inv[row][k] -= factor*inv[col][k];
+                    }
+                }
+            }
+        }
+        Some(Mat4::new(inv))
+    }
+
+    pub fn transform_point(&self, p: Point3) -> Point3{
+        let m = &self.m;
+        let x = m[0][0]*p.x() + m[0][1]*p.y() + m[0][2]*p.z() + m[0][3];
+        let y = m[1][0]*p.x() + m[1][1]*p.y() + m[1][2]*p.z() + m[1][3];
+        let z = m[2][0]*p.x() + m[2][1]*p.y() + m[2][2]*p.z() + m[2][3];
+        let w = m[3][0]*p.x() + m[3][1]*p.y() + m[3][2]*p.z() + m[3][3];
+        if w == 1.0{
+            Point3::new(x, y, z)
+        } else{
+            Point3::new(x, y, z)/w
+        }
+    }
+
+    //Directions ignore the translation
+    pub fn transform_vector(&self, v: Vec3) -> Vec3{
+        let m = &self.m;
+        Vec3::new(m[0][0]*v.x() + m[0][1]*v.y() + m[0][2]*v.z(),
+                  m[1][0]*v.x() + m[1][1]*v.y() + m[1][2]*v.z(),
+                  m[2][0]*v.x() + m[2][1]*v.y() + m[2][2]*v.z())
+    }
+}
+
+impl_op_ex!(* |lhs: &Mat4, rhs: &Mat4| -> Mat4 {
+    let mut m = [[0.0; 4]; 4];
+    for (row, m_row) in m.iter_mut().enumerate(){
+        for (col, value) in m_row.iter_mut().enumerate(){
+            *value = (0..4).map(|k| lhs.m[row][k]*rhs.m[k][col]).sum();
+        }
+    }
+    Mat4::new(m)
+});
+
+impl Default for Mat4{
+    fn default() -> Mat4{
+        Mat4::identity()
+    }
+}
+
+impl Transform{
+    //None if the matrix cannot be inverted
+    pub fn new(matrix: Mat4) -> Option<Transform>{
+        matrix.inverse().map(|inverse| Transform{matrix, inverse})
+    }
+
+    pub fn identity() -> Transform{
+        Transform{matrix: Mat4::identity(), inverse: Mat4::identity()}
+    }
+
+    //Applies other after self
+    pub fn then(&self, other: &Transform) -> Transform{
+        Transform{matrix: other.matrix*self.matrix, inverse: self.inverse*other.inverse}
+    }
+
+    pub fn translate(&self, offset: Vec3) -> Transform{
+        self.then(&Transform{matrix: Mat4::translation(offset), inverse: Mat4::translation(-offset)})
+    }
+
+    //Factors of zero leave the transform without an inverse
+    pub fn scale(&self, factors: Vec3) -> Transform{
+        let inverse_factors = Vec3::new(1.0/factors.x(), 1.0/factors.y(), 1.0/factors.z());
+        self.then(&Transform{matrix: Mat4::scaling(factors), inverse: Mat4::scaling(inverse_factors)})
+    }
+
+    pub fn rotate_x(&self, angle: f64) -> Transform{
+        self.then(&Transform{matrix: Mat4::rotation_x(angle), inverse: Mat4::rotation_x(-angle)})
+    }
+
+    pub fn rotate_y(&self, angle: f64) -> Transform{
+        self.then(&Transform{matrix: Mat4::rotation_y(angle), inverse: Mat4::rotation_y(-angle)})
+    }
+
+    pub fn rotate_z(&self, angle: f64) -> Transform{
+        self.then(&Transform{matrix: Mat4::rotation_z(angle), inverse: Mat4::rotation_z(-angle)})
+    }
+
+    pub fn matrix(&self) -> Mat4{
+        self.matrix
+    }
+
+    pub fn inverse(&self) -> Transform{
+        Transform{matrix: self.inverse, inverse: self.matrix}
+    }
+
+    pub fn point(&self, p: Point3) -> Point3{
+        self.matrix.transform_point(p)
+    }
+
+    pub fn vector(&self, v: Vec3) -> Vec3{
+        self.matrix.transform_vector(v)
+    }
+
+    //Normals go through the inverse transpose so they stay perpendicular
+    //to the transformed surface. The result is not normalised.
+    pub fn normal(&self, n: Vec3) -> Vec3{
+        self.inverse.transpose().transform_vector(n)
+    }
+
+    //The direction is not normalised, so a hit has the same t in both spaces
+    pub fn inverse_ray(&self, r: &Ray) -> Ray{
+        Ray::new_at_time(self.inverse.transform_point(r.origin()), self.inverse.transform_vector(r.direction()), r.time())
+    }
+
+    //A box holding all eight transformed corners of bb
+    pub fn bounding_box(&self, bb: Aabb) -> Aabb{
+        let mut min = Point3::new(f64::INFINITY, f64::INFINITY, f64::INFINITY);
+        let mut max = -min;
+        for corner in 0..8{
+            let x = if corner & 1 == 0 {bb.min().x()} else {bb.max().x()};
+            let y = if corner & 2 == 0 {bb.min().y()} else {bb.max().y()};
+            let z = if corner & 4 == 0 {bb.min().z()} else {bb.max().z()};
+            let p = self.point(Point3::new(x, y, z));
+            for axis in 0..3{
+                min[axis] = min[axis].min(p[axis]);
+                max[axis] = max[axis].max(p[axis]);
+            }
+        }
+        Aabb::new(min, max)
+    }
+}
+
+impl Default for Transform{
+    fn default() -> Transform{
+        Transform::identity()
+    }
+}
+
+impl Keyframe{
+    pub fn new(time: f64, scale: Vec3, rotate: Vec3, translate: Vec3) -> Keyframe{
+        Keyframe{time, scale, rotate, translate}
+    }
+
+    //Factors of zero leave the transform without an inverse
+    pub fn transform(&self) -> Transform{
+        Transform::identity().scale(self.scale)
+                             .rotate_x(self.rotate.x())
+                             .rotate_y(self.rotate.y())
+                             .rotate_z(self.rotate.z())
+                             .translate(self.translate)
+    }
+
+    fn lerp(&self, other: &Keyframe, f: f64) -> Keyframe{
+        Keyframe{time: self.time + f*(other.time - self.time),
+                 scale: self.scale + f*(other.scale - self.scale),
+                 rotate: self.rotate + f*(other.rotate - self.rotate),
+                 translate: self.translate + f*(other.translate - self.translate)}
+    }
+}
+
+impl AnimatedTransform{
+    //Keyframes may be given in any order, but there must be at least one
+    pub fn new(mut keyframes: Vec<Keyframe>) -> AnimatedTransform{
+        assert!(!keyframes.is_empty(), "An animated transform needs a keyframe");
+        keyframes.sort_by(|a, b| a.time.total_cmp(&b.time));
+        AnimatedTransform{keyframes}
+    }
+
+    pub fn keyframes(&self) -> &[Keyframe]{
+        &self.keyframes
+    }
+
+    pub fn keyframe_at(&self, time: f64) -> Keyframe{
+        let next = self.keyframes.partition_point(|keyframe| keyframe.time <= time);
+        if next == 0{
+            return self.keyframes[0];
+        }
+        if next == self.keyframes.len(){
+            return self.keyframes[next - 1];
+        }
+        let (a, b) = (&self.keyframes[next - 1], &self.keyframes[next]);
+        a.lerp(b, (time - a.time) / (b.time - a.time))
+    }
+
+    pub fn at(&self, time: f64) -> Transform{
+        self.keyframe_at(time).transform()
+    }
+
+    //A box holding bb wherever the transform takes it. Rotations move the
+    //corners along arcs, so the motion is followed in small steps and the
+    //box is padded by the furthest any corner moves in one step, which is
+    //more than it can stray from the straight line between steps.
+    pub fn bounding_box(&self, bb: Aabb) -> Aabb{
+        const STEPS: usize = 32;
+        let first = self.keyframes[0].transform().bounding_box(bb);
+        let (mut min, mut max) = (first.min(), first.max());
+        let mut pad = 0.0f64;
+        for pair in self.keyframes.windows(2){
+            let mut previous = corners(&pair[0].transform(), bb);
+            for step in 1..=STEPS{
+                let current = corners(&pair[0].lerp(&pair[1], step as f64 / STEPS as f64).transform(), bb);
+                for (p, q) in previous.iter().zip(current.iter()){
+                    pad = pad.max((*q - *p).length());
+                    for axis in 0..3{
+                        min[axis] = min[axis].min(q[axis]);
+                        max[axis] = max[axis].max(q[axis]);
+                    }
+                }
+                previous = current;
+            }
+        }
+        let pad = Vec3::new(pad, pad, pad);
+        Aabb::new(min - pad, max + pad)
+    }
+}
+
+//The eight corners of bb taken through transform
+fn corners(transform: &Transform, bb: Aabb) -> [Point3; 8]{
+    let mut corners = [Point3::default(); 8];
+    for (corner, p) in corners.iter_mut().enumerate(){
+        let x = if corner & 1 == 0 {bb.min().x()} else {bb.max().x()};
+        let y = if corner & 2 == 0 {bb.min().y()} else {bb.max().y()};
+        let z = if corner & 4 == 0 {bb.min().z()} else {bb.max().z()};
+        *p = transform.point(Point3::new(x, y, z));
+    }
+    corners
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f64::consts::PI;
+
+    fn near(a: Vec3, b: Vec3) -> bool{
+        (a - b).length() < 1e-9
+    }
+
+    #[test]
+    fn test_inverse(){
+        let m = Mat4::translation(Vec3::new(1.0, -2.0, 3.0)) * Mat4::rotation_y(0.3) * Mat4::scaling(Vec3::new(2.0, 1.0, 0.5));
+        let product = m * m.inverse().unwrap();
+        for row in 0..4{
+            for col in 0..4{
+                let expected = if row == col {1.0} else {0.0};
+                assert!((product.get(row, col) - expected).abs() < 1e-12);
+            }
+        }
+        assert_eq!(Mat4::scaling(Vec3::new(1.0, 0.0, 1.0)).inverse(), None);
+    }
+
+    #[test]
+    fn test_chained(){
+        let t = Transform::identity().scale(Vec3::new(2.0, 2.0, 2.0))
+                                     .rotate_z(PI/2.0)
+                                     .translate(Vec3::new(0.0, 0.0, 5.0));
+
+        //Case 1: Scaled, then rotated, then translated
+        assert!(near(t.point(Point3::new(1.0, 0.0, 0.0)), Point3::new(0.0, 2.0, 5.0)));
+        assert!(near(t.vector(Vec3::new(1.0, 0.0, 0.0)), Vec3::new(0.0, 2.0, 0.0)));
+
+        //Case 2: The kept inverse matches a computed one
+        let inverse = t.matrix().inverse().unwrap();
+        let p = Point3::new(0.3, -1.0, 2.0);
+        assert!(near(t.inverse().point(p), inverse.transform_point(p)));
+        assert!(near(t.inverse().point(t.point(p)), p));
+
+        //Case 3: Normals of a squashed plane
+        let t = Transform::identity().scale(Vec3::new(1.0, 2.0, 1.0));
+        let n = t.normal(Vec3::new(1.0, 1.0, 0.0));
+        assert!(n.dot(t.vector(Vec3::new(1.0, -1.0, 0.0))).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_animated(){
+        let animated = AnimatedTransform::new(vec![
+            Keyframe::new(1.0, Vec3::new(1.0, 1.0, 1.0), Vec3::new(0.0, PI/2.0, 0.0), Vec3::new(0.0, 0.0, 0.0)),
+            Keyframe::new(0.0, Vec3::new(1.0, 1.0, 1.0), Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 0.0)),
+            Keyframe::new(2.0, Vec3::new(2.0, 2.0, 2.0), Vec3::new(0.0, PI/2.0, 0.0), Vec3::new(0.0, 4.0, 0.0)),
+        ]);
+        assert_eq!(animated.keyframes()[0].time, 0.0);
+
+        //Case 1: Between keyframes the rotation turns rather than cuts the corner
+        let p = Point3::new(1.0, 0.0, 0.0);
+        let half = 0.5_f64.sqrt();
+        assert!(near(animated.at(0.5).point(p), Point3::new(half, 0.0, -half)));
+        assert!(near(animated.at(1.5).point(p), Point3::new(0.0, 2.0, -1.5)));
+
+        //Case 2: Outside the keyframes it holds still
+        assert_eq!(animated.at(-1.0), animated.at(0.0));
+        assert_eq!(animated.at(5.0), animated.keyframes()[2].transform());
+
+        //Case 3: The box holds the whole path of a point circling the y axis
+        let bb = animated.bounding_box(Aabb::new(Point3::new(0.9, -0.1, -0.1), Point3::new(1.1, 0.1, 0.1)));
+        for step in 0..=200{
+            let q = animated.at(step as f64 / 100.0).point(p);
+            for axis in 0..3{
+                assert!(bb.min()[axis] <= q[axis] && q[axis] <= bb.max()[axis], "{:?} is outside the box", q);
+            }
+        }
+        assert!(bb.max().y() < 4.5 && bb.min().x() > -0.5);
+
+        //Case 4: A single keyframe is a fixed transform
+        let fixed = AnimatedTransform::new(vec![Keyframe::new(0.0, Vec3::new(1.0, 1.0, 1.0), Vec3::default(), Vec3::new(1.0, 0.0, 0.0))]);
+        let unit = Aabb::new(Point3::new(0.0, 0.0, 0.0), Point3::new(1.0, 1.0, 1.0));
+        assert_eq!(fixed.bounding_box(unit), fixed.at(0.0).bounding_box(unit));
+    }
+
+    #[test]
+    fn test_bounding_box(){
+        let t = Transform::identity().rotate_y(PI/4.0).translate(Vec3::new(1.0, 0.0, 0.0));
+        let bb = t.bounding_box(Aabb::new(Point3::new(-1.0, -1.0, -1.0), Point3::new(1.0, 1.0, 1.0)));
+        let r = 2.0_f64.sqrt();
+        assert!(near(bb.min(), Point3::new(1.0 - r, -1.0, -r)));
+        assert!(near(bb.max(), Point3::new(1.0 + r, 1.0, r)));
+    }
+}
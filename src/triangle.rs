@@ -3,19 +3,28 @@ use crate::ray::*;
 use crate::traceable::*;
 use crate::bvh::*;
 use crate::material::*;
+use crate::light::*;
+use crate::rng::*;
 use crate::util::*;
 
-#[derive (Copy, Clone)]
+#[derive (Clone)]
 pub struct Triangle {
     vertices: [Point3; 3],
     normals: [Vec3; 3],
+    texcoords: [[f64; 2]; 3],
     material: Material
 }
 
 impl Triangle{
 
+    //Without texture coordinates, u and v are the barycentric weights of
+    //the second and third vertices
     pub fn new(vertices: [Point3; 3], normals: [Vec3;3], mat: Material) -> Triangle{
-        Triangle{vertices, normals, material: mat}
+        Triangle::new_textured(vertices, normals, [[0.0, 0.0], [1.0, 0.0], [0.0, 1.0]], mat)
+    }
+
+    pub fn new_textured(vertices: [Point3; 3], normals: [Vec3;3], texcoords: [[f64; 2]; 3], mat: Material) -> Triangle{
+        Triangle{vertices, normals, texcoords, material: mat}
     }
 
     pub fn get_vertex(&self, index: usize) -> Point3{
@@ -32,53 +41,64 @@ impl Triangle{
     }
 
     pub fn shear_xy(&mut self, r: &Ray){
+        Triangle::shear_vertices_xy(&mut self.vertices, r);
+    }
+
+    pub fn shear_z(&mut self, r: &Ray){
+        Triangle::shear_vertices_z(&mut self.vertices, r);
+    }
+
+    //The shears work on a copy of the vertices so that hit doesn't have
+    //to clone the material
+    fn shear_vertices_xy(vertices: &mut [Point3; 3], r: &Ray){
         let sx = -r.direction().x()/ r.direction().z();
         let sy = -r.direction().y()/r.direction().z();
 
-        for i in 0..3{
-            self.vertices[i] = Point3::new(self.get_vertex(i).x() + sx * self.get_vertex(i).z(),
-                                           self.get_vertex(i).y() + sy * self.get_vertex(i).z(),
-                                            self.get_vertex(i).z());
+        for vertex in vertices.iter_mut(){
+            *vertex = Point3::new(vertex.x() + sx * vertex.z(),
+                                  vertex.y() + sy * vertex.z(),
+                                  vertex.z());
         }
 
     } 
 
-    pub fn shear_z(&mut self, r: &Ray){
+    fn shear_vertices_z(vertices: &mut [Point3; 3], r: &Ray){
         let sz = 1.0/r.direction().z();
-        self.vertices[0][2] = self.vertices[0][2]*sz;
-        self.vertices[1][2] = self.vertices[1][2]*sz;
-        self.vertices[2][2] = self.vertices[2][2]*sz;
+        vertices[0][2] *= sz;
+        vertices[1][2] *= sz;
+        vertices[2][2] *= sz;
     }
 }
 
 impl Hit for Triangle {
-    fn hit(&self ,r: &Ray, t_min: f64, t_max: f64) -> Option<(HitRecord, &Material)>{
+    fn hit(&self ,r: &Ray, t_min: f64, t_max: f64, _: &mut Rng) -> Option<(HitRecord, &Material)>{
 
-        let mut rc = r.clone();
-        rc.dir = r.dir/r.dir.length();
-        let mut t = self.clone();
+        //The direction is left unnormalised so that t is measured along the
+        //ray as given, like every other shape, rather than as a distance
+        let mut rc = *r;
+        let mut vertices = self.vertices;
 
         //Translate vertices
-        t.vertices[0] = t.vertices[0] - rc.origin();
-        t.vertices[1] = t.vertices[1] - rc.origin();
-        t.vertices[2] = t.vertices[2] - rc.origin();
+        vertices[0] = vertices[0] - rc.origin();
+        vertices[1] = vertices[1] - rc.origin();
+        vertices[2] = vertices[2] - rc.origin();
 
         //Permute dimensions
         let max_dim = r.direction().max_dim();
         if max_dim < 2{
-            t.vertices[0].permute(max_dim, 2);
-            t.vertices[1].permute(max_dim, 2);
-            t.vertices[2].permute(max_dim, 2);
+            vertices[0].permute(max_dim, 2);
+            vertices[1].permute(max_dim, 2);
+            vertices[2].permute(max_dim, 2);
             rc.dir.permute(max_dim, 2);
         }
 
         //Only shear the (x,y) coordinates to minimise computations
-        t.shear_xy(&rc);
+        Triangle::shear_vertices_xy(&mut vertices, &rc);
 
         //Call edge function on all three sides
-        let e0 = Triangle::edge_fn(t.vertices[1], t.vertices[2]);
-        let e1 = Triangle::edge_fn(t.vertices[2], t.vertices[0]);
-        let e2 = Triangle::edge_fn(t.vertices[0], t.vertices[1]);
+        let e0 = Triangle::edge_fn(vertices[1], vertices[2]);
+        let e1 = Triangle::edge_fn(vertices[2], vertices[0]);
+        let e2 = Triangle::edge_fn(vertices[0], vertices[1]);
 
         //Check for miss
         if (e0 < 0.0 || e1 < 0.0 || e2 < 0.0) && (e0 > 0.0 || e1 > 0.0 || e2 > 0.0){
@@ -92,8 +112,8 @@ impl Hit for Triangle {
         }
 
         //Compute scaled hit distance to triangle and test against ray range
-        t.shear_z(&rc);
-        let t_scaled = e0 * t.vertices[0].z() + e1 * t.vertices[1].z() + e2 * t.vertices[2].z();
+        Triangle::shear_vertices_z(&mut vertices, &rc);
+        let t_scaled = e0 * vertices[0].z() + e1 * vertices[1].z() + e2 * vertices[2].z();
         if det < 0.0 && (t_scaled >= t_min * det || t_scaled < t_max * det){
             return None;
         } else if det > 0.0 && (t_scaled <= t_min * det || t_scaled > t_max * det){
@@ -123,7 +143,10 @@ impl Hit for Triangle {
 
        let p_err = gamma(7) * Vec3::new(x_err, y_err, z_err);
        let p = b0 * self.vertices[0] + b1 * self.vertices[1] + b2 * self.vertices[2];
-       Some((HitRecord::new(p, norm, t, *r, p_err), &self.material))
+       let mut rec = HitRecord::new(p, norm, t, *r, p_err);
+       let uv = |i: usize| b0 * self.texcoords[0][i] + b1 * self.texcoords[1][i] + b2 * self.texcoords[2][i];
+       rec.set_uv(uv(0), uv(1));
+       Some((rec, &self.material))
     }
 
     fn bounding_box(&self) -> Option<Aabb>{
@@ -139,6 +162,25 @@ impl Hit for Triangle {
     }
 }
 
+impl Surface for Triangle {
+    fn area(&self) -> f64{
+        0.5*(self.vertices[1] - self.vertices[0]).cross(self.vertices[2] - self.vertices[0]).length()
+    }
+
+    fn sample_surface(&self, rng: &mut Rng) -> (Point3, Vec3){
+        //Folding the square onto the triangle keeps the samples uniform
+        let sqrt_r1 = rng.f64().sqrt();
+        let r2 = rng.f64();
+        let point = (1.0 - sqrt_r1)*self.vertices[0] + (sqrt_r1*(1.0 - r2))*self.vertices[1] + (sqrt_r1*r2)*self.vertices[2];
+        let normal = (self.vertices[1] - self.vertices[0]).cross(self.vertices[2] - self.vertices[0]).unit_vector();
+        (point, normal)
+    }
+
+    fn material(&self) -> &Material{
+        &self.material
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -178,6 +220,7 @@ mod tests {
 
     #[test]
     fn test_hit(){
+        let mut rng = Rng::new(0);
 
         //Initialisations
         let mat = Material::Lambertian(Lambertian::new(Vec3::new(1.0, 1.0, 1.0)));
@@ -189,7 +232,7 @@ mod tests {
         
         //Case 1: Front-facing intersection
         let r = Ray::new(Vec3::new(0.0, 3.0, 20.0), Vec3::new(0.0, 0.0, -1.0));
-        let result = t.hit(&r, 0.0, 100.0);
+        let result = t.hit(&r, 0.0, 100.0, &mut rng);
         assert!(result.is_some());
         let (rec, _) = result.unwrap();
         assert_eq!(rec.t, 20.0);
@@ -198,7 +241,7 @@ mod tests {
 
         //Case 2: Back-facing interection
         let r = Ray::new(Vec3::new(0.0, 3.0, -20.0), Vec3::new(0.0, 0.0, 1.0));
-        let result = t.hit(&r, 0.0, 100.0);
+        let result = t.hit(&r, 0.0, 100.0, &mut rng);
         assert!(result.is_some());
         let (rec, _) = result.unwrap();
         assert_eq!(rec.t, 20.0);
@@ -207,12 +250,12 @@ mod tests {
 
         //Case 3: Edge-on intersection
         let r = Ray::new(Vec3::new(-10.0, 2.0, 0.0), Vec3::new(1.0, 0.0, 0.0));
-        let result = t.hit(&r, 0.0, 100.0);
+        let result = t.hit(&r, 0.0, 100.0, &mut rng);
         assert!(result.is_none());
 
         //Case 4: Edge intersection
         let r = Ray::new(Vec3::new(0.0, 2.0,10.0), Vec3::new(0.0, 0.0, -1.0));
-        let result = t.hit(&r, 0.0, 10.0);
+        let result = t.hit(&r, 0.0, 10.0, &mut rng);
         assert!(result.is_some());
         let (rec, _) = result.unwrap();
         assert_eq!(rec.t, 10.0);
@@ -221,14 +264,21 @@ mod tests {
 
         //Case 5: Miss (due to timeout)
         let r = Ray::new(Vec3::new(0.0, 2.0,10.0), Vec3::new(0.0, 0.0, -1.0));
-        let result = t.hit(&r, 0.0, 10.0 - - std::f64::MIN_POSITIVE);
+        let result = t.hit(&r, 0.0, 10.0 - - std::f64::MIN_POSITIVE, &mut rng);
         assert!(result.is_some());
 
         //Case 6: Miss (due to geometry)
         let r = Ray::new(Vec3::new(0.5, -1.0, 3.0), Vec3::new(0.0, 1.0, 0.0));
-        let result = t.hit(&r, 0.0, 100.0);
+        let result = t.hit(&r, 0.0, 100.0, &mut rng);
         assert!(result.is_none());
 
+        //Case 7: t is in units of a direction that is not unit length
+        let r = Ray::new(Vec3::new(0.0, 3.0, 20.0), Vec3::new(0.0, 0.0, -4.0));
+        let (rec, _) = t.hit(&r, 0.0, 100.0, &mut rng).unwrap();
+        assert_eq!(rec.t, 5.0);
+        assert_eq!(rec.p, Vec3::new(0.0, 3.0, 0.0));
+        assert!(t.hit(&r, 0.0, 4.9, &mut rng).is_none());
+
     }
 
     #[test]
@@ -243,4 +293,22 @@ mod tests {
         let bb = result.unwrap();
         assert_eq!(bb, Aabb::new(Vec3::new(-0.001, -0.001, -0.001), Vec3::new(1.0 + 0.001, 2.0 + 0.001, 2.0 + 0.001)));
     }
+    #[test]
+    fn test_hit_uv(){
+        let mut rng = Rng::new(0);
+        let mat = Material::new_lambertian(Vec3::new(1.0, 1.0, 1.0));
+        let vertices = [Vec3::new(0.0, 0.0, 0.0), Vec3::new(4.0, 0.0, 0.0), Vec3::new(0.0, 4.0, 0.0)];
+        let norm = [Vec3::new(0.0, 0.0, 1.0); 3];
+        let r = Ray::new(Vec3::new(1.0, 2.0, 10.0), Vec3::new(0.0, 0.0, -1.0));
+
+        //Case 1: Barycentric weights without texture coordinates
+        let t = Triangle::new(vertices, norm, mat.clone());
+        let (rec, _) = t.hit(&r, 0.0, 100.0, &mut rng).unwrap();
+        assert!((rec.u - 0.25).abs() < 1e-12 && (rec.v - 0.5).abs() < 1e-12);
+
+        //Case 2: Interpolated texture coordinates
+        let t = Triangle::new_textured(vertices, norm, [[0.5, 0.5], [1.0, 0.5], [0.5, 1.0]], mat);
+        let (rec, _) = t.hit(&r, 0.0, 100.0, &mut rng).unwrap();
+        assert!((rec.u - 0.625).abs() < 1e-12 && (rec.v - 0.75).abs() < 1e-12);
+    }
 }
\ No newline at end of file
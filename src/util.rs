@@ -1,4 +1,5 @@
 use crate::vec::*;
+use crate::rng::*;
 
 use std::f64::INFINITY;
 use std::f64::consts::PI;
@@ -10,8 +11,8 @@ pub fn deg_to_rad(deg:f64) -> f64{
 }
 
 //Generates random numbers between [min_inc, max_exc)
-pub fn rand_double(min_inc: f64, max_exc: f64) -> f64{
-    fastrand::f64()*(max_exc - min_inc) + min_inc
+pub fn rand_double(rng: &mut Rng, min_inc: f64, max_exc: f64) -> f64{
+    rng.f64()*(max_exc - min_inc) + min_inc
 }
 
 pub fn bound(x: f64, min: f64, max:f64) -> f64{
@@ -21,6 +22,10 @@ pub fn bound(x: f64, min: f64, max:f64) -> f64{
 }
 
 pub fn import_obj(file_name: &str) -> (Vec<tobj::Model>, Option<Vec<tobj::Material>>){
+    try_import_obj(file_name).expect("Invalid file name.")
+}
+
+pub fn try_import_obj(file_name: &str) -> Result<(Vec<tobj::Model>, Option<Vec<tobj::Material>>), tobj::LoadError>{
 
     let load_options = &tobj::LoadOptions{single_index: true,
         triangulate: true,
@@ -28,17 +33,16 @@ pub fn import_obj(file_name: &str) -> (Vec<tobj::Model>, Option<Vec<tobj::Materi
         ignore_points: true,
         ..Default::default()};
         
-    let obj = tobj::load_obj(file_name,load_options);
-    let (models, materials_res) = obj.expect("Invalid file name.");
+    let (models, materials_res) = tobj::load_obj(file_name,load_options)?;
     match materials_res{
         Ok(mat) => {
             if mat.len() > 0{
-                (models, Some(mat))
+                Ok((models, Some(mat)))
             }else{
-                (models, None)
+                Ok((models, None))
             }
         }
-        Err(_) => (models, None)
+        Err(_) => Ok((models, None))
     }
 }
 
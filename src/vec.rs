@@ -1,7 +1,8 @@
 use std::ops;
 use core::cmp::Ordering;
 use std::ops::{Index, IndexMut};
-use crate::*;
+use crate::util::*;
+use crate::rng::*;
 
 #[derive (PartialEq, Debug, Copy, Clone, Default)]
 pub struct Vec3{
@@ -16,30 +17,30 @@ impl Vec3{
         Vec3{arr: [x,y,z]}
     }
 
-    pub fn rand(min: f64, max:f64) -> Vec3{
-        Vec3{arr:[rand_double(min, max), rand_double(min, max), rand_double(min, max)]}
+    pub fn rand(rng: &mut Rng, min: f64, max:f64) -> Vec3{
+        Vec3{arr:[rand_double(rng, min, max), rand_double(rng, min, max), rand_double(rng, min, max)]}
     }
 
-    pub fn rand_in_unit_sphere() -> Vec3{
+    pub fn rand_in_unit_sphere(rng: &mut Rng) -> Vec3{
         loop{
-            let p = Vec3::rand(-1.0, 1.0);
+            let p = Vec3::rand(rng, -1.0, 1.0);
             if p.length_squared() < 1.0{
                 break(p)
             }
         }
     }
 
-    pub fn rand_in_unit_disk() -> Vec3{
+    pub fn rand_in_unit_disk(rng: &mut Rng) -> Vec3{
         loop{
-            let p = Vec3::new(rand_double(-1.0, 1.0), rand_double(-1.0, 1.0), 0.0);
+            let p = Vec3::new(rand_double(rng, -1.0, 1.0), rand_double(rng, -1.0, 1.0), 0.0);
             if p.length_squared() < 1.0{
                 break(p)
             }
         }
     }
 
-    pub fn rand_unit_vec() -> Vec3{
-        Vec3::rand_in_unit_sphere().unit_vector()
+    pub fn rand_unit_vec(rng: &mut Rng) -> Vec3{
+        Vec3::rand_in_unit_sphere(rng).unit_vector()
     }
 
     pub fn x(&self) -> f64{
@@ -178,21 +179,38 @@ impl IndexMut<usize> for Vec3{
 
 impl Color{
 
-    pub fn write_color<T: std::io::Write>(self, writer: &mut T, samples: i32)
+    //Perceived brightness of linear sRGB
+    pub fn luminance(self) -> f64
     {
-        let mut r = self.x();
-        let mut g = self.y();
-        let mut b = self.z();
+        0.2126*self.x() + 0.7152*self.y() + 0.0722*self.z()
+    }
 
+    //Averages the summed samples and applies gamma 2 correction
+    pub fn gamma_corrected(self, samples: i32) -> Color
+    {
         let scale = 1.0/(samples as f64);
-        r = (scale*r).sqrt();
-        g = (scale*g).sqrt();
-        b = (scale*b).sqrt();
-        
-
-        let ir = (256.0*bound(r, 0.0, 0.999)) as i64;
-        let ig = (256.0*bound(g, 0.0, 0.999)) as i64;
-        let ib = (256.0*bound(b, 0.0, 0.999)) as i64;
+        Color::new((scale*self.x()).sqrt(), (scale*self.y()).sqrt(), (scale*self.z()).sqrt())
+    }
+
+    pub fn to_rgb8(self, samples: i32) -> [u8; 3]
+    {
+        let c = self.gamma_corrected(samples);
+        [(256.0*bound(c.x(), 0.0, 0.999)) as u8,
+         (256.0*bound(c.y(), 0.0, 0.999)) as u8,
+         (256.0*bound(c.z(), 0.0, 0.999)) as u8]
+    }
+
+    pub fn to_rgb16(self, samples: i32) -> [u16; 3]
+    {
+        let c = self.gamma_corrected(samples);
+        [(65536.0*bound(c.x(), 0.0, 0.99999)) as u16,
+         (65536.0*bound(c.y(), 0.0, 0.99999)) as u16,
+         (65536.0*bound(c.z(), 0.0, 0.99999)) as u16]
+    }
+
+    pub fn write_color<T: std::io::Write>(self, writer: &mut T, samples: i32)
+    {
+        let [ir, ig, ib] = self.to_rgb8(samples);
         writeln!(writer, "{} {} {}", ir, ig, ib).unwrap();
     }
 }
@@ -320,9 +338,10 @@ mod tests {
 
     #[test]
     fn test_rand_unit_sphere(){
-        let vec_1 = Vec3::rand_unit_vec();
+        let mut rng = Rng::new(0);
+        let vec_1 = Vec3::rand_unit_vec(&mut rng);
         assert!(vec_1.length() <= 1.0);
-        let vec_2 = Vec3::rand_unit_vec();
+        let vec_2 = Vec3::rand_unit_vec(&mut rng);
         assert!(vec_1 != vec_2);
     }
 
@@ -370,6 +389,15 @@ mod tests {
         assert_eq!(vec, Vec3::new(0.3, 0.1, 0.2));
     }
 
+    #[test]
+    fn test_to_rgb(){
+        let color = Color::new(4.0, 1.0, 16.0);
+        assert_eq!(color.gamma_corrected(4), Color::new(1.0, 0.5, 2.0));
+        assert_eq!(color.to_rgb8(4), [255, 128, 255]);
+        assert_eq!(color.to_rgb16(4), [65535, 32768, 65535]);
+        assert_eq!(Color::new(-1.0, 0.0, 0.0).to_rgb8(1), [0, 0, 0]);
+    }
+
     #[test]
     fn test_max_dim(){
         let vec = Vec3::new(0.4, 0.3, 0.2);
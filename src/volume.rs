@@ -0,0 +1,285 @@
+use crate::vec::*;
+use crate::bvh::*;
+use crate::texture::*;
+
+use std::fs::File;
+use std::io;
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+const MAGIC: &str = "ray_trace voxels 1";
+
+//A dense grid of values stretched over a box, with one value at the center
+//of each voxel and trilinear interpolation between them. Outside the box
+//the value is zero.
+#[derive (Clone, PartialEq, Debug)]
+pub struct VoxelGrid{
+    size: [usize; 3],
+    values: Vec<f64>,
+    bb: Aabb,
+    max_value: f64,
+}
+
+//Billowing Perlin turbulence, fading out towards the sides of its box so
+//that it makes a puff of smoke rather than a block
+#[derive (Clone, PartialEq, Debug)]
+pub struct NoiseDensity{
+    perlin: Perlin,
+    frequency: f64,
+    bb: Aabb,
+}
+
+//Where a heterogeneous medium is dense, as a value from 0 up to at most
+//max_value at each point
+#[derive (Clone, PartialEq, Debug)]
+pub enum DensityField{
+    Grid(Arc<VoxelGrid>),
+    Noise(Arc<NoiseDensity>),
+}
+
+impl VoxelGrid{
+    //Values run along x first, then y, then z. Negative values are raised
+    //to zero.
+    pub fn new(size: [usize; 3], values: Vec<f64>, bb: Aabb) -> VoxelGrid{
+        assert!(size.iter().all(|&n| n > 0), "A voxel grid must have at least one voxel along each axis");
+        assert_eq!(values.len(), size[0] * size[1] * size[2], "A voxel grid needs one value per voxel");
+        let values: Vec<f64> = values.into_iter().map(|value| value.max(0.0)).collect();
+        let max_value = values.iter().cloned().fold(0.0, f64::max);
+        VoxelGrid{size, values, bb, max_value}
+    }
+
+    //A text header giving the number of voxels along each axis, then the
+    //values as little-endian f32s in the order new takes them. The file
+    //says nothing about where the grid is, so it is stretched over bb.
+    pub fn load<P: AsRef<Path>>(path: P, bb: Aabb) -> io::Result<VoxelGrid>{
+        let mut reader = BufReader::new(File::open(path)?);
+        if read_line(&mut reader)? != MAGIC{
+            return Err(invalid_data("not a voxel grid file".to_string()));
+        }
+        let line = read_line(&mut reader)?;
+        let size: Vec<usize> = match line.strip_prefix("size "){
+            Some(size) => size.split(' ').map(|n| n.parse()).collect::<Result<_, _>>()
+                .map_err(|_| invalid_data(format!("invalid size '{}' in the voxel grid header", size)))?,
+            None => return Err(invalid_data(format!("expected 'size' in the voxel grid header, found '{}'", line))),
+        };
+        if size.len() != 3 || size.contains(&0) || !read_line(&mut reader)?.is_empty(){
+            return Err(invalid_data("malformed voxel grid header".to_string()));
+        }
+
+        let count = size[0].checked_mul(size[1]).and_then(|n| n.checked_mul(size[2]))
+            .ok_or_else(|| invalid_data("voxel grid is too large".to_string()))?;
+        let length = count.checked_mul(4).ok_or_else(|| invalid_data("voxel grid is too large".to_string()))?;
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+        if bytes.len() != length{
+            return Err(invalid_data(format!("expected {} voxel values, found {} bytes of data", count, bytes.len())));
+        }
+        let values: Vec<f64> = bytes.chunks_exact(4).map(|chunk| f32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]) as f64).collect();
+        if values.iter().any(|value| !value.is_finite()){
+            return Err(invalid_data("voxel grid has values that are not finite".to_string()));
+        }
+        Ok(VoxelGrid::new([size[0], size[1], size[2]], values, bb))
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()>{
+        let mut writer = BufWriter::new(File::create(path)?);
+        writeln!(writer, "{}", MAGIC)?;
+        writeln!(writer, "size {} {} {}", self.size[0], self.size[1], self.size[2])?;
+        writeln!(writer)?;
+        for value in self.values.iter(){
+            writer.write_all(&(*value as f32).to_le_bytes())?;
+        }
+        writer.flush()
+    }
+
+    pub fn size(&self) -> [usize; 3]{
+        self.size
+    }
+
+    pub fn bounding_box(&self) -> Aabb{
+        self.bb
+    }
+
+    pub fn max_value(&self) -> f64{
+        self.max_value
+    }
+
+    pub fn value(&self, p: Point3) -> f64{
+        let (min, max) = (self.bb.min(), self.bb.max());
+        let mut cell = [0; 3];
+        let mut frac = [0.0; 3];
+        for axis in 0..3{
+            let x = (p[axis] - min[axis]) / (max[axis] - min[axis]);
+            if !(0.0..=1.0).contains(&x){
+                return 0.0;
+            }
+            //Voxel centers sit half a voxel in from the sides, and the
+            //outermost values carry on out to the sides
+            let x = (x * self.size[axis] as f64 - 0.5).max(0.0).min((self.size[axis] - 1) as f64);
+            cell[axis] = (x.floor() as usize).min(self.size[axis].saturating_sub(2));
+            frac[axis] = x - cell[axis] as f64;
+        }
+
+        let mut accum = 0.0;
+        for di in 0..2{
+            for dj in 0..2{
+                for dk in 0..2{
+                    let (i, j, k) = ((cell[0] + di).min(self.size[0] - 1), (cell[1] + dj).min(self.size[1] - 1), (cell[2] + dk).min(self.size[2] - 1));
+                    let weight = (if di == 1 {frac[0]} else {1.0 - frac[0]})
+                               * (if dj == 1 {frac[1]} else {1.0 - frac[1]})
+                               * (if dk == 1 {frac[2]} else {1.0 - frac[2]});
+                    accum += weight * self.values[i + self.size[0] * (j + self.size[1] * k)];
+                }
+            }
+        }
+        accum
+    }
+}
+
+impl NoiseDensity{
+    //Frequency is how many puffs of noise fit in a unit of distance
+    pub fn new(bb: Aabb, frequency: f64) -> NoiseDensity{
+        NoiseDensity{perlin: Perlin::new(), frequency, bb}
+    }
+
+    pub fn value(&self, p: Point3) -> f64{
+        let (min, max) = (self.bb.min(), self.bb.max());
+        let center = min + 0.5*(max - min);
+        let mut radius_squared = 0.0;
+        for axis in 0..3{
+            let x = 2.0 * (p[axis] - center[axis]) / (max[axis] - min[axis]);
+            radius_squared += x*x;
+        }
+        let falloff = 1.0 - radius_squared;
+        if falloff <= 0.0{
+            return 0.0;
+        }
+        (falloff * self.perlin.turbulence(self.frequency * p, 5)).min(1.0)
+    }
+}
+
+impl DensityField{
+    pub fn new_grid(grid: VoxelGrid) -> DensityField{
+        DensityField::Grid(Arc::new(grid))
+    }
+
+    pub fn new_noise(bb: Aabb, frequency: f64) -> DensityField{
+        DensityField::Noise(Arc::new(NoiseDensity::new(bb, frequency)))
+    }
+
+    pub fn value(&self, p: Point3) -> f64{
+        match self{
+            DensityField::Grid(grid) => grid.value(p),
+            DensityField::Noise(noise) => noise.value(p),
+        }
+    }
+
+    //No point has a greater value
+    pub fn max_value(&self) -> f64{
+        match self{
+            DensityField::Grid(grid) => grid.max_value(),
+            DensityField::Noise(_) => 1.0,
+        }
+    }
+
+    pub fn bounding_box(&self) -> Aabb{
+        match self{
+            DensityField::Grid(grid) => grid.bounding_box(),
+            DensityField::Noise(noise) => noise.bb,
+        }
+    }
+}
+
+fn invalid_data(message: String) -> io::Error{
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn read_line<R: BufRead>(reader: &mut R) -> io::Result<String>{
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    if !line.ends_with('\n'){
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "voxel grid header is cut short"));
+    }
+    line.pop();
+    Ok(line)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+
+    fn unit_box() -> Aabb{
+        Aabb::new(Point3::new(0.0, 0.0, 0.0), Point3::new(1.0, 1.0, 1.0))
+    }
+
+    #[test]
+    fn test_grid_value(){
+        //Two voxels along x, one along y and z
+        let grid = VoxelGrid::new([2, 1, 1], vec![1.0, 3.0], unit_box());
+        assert_eq!(grid.max_value(), 3.0);
+
+        //Case 1: Voxel centers have their own values
+        assert_eq!(grid.value(Point3::new(0.25, 0.5, 0.5)), 1.0);
+        assert_eq!(grid.value(Point3::new(0.75, 0.1, 0.9)), 3.0);
+
+        //Case 2: Interpolated between centers and held out to the sides
+        assert_eq!(grid.value(Point3::new(0.5, 0.5, 0.5)), 2.0);
+        assert_eq!(grid.value(Point3::new(0.0, 0.5, 0.5)), 1.0);
+        assert_eq!(grid.value(Point3::new(1.0, 0.5, 0.5)), 3.0);
+
+        //Case 3: Nothing outside
+        assert_eq!(grid.value(Point3::new(1.01, 0.5, 0.5)), 0.0);
+
+        //Case 4: Along every axis
+        let values = (0..8).map(|i| i as f64).collect();
+        let grid = VoxelGrid::new([2, 2, 2], values, Aabb::new(Point3::new(-1.0, -1.0, -1.0), Point3::new(1.0, 1.0, 1.0)));
+        assert_eq!(grid.value(Point3::new(-0.5, -0.5, 0.5)), 4.0);
+        assert_eq!(grid.value(Point3::new(0.5, 0.5, -0.5)), 3.0);
+        assert!((grid.value(Point3::new(0.0, 0.0, 0.0)) - 3.5).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_grid_save_load(){
+        let path = env::temp_dir().join(format!("ray_trace_voxels_{}.grid", std::process::id()));
+        let grid = VoxelGrid::new([3, 2, 1], vec![0.0, 0.5, 1.0, 1.5, 2.0, 2.5], unit_box());
+        grid.save(&path).unwrap();
+        let loaded = VoxelGrid::load(&path, unit_box());
+        let mut bytes = fs::read(&path).unwrap();
+        assert_eq!(loaded.unwrap(), grid);
+
+        //Case 1: Data cut short
+        bytes.pop();
+        fs::write(&path, &bytes).unwrap();
+        let err = VoxelGrid::load(&path, unit_box()).err().unwrap();
+        assert_eq!(err.to_string(), "expected 6 voxel values, found 23 bytes of data");
+
+        //Case 2: Not a grid
+        fs::write(&path, "P6\n").unwrap();
+        let err = VoxelGrid::load(&path, unit_box()).err().unwrap();
+        assert_eq!(err.to_string(), "not a voxel grid file");
+
+        //Case 3: More voxels than there are bytes to hold them
+        fs::write(&path, format!("{}\nsize {} 1 1\n\n", MAGIC, usize::MAX / 2)).unwrap();
+        let err = VoxelGrid::load(&path, unit_box()).err().unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!((err.kind(), err.to_string()), (io::ErrorKind::InvalidData, "voxel grid is too large".to_string()));
+    }
+
+    #[test]
+    fn test_noise_density(){
+        let noise = DensityField::new_noise(Aabb::new(Point3::new(-2.0, 0.0, -2.0), Point3::new(2.0, 4.0, 2.0)), 2.0);
+        assert_eq!(noise.bounding_box().max(), Point3::new(2.0, 4.0, 2.0));
+
+        //Values stay in range, and fade to nothing at the corners
+        let mut rng = crate::rng::Rng::new(0);
+        for _ in 0..1000{
+            let p = Point3::new(4.0*rng.f64() - 2.0, 4.0*rng.f64(), 4.0*rng.f64() - 2.0);
+            let value = noise.value(p);
+            assert!((0.0..=noise.max_value()).contains(&value));
+        }
+        assert_eq!(noise.value(Point3::new(1.9, 3.9, 1.9)), 0.0);
+    }
+}
@@ -0,0 +1,162 @@
+//Renders small versions of the built-in scenes with a fixed seed and
+//compares them against the images in tests/references. Renders are
+//repeatable, so any difference comes from a change to the renderer or to
+//floating point on another machine; the tolerance allows for the latter.
+//On failure the render and a diff image are written to the target
+//directory. After an intended change to the output, store new references
+//with: UPDATE_REFERENCES=1 cargo test --no-default-features --test reference_images
+extern crate ray_trace;
+extern crate image;
+
+use ray_trace::*;
+use ray_trace::bvh::FlatBvh;
+use ray_trace::light::LightList;
+use ray_trace::scenes;
+
+use image::{ImageBuffer, Rgb, RgbImage};
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const IMAGE_DATA: ImageData = ImageData {
+    image_width: 120,
+    image_height: 80,
+    samples_per_pixel: 32,
+    max_depth: 8,
+    roulette_depth: 3,
+    seed: 1,
+    adaptive: None
+};
+
+//Root mean square difference allowed between a render and its reference,
+//with channels running from 0 to 1 as displayed
+const MAX_RMSE: f64 = 0.01;
+
+//The most a single channel may differ, so a small broken patch fails even
+//when the rest of the image matches
+const MAX_ERROR: f64 = 0.25;
+
+//How far apart two images are, measured on the gamma corrected channels
+//that are displayed
+struct Difference {
+    rmse: f64,
+    max_error: f64,
+    //The difference of each pixel scaled up to be visible, from black for
+    //a match to white for an error of MAX_ERROR or more
+    image: RgbImage,
+}
+
+#[test]
+fn test_sphere_world(){
+    check_scene("sphere_world", scenes::sphere_world);
+}
+
+#[test]
+fn test_light_test(){
+    check_scene("light_test", scenes::light_test);
+}
+
+#[test]
+fn test_triangle_test(){
+    check_scene("triangle_test", scenes::triangle_test);
+}
+
+#[test]
+fn test_triangle_bb_test(){
+    check_scene("triangle_bb_test", scenes::triangle_bb_test);
+}
+
+#[test]
+fn test_obj_test(){
+    if Path::new(scenes::CAR_OBJ).exists() {
+        check_scene("obj_test", scenes::obj_test);
+    } else {
+        println!("obj_test: skipped, {} not found", scenes::CAR_OBJ);
+    }
+}
+
+#[test]
+fn test_mesh_test(){
+    check_scene("mesh_test", scenes::mesh_test);
+}
+
+#[test]
+fn test_instance_test(){
+    check_scene("instance_test", scenes::instance_test);
+}
+
+#[test]
+fn test_compare(){
+    let reference = RgbImage::from_pixel(4, 2, Rgb([100, 100, 100]));
+
+    //Case 1: Identical
+    let difference = compare(&reference, &reference);
+    assert_eq!((difference.rmse, difference.max_error), (0.0, 0.0));
+    assert_eq!(difference.image.get_pixel(3, 1).0, [0, 0, 0]);
+
+    //Case 2: One channel of one pixel is off by a fifth
+    let mut image = reference.clone();
+    image.put_pixel(3, 1, Rgb([151, 100, 100]));
+    let difference = compare(&image, &reference);
+    assert!((difference.max_error - 0.2).abs() < 1e-9);
+    assert!((difference.rmse - (0.04f64 / 24.0).sqrt()).abs() < 1e-9);
+    assert_eq!(difference.image.get_pixel(3, 1).0, [204, 0, 0]);
+}
+
+fn check_scene(name: &str, scene: fn() -> scenes::Scene){
+    let (world, background, look_from, look_at) = scene();
+    let lights = LightList::new(&world);
+    let aspect_ratio = IMAGE_DATA.image_width as f64 / IMAGE_DATA.image_height as f64;
+    let cam = CameraSettings::new(look_from, look_at).to_camera(aspect_ratio);
+    let scene_data = SceneData { world: FlatBvh::new(world), lights, background: background.into(), fog: None, cam };
+    let framebuffer = render(IMAGE_DATA, scene_data, 1);
+    let image: RgbImage = ImageBuffer::from_raw(IMAGE_DATA.image_width as u32, IMAGE_DATA.image_height as u32, framebuffer.to_rgb8())
+        .expect("The framebuffer size does not match its dimensions");
+
+    let reference_path = reference_dir().join(format!("{}.png", name));
+    if env::var_os("UPDATE_REFERENCES").is_some() {
+        fs::create_dir_all(reference_dir()).unwrap();
+        image.save(&reference_path).unwrap();
+        return;
+    }
+    let reference = match image::open(&reference_path) {
+        Ok(reference) => reference.to_rgb8(),
+        Err(err) => panic!("cannot open the reference image '{}': {}. Run with UPDATE_REFERENCES=1 to create it",
+                           reference_path.display(), err),
+    };
+    assert_eq!(reference.dimensions(), image.dimensions(), "{}: the reference image is a different size", name);
+
+    let difference = compare(&image, &reference);
+    if difference.rmse > MAX_RMSE || difference.max_error > MAX_ERROR {
+        let output_dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("reference_images");
+        fs::create_dir_all(&output_dir).unwrap();
+        let render_path = output_dir.join(format!("{}.png", name));
+        let diff_path = output_dir.join(format!("{}_diff.png", name));
+        image.save(&render_path).unwrap();
+        difference.image.save(&diff_path).unwrap();
+        panic!("{}: the render differs from its reference by an RMSE of {:.4} (allowed {}) and at most {:.3} (allowed {}). \
+                The render is at '{}' and the difference at '{}'",
+               name, difference.rmse, MAX_RMSE, difference.max_error, MAX_ERROR, render_path.display(), diff_path.display());
+    }
+}
+
+fn reference_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("references")
+}
+
+fn compare(image: &RgbImage, reference: &RgbImage) -> Difference {
+    let mut total = 0.0;
+    let mut max_error = 0.0f64;
+    let diff = ImageBuffer::from_fn(image.width(), image.height(), |i, j| {
+        let (pixel, reference_pixel) = (image.get_pixel(i, j).0, reference.get_pixel(i, j).0);
+        Rgb([0, 1, 2].map(|c| {
+            let error = (pixel[c] as f64 - reference_pixel[c] as f64).abs() / 255.0;
+            total += error * error;
+            max_error = max_error.max(error);
+            (255.0 * (error / MAX_ERROR).min(1.0)).round() as u8
+        }))
+    });
+    let channels = (image.width() * image.height() * 3) as f64;
+    Difference { rmse: (total / channels).sqrt(), max_error, image: diff }
+}